@@ -0,0 +1,339 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compares the wire size of OTAP streams encoded with each [SortStrategy], before and after
+//! deflate compression as used by gRPC `gzip`.
+//!
+//! Run with `cargo bench --bench sort_strategies`.
+
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use opentelemetry_proto::tonic::common::v1::{any_value, AnyValue, InstrumentationScope, KeyValue};
+use opentelemetry_proto::tonic::metrics::v1::{
+    metric, number_data_point, AggregationTemporality, Gauge, Metric, NumberDataPoint,
+    ResourceMetrics, ScopeMetrics, Sum,
+};
+use opentelemetry_proto::tonic::resource::v1::Resource;
+use otel_arrow_rust::{AttributeOrder, DataPointOrder, Producer, SortStrategy};
+use prost::Message;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const BATCHES: usize = 10;
+const INTERVAL_NANOS: u64 = 10_000_000_000;
+const START_NANOS: u64 = 1_700_000_000_000_000_000;
+
+/// Generates the request of the given batch of a workload.
+type Workload = fn(&mut StdRng, usize) -> ExportMetricsServiceRequest;
+
+fn kv(key: &str, value: impl Into<String>) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue {
+            value: Some(any_value::Value::StringValue(value.into())),
+        }),
+    }
+}
+
+fn data_point(
+    attributes: Vec<KeyValue>,
+    start: u64,
+    time: u64,
+    value: number_data_point::Value,
+) -> NumberDataPoint {
+    NumberDataPoint {
+        attributes,
+        start_time_unix_nano: start,
+        time_unix_nano: time,
+        value: Some(value),
+        ..Default::default()
+    }
+}
+
+fn sum(name: &str, unit: &str, monotonic: bool, data_points: Vec<NumberDataPoint>) -> Metric {
+    Metric {
+        name: name.to_string(),
+        unit: unit.to_string(),
+        data: Some(metric::Data::Sum(Sum {
+            data_points,
+            aggregation_temporality: AggregationTemporality::Cumulative as i32,
+            is_monotonic: monotonic,
+        })),
+        ..Default::default()
+    }
+}
+
+fn gauge(name: &str, unit: &str, data_points: Vec<NumberDataPoint>) -> Metric {
+    Metric {
+        name: name.to_string(),
+        unit: unit.to_string(),
+        data: Some(metric::Data::Gauge(Gauge { data_points })),
+        ..Default::default()
+    }
+}
+
+fn scope(name: &str, metrics: Vec<Metric>) -> ScopeMetrics {
+    ScopeMetrics {
+        scope: Some(InstrumentationScope {
+            name: name.to_string(),
+            version: "0.110.0".to_string(),
+            ..Default::default()
+        }),
+        metrics,
+        ..Default::default()
+    }
+}
+
+fn resource(attributes: Vec<KeyValue>, scope_metrics: Vec<ScopeMetrics>) -> ResourceMetrics {
+    ResourceMetrics {
+        resource: Some(Resource {
+            attributes,
+            dropped_attributes_count: 0,
+        }),
+        scope_metrics,
+        schema_url: "https://opentelemetry.io/schemas/1.9.0".to_string(),
+    }
+}
+
+/// One scrape of the `hostmetrics` receiver on 20 hosts, as seen by a gateway.
+fn host_metrics(rng: &mut StdRng, batch: usize) -> ExportMetricsServiceRequest {
+    use number_data_point::Value::{AsDouble, AsInt};
+    let time = START_NANOS + batch as u64 * INTERVAL_NANOS;
+    let resource_metrics = (0..20)
+        .map(|host| {
+            let start = START_NANOS - (host as u64 + 1) * 3_600_000_000_000;
+            let cpu = (0..8)
+                .flat_map(|cpu| {
+                    [
+                        "user",
+                        "system",
+                        "idle",
+                        "interrupt",
+                        "nice",
+                        "softirq",
+                        "steal",
+                        "wait",
+                    ]
+                    .map(|state| (cpu, state))
+                })
+                .map(|(cpu, state)| {
+                    let attributes = vec![kv("cpu", format!("cpu{cpu}")), kv("state", state)];
+                    let value = AsDouble(rng.gen_range(0.0..100_000.0));
+                    data_point(attributes, start, time, value)
+                })
+                .collect();
+            let memory = ["used", "free", "buffered", "cached"]
+                .map(|state| {
+                    let value = AsInt(rng.gen_range(0..1 << 34));
+                    data_point(vec![kv("state", state)], start, time, value)
+                })
+                .to_vec();
+            let io = |rng: &mut StdRng, devices: &[&str], directions: [&str; 2]| {
+                devices
+                    .iter()
+                    .flat_map(|device| directions.map(|direction| (*device, direction)))
+                    .map(|(device, direction)| {
+                        let attributes = vec![kv("device", device), kv("direction", direction)];
+                        let value = AsInt(rng.gen_range(0..1 << 40));
+                        data_point(attributes, start, time, value)
+                    })
+                    .collect::<Vec<_>>()
+            };
+            let load = vec![data_point(
+                vec![],
+                0,
+                time,
+                AsDouble(rng.gen_range(0.0..8.0)),
+            )];
+            resource(
+                vec![
+                    kv("host.name", format!("host-{host:03}")),
+                    kv("host.id", format!("{:032x}", host * 7919)),
+                    kv("os.type", "linux"),
+                    kv("cloud.region", ["us-east-1", "eu-west-1"][host % 2]),
+                ],
+                vec![
+                    scope(
+                        "otelcol/hostmetricsreceiver/cpu",
+                        vec![sum("system.cpu.time", "s", true, cpu)],
+                    ),
+                    scope(
+                        "otelcol/hostmetricsreceiver/memory",
+                        vec![sum("system.memory.usage", "By", false, memory)],
+                    ),
+                    scope(
+                        "otelcol/hostmetricsreceiver/disk",
+                        vec![sum(
+                            "system.disk.io",
+                            "By",
+                            true,
+                            io(rng, &["sda", "sdb", "nvme0n1"], ["read", "write"]),
+                        )],
+                    ),
+                    scope(
+                        "otelcol/hostmetricsreceiver/network",
+                        vec![sum(
+                            "system.network.io",
+                            "By",
+                            true,
+                            io(rng, &["eth0", "lo"], ["receive", "transmit"]),
+                        )],
+                    ),
+                    scope(
+                        "otelcol/hostmetricsreceiver/load",
+                        vec![gauge("system.cpu.load_average.1m", "{thread}", load)],
+                    ),
+                ],
+            )
+        })
+        .collect();
+    ExportMetricsServiceRequest { resource_metrics }
+}
+
+/// One scrape of the `kubeletstats` receiver for 60 pods on 6 nodes.
+fn k8s_metrics(rng: &mut StdRng, batch: usize) -> ExportMetricsServiceRequest {
+    use number_data_point::Value::{AsDouble, AsInt};
+    let time = START_NANOS + batch as u64 * INTERVAL_NANOS;
+    let namespaces = ["default", "kube-system", "monitoring", "payments"];
+    let resource_metrics = (0..60)
+        .map(|pod| {
+            let start = START_NANOS - (pod as u64 % 7 + 1) * 86_400_000_000_000;
+            let namespace = namespaces[pod % namespaces.len()];
+            let deployment = format!("{namespace}-app-{}", pod % 5);
+            let containers: Vec<_> = (0..1 + pod % 3)
+                .map(|c| ["app", "sidecar", "istio-proxy"][c])
+                .collect();
+            let per_container = |rng: &mut StdRng, f: &mut dyn FnMut(&mut StdRng) -> _| {
+                containers
+                    .iter()
+                    .map(|container| {
+                        let attributes = vec![kv("k8s.container.name", *container)];
+                        data_point(attributes, start, time, f(rng))
+                    })
+                    .collect::<Vec<_>>()
+            };
+            let network = ["receive", "transmit"]
+                .map(|direction| {
+                    let attributes = vec![kv("interface", "eth0"), kv("direction", direction)];
+                    data_point(attributes, start, time, AsInt(rng.gen_range(0..1 << 36)))
+                })
+                .to_vec();
+            let metrics = vec![
+                sum(
+                    "k8s.pod.cpu.time",
+                    "s",
+                    true,
+                    vec![data_point(
+                        vec![],
+                        start,
+                        time,
+                        AsDouble(rng.gen_range(0.0..1e6)),
+                    )],
+                ),
+                gauge(
+                    "k8s.pod.memory.usage",
+                    "By",
+                    vec![data_point(
+                        vec![],
+                        0,
+                        time,
+                        AsInt(rng.gen_range(0..1 << 32)),
+                    )],
+                ),
+                sum("k8s.pod.network.io", "By", true, network),
+                sum(
+                    "container.cpu.time",
+                    "s",
+                    true,
+                    per_container(rng, &mut |rng| AsDouble(rng.gen_range(0.0..1e5))),
+                ),
+                gauge(
+                    "container.memory.working_set",
+                    "By",
+                    per_container(rng, &mut |rng| AsInt(rng.gen_range(0..1 << 30))),
+                ),
+            ];
+            resource(
+                vec![
+                    kv("k8s.namespace.name", namespace),
+                    kv("k8s.pod.name", format!("{deployment}-{pod:05x}")),
+                    kv("k8s.pod.uid", format!("{:032x}", pod * 104_729)),
+                    kv("k8s.node.name", format!("node-{}", pod % 6)),
+                    kv("k8s.deployment.name", deployment),
+                ],
+                vec![scope("otelcol/kubeletstatsreceiver", metrics)],
+            )
+        })
+        .collect();
+    ExportMetricsServiceRequest { resource_metrics }
+}
+
+struct Sizes {
+    otap: usize,
+    compressed: usize,
+}
+
+fn measure(requests: &[ExportMetricsServiceRequest], sort: SortStrategy) -> Sizes {
+    let mut producer = Producer::default().with_sort(sort);
+    let mut sizes = Sizes {
+        otap: 0,
+        compressed: 0,
+    };
+    for request in requests {
+        let batch = producer.produce_batches(request).unwrap();
+        let bytes = batch.encode_to_vec();
+        sizes.otap += bytes.len();
+        sizes.compressed += miniz_oxide::deflate::compress_to_vec(&bytes, 6).len();
+    }
+    sizes
+}
+
+fn main() {
+    let strategies = [
+        ("upstream", SortStrategy::default()),
+        (
+            "parent-key",
+            SortStrategy {
+                attributes: AttributeOrder::ParentKey,
+                data_points: DataPointOrder::Timestamps,
+            },
+        ),
+        ("unsorted", SortStrategy::UNSORTED),
+    ];
+    let workloads: [(&str, Workload); 2] = [("host-metrics", host_metrics), ("k8s", k8s_metrics)];
+
+    println!(
+        "{:<14} {:<12} {:>12} {:>12} {:>8}",
+        "workload", "strategy", "bytes", "deflated", "ratio"
+    );
+    for (workload, generate) in workloads {
+        let mut rng = StdRng::seed_from_u64(42);
+        let requests: Vec<_> = (0..BATCHES).map(|i| generate(&mut rng, i)).collect();
+        let otlp: usize = requests.iter().map(|r| r.encoded_len()).sum();
+        let otlp_compressed: usize = requests
+            .iter()
+            .map(|r| miniz_oxide::deflate::compress_to_vec(&r.encode_to_vec(), 6).len())
+            .sum();
+        println!(
+            "{workload:<14} {:<12} {otlp:>12} {otlp_compressed:>12} {:>8.3}",
+            "otlp", 1.0
+        );
+        for (name, sort) in strategies {
+            let sizes = measure(&requests, sort);
+            println!(
+                "{workload:<14} {name:<12} {:>12} {:>12} {:>8.3}",
+                sizes.otap,
+                sizes.compressed,
+                sizes.compressed as f64 / otlp_compressed as f64
+            );
+        }
+    }
+}
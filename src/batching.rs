@@ -0,0 +1,430 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Splitting and coalescing of decoded metrics requests by data point count and encoded size.
+//!
+//! OTAP batches are split or coalesced by decoding them with a [crate::Consumer] and encoding
+//! the resulting requests with a [crate::Producer]. Sizes are those of the OTLP encoding.
+
+use crate::otlp::metric::regroup_metrics;
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use opentelemetry_proto::tonic::metrics::v1::{
+    metric, ExponentialHistogram, Gauge, Histogram, Metric, ResourceMetrics, ScopeMetrics, Sum,
+    Summary,
+};
+use prost::encoding::message::encoded_len;
+use prost::Message;
+
+/// Upper bound of the bytes added by one level of nesting: its tag, length prefix, and the
+/// scalar fields of the data of a metric.
+const NESTING_OVERHEAD: usize = 16;
+
+/// Bounds of the requests produced by [split_metrics] and [MetricsBatcher], unlimited by
+/// default.
+#[derive(Clone, Copy, Debug)]
+pub struct BatchLimits {
+    max_data_points: usize,
+    max_bytes: usize,
+}
+
+impl Default for BatchLimits {
+    fn default() -> Self {
+        Self {
+            max_data_points: usize::MAX,
+            max_bytes: usize::MAX,
+        }
+    }
+}
+
+impl BatchLimits {
+    pub fn with_max_data_points(mut self, max_data_points: usize) -> Self {
+        self.max_data_points = max_data_points.max(1);
+        self
+    }
+
+    /// Bounds the OTLP encoded size of requests. A data point too large to fit on its own is
+    /// still sent, alone in its request.
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+}
+
+/// Splits a request into requests within the limits, repeating resources, scopes and metric
+/// metadata as needed. Data points keep their order.
+pub fn split_metrics(
+    request: ExportMetricsServiceRequest,
+    limits: BatchLimits,
+) -> Vec<ExportMetricsServiceRequest> {
+    let mut batcher = MetricsBatcher::new(limits);
+    let mut requests = batcher.push(request);
+    requests.extend(batcher.flush());
+    requests
+}
+
+/// Returns the number of data points of a request.
+pub fn data_point_count(request: &ExportMetricsServiceRequest) -> usize {
+    request
+        .resource_metrics
+        .iter()
+        .flat_map(|rm| &rm.scope_metrics)
+        .flat_map(|sm| &sm.metrics)
+        .filter_map(|m| m.data.as_ref())
+        .map(|data| match data {
+            metric::Data::Gauge(g) => g.data_points.len(),
+            metric::Data::Sum(s) => s.data_points.len(),
+            metric::Data::Histogram(h) => h.data_points.len(),
+            metric::Data::ExponentialHistogram(h) => h.data_points.len(),
+            metric::Data::Summary(s) => s.data_points.len(),
+        })
+        .sum()
+}
+
+/// Coalesces requests, e.g. from many clients, into batches as large as the limits allow,
+/// splitting requests that don't fit. Equal resources and scopes of a batch are merged, see
+/// [crate::regroup_metrics].
+///
+/// A batch is returned once the next data point doesn't fit; the caller flushes the pending
+/// batch, typically on a timer.
+pub struct MetricsBatcher {
+    limits: BatchLimits,
+    full: Vec<ExportMetricsServiceRequest>,
+    pending: ExportMetricsServiceRequest,
+    data_points: usize,
+    /// Estimated encoded size of the pending batch, never below the actual one.
+    bytes: usize,
+    /// Input resource and scope the last groups of the pending batch belong to.
+    open_resource: Option<u64>,
+    open_scope: Option<u64>,
+    next_group: u64,
+}
+
+impl MetricsBatcher {
+    pub fn new(limits: BatchLimits) -> Self {
+        Self {
+            limits,
+            full: vec![],
+            pending: ExportMetricsServiceRequest::default(),
+            data_points: 0,
+            bytes: 0,
+            open_resource: None,
+            open_scope: None,
+            next_group: 0,
+        }
+    }
+
+    /// Appends the data points of a request, returning the batches filled along the way.
+    pub fn push(
+        &mut self,
+        request: ExportMetricsServiceRequest,
+    ) -> Vec<ExportMetricsServiceRequest> {
+        for mut rm in request.resource_metrics {
+            let resource = self.next_group();
+            for mut sm in std::mem::take(&mut rm.scope_metrics) {
+                let scope = self.next_group();
+                for metric in std::mem::take(&mut sm.metrics) {
+                    self.push_metric((resource, &rm), (scope, &sm), metric);
+                }
+            }
+        }
+        std::mem::take(&mut self.full)
+    }
+
+    /// Returns the pending batch, if it has any metric.
+    pub fn flush(&mut self) -> Option<ExportMetricsServiceRequest> {
+        self.data_points = 0;
+        self.bytes = 0;
+        self.open_resource = None;
+        self.open_scope = None;
+        let mut request = std::mem::take(&mut self.pending);
+        if request.resource_metrics.is_empty() {
+            return None;
+        }
+        regroup_metrics(&mut request);
+        Some(request)
+    }
+
+    /// Number of data points of the pending batch.
+    pub fn pending_data_points(&self) -> usize {
+        self.data_points
+    }
+
+    fn next_group(&mut self) -> u64 {
+        self.next_group += 1;
+        self.next_group
+    }
+
+    fn push_metric(
+        &mut self,
+        (resource, rm): (u64, &ResourceMetrics),
+        (scope, sm): (u64, &ScopeMetrics),
+        mut metric: Metric,
+    ) {
+        let mut data = metric.data.take();
+        let sizes = data.as_ref().map(point_sizes).unwrap_or_default();
+        let metric_bytes = encoded_len(2, &metric) + 2 * NESTING_OVERHEAD;
+
+        let mut start = 0;
+        loop {
+            let mut overhead = metric_bytes;
+            if self.open_scope != Some(scope) {
+                overhead += encoded_len(2, sm) + NESTING_OVERHEAD;
+            }
+            if self.open_resource != Some(resource) {
+                overhead += encoded_len(1, rm) + NESTING_OVERHEAD;
+            }
+
+            // data points fitting in the pending batch.
+            let mut bytes = self.bytes + overhead;
+            let mut n = 0;
+            for size in &sizes[start..] {
+                if self.data_points + n >= self.limits.max_data_points
+                    || bytes + size > self.limits.max_bytes
+                {
+                    break;
+                }
+                bytes += size;
+                n += 1;
+            }
+            let remaining = sizes.len() - start;
+            if (n == 0 && remaining > 0) || bytes > self.limits.max_bytes {
+                if !self.pending.resource_metrics.is_empty() {
+                    self.flush_full();
+                    continue;
+                }
+                // too large on its own.
+                n = n.max(1).min(remaining);
+                bytes += sizes[start..start + n].iter().sum::<usize>();
+            }
+
+            if self.open_resource != Some(resource) {
+                self.open_resource = Some(resource);
+                self.open_scope = None;
+                self.pending.resource_metrics.push(ResourceMetrics {
+                    resource: rm.resource.clone(),
+                    scope_metrics: vec![],
+                    schema_url: rm.schema_url.clone(),
+                });
+            }
+            // safety: pushed above if absent
+            let scope_metrics = &mut self
+                .pending
+                .resource_metrics
+                .last_mut()
+                .unwrap()
+                .scope_metrics;
+            if self.open_scope != Some(scope) {
+                self.open_scope = Some(scope);
+                scope_metrics.push(ScopeMetrics {
+                    scope: sm.scope.clone(),
+                    metrics: vec![],
+                    schema_url: sm.schema_url.clone(),
+                });
+            }
+            // safety: pushed above if absent
+            scope_metrics.last_mut().unwrap().metrics.push(Metric {
+                data: data.as_mut().map(|data| take_points(data, n)),
+                ..metric.clone()
+            });
+            self.data_points += n;
+            self.bytes = bytes;
+            start += n;
+
+            if start == sizes.len() {
+                return;
+            }
+            self.flush_full();
+        }
+    }
+
+    fn flush_full(&mut self) {
+        if let Some(request) = self.flush() {
+            self.full.push(request);
+        }
+    }
+}
+
+/// Encoded sizes of the data points, as fields of their metric data.
+fn point_sizes(data: &metric::Data) -> Vec<usize> {
+    fn sizes<M: Message>(points: &[M]) -> Vec<usize> {
+        points.iter().map(|p| encoded_len(1, p)).collect()
+    }
+    match data {
+        metric::Data::Gauge(g) => sizes(&g.data_points),
+        metric::Data::Sum(s) => sizes(&s.data_points),
+        metric::Data::Histogram(h) => sizes(&h.data_points),
+        metric::Data::ExponentialHistogram(h) => sizes(&h.data_points),
+        metric::Data::Summary(s) => sizes(&s.data_points),
+    }
+}
+
+/// Moves the first `n` data points into new data of the same type.
+fn take_points(data: &mut metric::Data, n: usize) -> metric::Data {
+    match data {
+        metric::Data::Gauge(g) => metric::Data::Gauge(Gauge {
+            data_points: g.data_points.drain(..n).collect(),
+        }),
+        metric::Data::Sum(s) => metric::Data::Sum(Sum {
+            data_points: s.data_points.drain(..n).collect(),
+            aggregation_temporality: s.aggregation_temporality,
+            is_monotonic: s.is_monotonic,
+        }),
+        metric::Data::Histogram(h) => metric::Data::Histogram(Histogram {
+            data_points: h.data_points.drain(..n).collect(),
+            aggregation_temporality: h.aggregation_temporality,
+        }),
+        metric::Data::ExponentialHistogram(h) => {
+            metric::Data::ExponentialHistogram(ExponentialHistogram {
+                data_points: h.data_points.drain(..n).collect(),
+                aggregation_temporality: h.aggregation_temporality,
+            })
+        }
+        metric::Data::Summary(s) => metric::Data::Summary(Summary {
+            data_points: s.data_points.drain(..n).collect(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::batching::{data_point_count, split_metrics, BatchLimits, MetricsBatcher};
+    use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+    use opentelemetry_proto::tonic::common::v1::{any_value, AnyValue, KeyValue};
+    use opentelemetry_proto::tonic::metrics::v1::number_data_point::Value;
+    use opentelemetry_proto::tonic::metrics::v1::{
+        metric, Gauge, Metric, NumberDataPoint, ResourceMetrics, ScopeMetrics, Sum,
+    };
+    use opentelemetry_proto::tonic::resource::v1::Resource;
+    use prost::Message;
+
+    fn request(hosts: &[&str], points: u64) -> ExportMetricsServiceRequest {
+        let points = || {
+            (0..points)
+                .map(|i| NumberDataPoint {
+                    time_unix_nano: i,
+                    value: Some(Value::AsInt(i as i64)),
+                    ..Default::default()
+                })
+                .collect::<Vec<_>>()
+        };
+        let resource_metrics = |host: &str| ResourceMetrics {
+            resource: Some(Resource {
+                attributes: vec![KeyValue {
+                    key: "host.name".to_string(),
+                    value: Some(AnyValue {
+                        value: Some(any_value::Value::StringValue(host.to_string())),
+                    }),
+                }],
+                ..Default::default()
+            }),
+            scope_metrics: vec![ScopeMetrics {
+                metrics: vec![
+                    Metric {
+                        name: "cpu".to_string(),
+                        data: Some(metric::Data::Gauge(Gauge {
+                            data_points: points(),
+                        })),
+                        ..Default::default()
+                    },
+                    Metric {
+                        name: "requests".to_string(),
+                        data: Some(metric::Data::Sum(Sum {
+                            data_points: points(),
+                            aggregation_temporality: 2,
+                            is_monotonic: true,
+                        })),
+                        ..Default::default()
+                    },
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        ExportMetricsServiceRequest {
+            resource_metrics: hosts.iter().map(|host| resource_metrics(host)).collect(),
+        }
+    }
+
+    /// Concatenates requests, merging equal groups.
+    fn merge(requests: Vec<ExportMetricsServiceRequest>) -> ExportMetricsServiceRequest {
+        let mut merged = ExportMetricsServiceRequest {
+            resource_metrics: requests
+                .into_iter()
+                .flat_map(|r| r.resource_metrics)
+                .collect(),
+        };
+        crate::regroup_metrics(&mut merged);
+        for sm in merged
+            .resource_metrics
+            .iter_mut()
+            .flat_map(|rm| &mut rm.scope_metrics)
+        {
+            let mut metrics: Vec<Metric> = vec![];
+            for metric in std::mem::take(&mut sm.metrics) {
+                match metrics.last_mut() {
+                    Some(last) if last.name == metric.name => match (&mut last.data, metric.data) {
+                        (Some(metric::Data::Gauge(a)), Some(metric::Data::Gauge(b))) => {
+                            a.data_points.extend(b.data_points)
+                        }
+                        (Some(metric::Data::Sum(a)), Some(metric::Data::Sum(b))) => {
+                            a.data_points.extend(b.data_points)
+                        }
+                        _ => unreachable!(),
+                    },
+                    _ => metrics.push(metric),
+                }
+            }
+            sm.metrics = metrics;
+        }
+        merged
+    }
+
+    #[test]
+    fn test_split_and_batch() {
+        let original = request(&["a", "b"], 10);
+        assert_eq!(data_point_count(&original), 40);
+
+        let split = split_metrics(
+            original.clone(),
+            BatchLimits::default().with_max_data_points(7),
+        );
+        assert_eq!(split.len(), 6);
+        assert!(split.iter().all(|r| data_point_count(r) <= 7));
+        assert_eq!(merge(split), original);
+
+        let max_bytes = original.encoded_len() / 4;
+        let split = split_metrics(
+            original.clone(),
+            BatchLimits::default().with_max_bytes(max_bytes),
+        );
+        assert!(split.len() >= 4);
+        assert!(split.iter().all(|r| r.encoded_len() <= max_bytes));
+        assert_eq!(merge(split), original);
+
+        // a point larger than the limit is sent alone.
+        let split = split_metrics(request(&["a"], 2), BatchLimits::default().with_max_bytes(1));
+        assert_eq!(split.len(), 4);
+
+        // small requests from the same host coalesce into one group per batch.
+        let mut batcher = MetricsBatcher::new(BatchLimits::default().with_max_data_points(10));
+        let mut batches = vec![];
+        for _ in 0..6 {
+            batches.extend(batcher.push(request(&["a"], 2)));
+        }
+        assert_eq!(batcher.pending_data_points(), 4);
+        batches.extend(batcher.flush());
+        assert_eq!(batches.len(), 3);
+        assert!(batches.iter().all(|r| r.resource_metrics.len() == 1));
+        assert_eq!(data_point_count(&batches[0]), 10);
+        assert!(batcher.flush().is_none());
+    }
+}
@@ -0,0 +1,370 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Dumps and validates captured OTAP streams.
+//!
+//! Accepts either a capture written by [otel_arrow_rust::replay::Recorder] or a file of
+//! length-delimited `BatchArrowRecords` messages.
+
+use arrow::array::{Array, ArrayRef, AsArray};
+use arrow::datatypes::{DataType, Field};
+use arrow::util::display::{ArrayFormatter, FormatOptions};
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use opentelemetry_proto::tonic::metrics::v1::metric::Data;
+use otel_arrow_rust::export::json::to_json;
+use otel_arrow_rust::export::protobuf::DelimitedReader;
+use otel_arrow_rust::opentelemetry::BatchArrowRecords;
+use otel_arrow_rust::replay::{Replayer, CAPTURE_MAGIC};
+use otel_arrow_rust::{decode_metrics, schema_id, BatchStats, Consumer, RecordMessage};
+use prost::Message;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+const USAGE: &str = "Dumps and validates captured OTAP BatchArrowRecords streams.
+
+Usage: otap-inspect [OPTIONS] <FILE>
+
+Options:
+  --format <auto|capture|delimited>  Format of the captured stream [default: auto]
+  --print-records                    Pretty prints the content of every record batch
+  --no-decode                        Only dumps payloads, skips decoding to OTLP
+  --json                             Prints each decoded request as OTLP/JSON
+  -h, --help                         Prints this help";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Format {
+    /// Detects the format from the leading magic bytes.
+    Auto,
+    /// Capture written by `otel_arrow_rust::replay::Recorder`.
+    Capture,
+    /// Length-delimited `BatchArrowRecords` messages.
+    Delimited,
+}
+
+#[derive(Debug)]
+struct Args {
+    file: PathBuf,
+    format: Format,
+    print_records: bool,
+    no_decode: bool,
+    json: bool,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut file = None;
+        let mut format = Format::Auto;
+        let mut print_records = false;
+        let mut no_decode = false;
+        let mut json = false;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--format" => {
+                    format = match args.next().as_deref() {
+                        Some("auto") => Format::Auto,
+                        Some("capture") => Format::Capture,
+                        Some("delimited") => Format::Delimited,
+                        other => return Err(format!("invalid format: {:?}", other)),
+                    }
+                }
+                "--print-records" => print_records = true,
+                "--no-decode" => no_decode = true,
+                "--json" => json = true,
+                "-h" | "--help" => return Err(String::new()),
+                a if a.starts_with('-') => return Err(format!("unknown option: {}", a)),
+                a if file.is_none() => file = Some(PathBuf::from(a)),
+                a => return Err(format!("unexpected argument: {}", a)),
+            }
+        }
+        Ok(Self {
+            file: file.ok_or("missing <FILE>")?,
+            format,
+            print_records,
+            no_decode,
+            json,
+        })
+    }
+}
+
+type Batches = Box<dyn Iterator<Item = otel_arrow_rust::Result<BatchArrowRecords>>>;
+
+fn main() -> ExitCode {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(message) => {
+            if !message.is_empty() {
+                eprintln!("error: {}\n", message);
+            }
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    match run(&args) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            report_error(&*e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Returns false if any batch failed to decode.
+fn run(args: &Args) -> Result<bool, Box<dyn Error>> {
+    let mut consumer = Consumer::default();
+    for (idx, batch) in open(args)?.enumerate() {
+        let mut batch = batch?;
+        println!(
+            "batch #{} batch_id: {}, payloads: {}, headers: {} bytes",
+            idx,
+            batch.batch_id,
+            batch.arrow_payloads.len(),
+            batch.headers.len()
+        );
+        let payload_sizes = batch
+            .arrow_payloads
+            .iter()
+            .map(|p| p.record.len())
+            .collect::<Vec<_>>();
+
+        let records = match consumer.consume_records(&mut batch) {
+            Ok(records) => records,
+            Err(e) => {
+                report_error(&e);
+                return Ok(false);
+            }
+        };
+        for (record, size) in records.iter().zip(payload_sizes) {
+            dump_record(record, size, args.print_records)?;
+        }
+        let mut stats = consumer.last_batch_stats().cloned().unwrap_or_default();
+
+        if args.no_decode {
+            continue;
+        }
+        match decode_metrics(&records) {
+            Ok(request) => {
+                stats.otlp_bytes = Some(request.encoded_len());
+                print_summary(&request, &stats);
+                if args.json {
+                    println!("{}", to_json(&request)?);
+                }
+            }
+            Err(e) => {
+                report_error(&e);
+                return Ok(false);
+            }
+        }
+    }
+    Ok(true)
+}
+
+fn open(args: &Args) -> Result<Batches, Box<dyn Error>> {
+    let mut file = BufReader::new(File::open(&args.file)?);
+    let mut head = vec![];
+    (&mut file)
+        .take(CAPTURE_MAGIC.len() as u64)
+        .read_to_end(&mut head)?;
+    let is_capture = head.as_slice() == CAPTURE_MAGIC;
+    let format = match args.format {
+        Format::Auto if is_capture => Format::Capture,
+        Format::Auto => Format::Delimited,
+        f => f,
+    };
+    let reader = Cursor::new(head).chain(file);
+    let batches: Batches = match format {
+        Format::Capture => Box::new(Replayer::try_new(reader)?.batches()),
+        _ => Box::new(DelimitedReader::<_, BatchArrowRecords>::new(reader)),
+    };
+    Ok(batches)
+}
+
+fn dump_record(record: &RecordMessage, size: usize, print: bool) -> Result<(), Box<dyn Error>> {
+    let rb = record.record();
+    println!(
+        "  {:?} schema_id: {}, rows: {}, ipc bytes: {}, memory bytes: {}",
+        record.payload_type(),
+        record.schema_id(),
+        rb.num_rows(),
+        size,
+        rb.get_array_memory_size()
+    );
+    if let Ok(id) = schema_id(&rb.schema()) {
+        println!("    canonical id: {}", id);
+    }
+    println!("    schema:");
+    for field in rb.schema().fields() {
+        print_field(field, 3);
+    }
+
+    let mut dictionaries = vec![];
+    for (field, column) in rb.schema().fields().iter().zip(rb.columns()) {
+        collect_dictionaries(field.name(), column, &mut dictionaries);
+    }
+    if !dictionaries.is_empty() {
+        println!("    dictionaries:");
+        for (name, keys, values) in dictionaries {
+            println!("      {}: {} keys, {} values", name, keys, values);
+        }
+    }
+
+    if print {
+        let options = FormatOptions::default().with_null("null");
+        let formatters = rb
+            .columns()
+            .iter()
+            .map(|c| ArrayFormatter::try_new(c.as_ref(), &options))
+            .collect::<Result<Vec<_>, _>>()?;
+        println!("    rows:");
+        for row in 0..rb.num_rows() {
+            let values = rb
+                .schema()
+                .fields()
+                .iter()
+                .zip(&formatters)
+                .map(|(f, fmt)| format!("{}: {}", f.name(), fmt.value(row)))
+                .collect::<Vec<_>>();
+            println!("      [{}] {}", row, values.join(", "));
+        }
+    }
+    Ok(())
+}
+
+fn print_field(field: &Field, depth: usize) {
+    let indent = "  ".repeat(depth);
+    let nullable = if field.is_nullable() { "" } else { " not null" };
+    match field.data_type() {
+        DataType::Struct(fields) => {
+            println!("{}{}: Struct{}", indent, field.name(), nullable);
+            for f in fields {
+                print_field(f, depth + 1);
+            }
+        }
+        DataType::List(item) => {
+            println!("{}{}: List{}", indent, field.name(), nullable);
+            print_field(item, depth + 1);
+        }
+        dt => println!("{}{}: {}{}", indent, field.name(), dt, nullable),
+    }
+}
+
+fn collect_dictionaries(name: &str, array: &ArrayRef, out: &mut Vec<(String, usize, usize)>) {
+    match array.data_type() {
+        DataType::Dictionary(_, _) => {
+            let dict = array.as_any_dictionary();
+            out.push((name.to_string(), dict.keys().len(), dict.values().len()));
+        }
+        DataType::Struct(fields) => {
+            let struct_array = array.as_struct();
+            for (f, c) in fields.iter().zip(struct_array.columns()) {
+                collect_dictionaries(&format!("{}.{}", name, f.name()), c, out);
+            }
+        }
+        DataType::List(_) => {
+            collect_dictionaries(name, array.as_list::<i32>().values(), out);
+        }
+        _ => {}
+    }
+}
+
+fn print_summary(request: &ExportMetricsServiceRequest, stats: &BatchStats) {
+    let scope_metrics = request
+        .resource_metrics
+        .iter()
+        .flat_map(|r| &r.scope_metrics)
+        .collect::<Vec<_>>();
+    let metrics = scope_metrics
+        .iter()
+        .flat_map(|s| &s.metrics)
+        .collect::<Vec<_>>();
+    let data_points = metrics
+        .iter()
+        .map(|m| match &m.data {
+            Some(Data::Gauge(g)) => g.data_points.len(),
+            Some(Data::Sum(s)) => s.data_points.len(),
+            Some(Data::Histogram(h)) => h.data_points.len(),
+            Some(Data::ExponentialHistogram(e)) => e.data_points.len(),
+            Some(Data::Summary(s)) => s.data_points.len(),
+            None => 0,
+        })
+        .sum::<usize>();
+    println!(
+        "  decoded: {} resource metrics, {} scope metrics, {} metrics, {} data points",
+        request.resource_metrics.len(),
+        scope_metrics.len(),
+        metrics.len(),
+        data_points
+    );
+    println!(
+        "  otap bytes: {}, otlp bytes: {}, compression ratio: {:.2}, schema resets: {}",
+        stats.otap_bytes(),
+        stats.otlp_bytes.unwrap_or_default(),
+        stats.compression_ratio().unwrap_or_default(),
+        stats.schema_resets()
+    );
+}
+
+fn report_error(e: &dyn Error) {
+    eprintln!("error: {}", e);
+    let mut source = e.source();
+    while let Some(s) = source {
+        eprintln!("  caused by: {}", s);
+        source = s.source();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Args, Format};
+    use std::path::PathBuf;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = parse(&["capture.bin"]).unwrap();
+        assert_eq!(args.file, PathBuf::from("capture.bin"));
+        assert_eq!(args.format, Format::Auto);
+        assert!(!args.print_records && !args.no_decode && !args.json);
+
+        let args = parse(&[
+            "--format",
+            "delimited",
+            "--print-records",
+            "capture.bin",
+            "--no-decode",
+            "--json",
+        ])
+        .unwrap();
+        assert_eq!(args.format, Format::Delimited);
+        assert!(args.print_records && args.no_decode && args.json);
+
+        // help is an empty error, printing only the usage.
+        assert_eq!(parse(&["a", "--help"]).unwrap_err(), "");
+        assert_eq!(parse(&[]).unwrap_err(), "missing <FILE>");
+        assert_eq!(parse(&["a", "b"]).unwrap_err(), "unexpected argument: b");
+        assert_eq!(parse(&["-x", "a"]).unwrap_err(), "unknown option: -x");
+        assert_eq!(
+            parse(&["a", "--format", "csv"]).unwrap_err(),
+            "invalid format: Some(\"csv\")"
+        );
+        assert_eq!(
+            parse(&["a", "--format"]).unwrap_err(),
+            "invalid format: None"
+        );
+    }
+}
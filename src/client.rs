@@ -0,0 +1,600 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! OTAP metrics client, encoding requests on a long-lived stream that is rotated
+//! periodically, with a fallback to plain OTLP for peers without OTAP support.
+
+mod queue;
+
+pub use queue::DiskQueue;
+
+use crate::encode::encoder::Producer;
+use crate::error;
+use crate::opentelemetry::{ArrowMetricsServiceClient, BatchArrowRecords, BatchStatus, StatusCode};
+use opentelemetry_proto::tonic::collector::metrics::v1::metrics_service_client::MetricsServiceClient;
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use snafu::{OptionExt, ResultExt};
+use std::future::Future;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::metadata::{MetadataKey, MetadataValue};
+use tonic::transport::Channel;
+use tonic::{Code, Streaming};
+
+/// Retry policy for requests failing with a retryable status, i.e. `Unavailable`,
+/// `ResourceExhausted` or `DeadlineExceeded`, or a stream closed before acknowledging.
+#[derive(Clone, Debug)]
+pub struct RetryConfig {
+    /// Retries after the first attempt. Zero disables retries.
+    pub max_retries: usize,
+    /// Wait before the first retry, doubled for every following retry.
+    pub initial_backoff: Duration,
+    /// Upper bound of the wait between retries.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ClientConfig {
+    /// Maximum time an attempt waits for its request to be acknowledged.
+    pub timeout: Option<Duration>,
+    pub retry: RetryConfig,
+    /// Maximum time a stream is used before moving to a new one. Should be below the
+    /// lifetime enforced by the server.
+    pub max_stream_lifetime: Option<Duration>,
+    /// Maximum time a stream is kept open without sending a batch.
+    pub idle_timeout: Option<Duration>,
+    /// Headers attached to every batch, e.g. credentials. Sent as gRPC metadata when falling
+    /// back to OTLP.
+    pub headers: Vec<(String, String)>,
+}
+
+/// One OTAP stream with the encoder state bound to it.
+struct ClientStream {
+    producer: Producer,
+    batches: mpsc::Sender<BatchArrowRecords>,
+    statuses: Streaming<BatchStatus>,
+    opened: Instant,
+    last_used: Instant,
+}
+
+impl ClientStream {
+    fn expired(&self, config: &ClientConfig, now: Instant) -> bool {
+        let too_old = config
+            .max_stream_lifetime
+            .is_some_and(|d| now >= self.opened + d);
+        let too_idle = config
+            .idle_timeout
+            .is_some_and(|d| now >= self.last_used + d);
+        too_old || too_idle
+    }
+
+    /// Stops sending on the stream and waits for the server to acknowledge the batches sent
+    /// so far and end the stream.
+    async fn drain(self) -> error::Result<()> {
+        let Self {
+            batches,
+            mut statuses,
+            ..
+        } = self;
+        drop(batches);
+        while statuses.message().await.context(error::RpcSnafu)?.is_some() {}
+        Ok(())
+    }
+}
+
+/// Sends metrics requests to an OTAP server, one batch per request.
+///
+/// Streams are opened lazily and rotated once they exceed the [ClientConfig] limits. Each
+/// new stream starts with a fresh [Producer], as the server starts a fresh `Consumer`. If
+/// the peer reports the Arrow service as `Unimplemented`, the client falls back to the OTLP
+/// `MetricsService` for the rest of its lifetime.
+///
+/// With a [DiskQueue], requests are persisted before being sent, for at-least-once delivery
+/// across outages and restarts.
+pub struct MetricsClient {
+    arrow: ArrowMetricsServiceClient<Channel>,
+    otlp: MetricsServiceClient<Channel>,
+    config: ClientConfig,
+    stream: Option<ClientStream>,
+    fallback: bool,
+    queue: Option<DiskQueue>,
+}
+
+impl MetricsClient {
+    pub fn new(channel: Channel, config: ClientConfig) -> Self {
+        Self {
+            arrow: ArrowMetricsServiceClient::new(channel.clone()),
+            otlp: MetricsServiceClient::new(channel),
+            config,
+            stream: None,
+            fallback: false,
+            queue: None,
+        }
+    }
+
+    /// Persists requests in the queue until they are delivered. Entries already in the queue
+    /// are sent with the next request, or by [MetricsClient::flush].
+    pub fn with_queue(mut self, queue: DiskQueue) -> Self {
+        self.queue = Some(queue);
+        self
+    }
+
+    pub fn queue(&self) -> Option<&DiskQueue> {
+        self.queue.as_ref()
+    }
+
+    /// Whether requests are sent as plain OTLP because the peer does not implement OTAP.
+    pub fn is_fallback(&self) -> bool {
+        self.fallback
+    }
+
+    /// Sends the request until it is accepted, retrying as configured. A batch rejected by
+    /// the server fails with [crate::Error::Rpc], carrying its status.
+    ///
+    /// With a queue, the request is persisted first and sent after the pending entries, see
+    /// [MetricsClient::flush].
+    pub async fn export(&mut self, request: &ExportMetricsServiceRequest) -> error::Result<()> {
+        match &mut self.queue {
+            Some(queue) => {
+                queue.push(request)?;
+                self.flush().await
+            }
+            None => self.send(request).await,
+        }
+    }
+
+    /// Sends the pending entries of the queue, oldest first. Entries are deleted once
+    /// accepted, or once rejected for their content, i.e. `InvalidArgument` or a corrupted
+    /// entry, as they would never be. Stops at the first entry failing for any other reason,
+    /// e.g. retries running out or missing credentials, which stays queued. Returns the first
+    /// error encountered.
+    pub async fn flush(&mut self) -> error::Result<()> {
+        let mut first_error = None;
+        while let Some(queue) = &self.queue
+            && let Some(seq) = queue.first()
+        {
+            let result = match queue.read(seq) {
+                Ok(request) => self.send(&request).await,
+                // a corrupted entry can never be sent.
+                Err(e @ error::Error::DecodeProtobuf { .. }) => Err(e),
+                Err(e) => return Err(e),
+            };
+            match result {
+                Ok(()) => {}
+                Err(e) if !is_content_error(&e) => return Err(e),
+                Err(e) => {
+                    #[cfg(feature = "trace")]
+                    tracing::warn!(error = %e, seq, "dropping rejected metrics request");
+                    first_error.get_or_insert(e);
+                }
+            }
+            if let Some(queue) = &mut self.queue {
+                queue.remove(seq)?;
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+
+    async fn send(&mut self, request: &ExportMetricsServiceRequest) -> error::Result<()> {
+        let retry = self.config.retry.clone();
+        let mut backoff = retry.initial_backoff;
+        let mut retries = 0;
+        loop {
+            let result = match self.fallback {
+                false => self.export_arrow(request).await,
+                true => self.export_otlp(request).await,
+            };
+            match result {
+                Err(e) if !self.fallback && rpc_code(&e) == Some(Code::Unimplemented) => {
+                    #[cfg(feature = "trace")]
+                    tracing::warn!("peer does not implement OTAP metrics, falling back to OTLP");
+                    self.fallback = true;
+                }
+                Err(e) if retries < retry.max_retries && is_retryable(&e) => {
+                    #[cfg(feature = "trace")]
+                    tracing::debug!(error = %e, retries, "retrying metrics export");
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(retry.max_backoff);
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Gracefully closes the current stream, if any, waiting at most [ClientConfig::timeout]
+    /// for the server to end it. The next request opens a new one.
+    pub async fn rotate(&mut self) -> error::Result<()> {
+        match self.stream.take() {
+            Some(stream) => with_timeout(self.config.timeout, stream.drain()).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn export_arrow(&mut self, request: &ExportMetricsServiceRequest) -> error::Result<()> {
+        let now = Instant::now();
+        if self
+            .stream
+            .as_ref()
+            .is_some_and(|s| s.expired(&self.config, now))
+        {
+            // the old stream is dropped either way, which must not fail the request.
+            #[cfg_attr(not(feature = "trace"), allow(unused_variables))]
+            if let Err(e) = self.rotate().await {
+                #[cfg(feature = "trace")]
+                tracing::warn!(error = %e, "failed to drain expired stream");
+            }
+        }
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => self.stream.insert(open_stream(&mut self.arrow).await?),
+        };
+        stream.last_used = now;
+
+        let headers: Vec<_> = self
+            .config
+            .headers
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        let batch = stream
+            .producer
+            .produce_batches_with_headers(request, &headers)?;
+        let result = with_timeout(self.config.timeout, send_and_wait(stream, batch)).await;
+        if result.is_err() {
+            // the state of the stream is unknown, so it can't be used anymore.
+            self.stream = None;
+        }
+        let status = result?;
+        match StatusCode::try_from(status.status_code) {
+            Ok(StatusCode::Ok) => Ok(()),
+            _ => Err(tonic::Status::new(
+                Code::from_i32(status.status_code),
+                status.status_message,
+            ))
+            .context(error::RpcSnafu),
+        }
+    }
+
+    async fn export_otlp(&mut self, request: &ExportMetricsServiceRequest) -> error::Result<()> {
+        let mut otlp_request = tonic::Request::new(request.clone());
+        for (key, value) in &self.config.headers {
+            // headers that are not valid metadata can't be sent over OTLP.
+            if let (Ok(key), Ok(value)) = (
+                MetadataKey::from_bytes(key.as_bytes()),
+                MetadataValue::try_from(value.as_str()),
+            ) {
+                otlp_request.metadata_mut().insert(key, value);
+            }
+        }
+        if let Some(timeout) = self.config.timeout {
+            otlp_request.set_timeout(timeout);
+        }
+        let call = async {
+            self.otlp
+                .export(otlp_request)
+                .await
+                .context(error::RpcSnafu)
+        };
+        with_timeout(self.config.timeout, call).await?;
+        Ok(())
+    }
+}
+
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    future: impl Future<Output = error::Result<T>>,
+) -> error::Result<T> {
+    match timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, future).await {
+            Ok(result) => result,
+            Err(_) => Err(tonic::Status::deadline_exceeded("metrics export timed out"))
+                .context(error::RpcSnafu),
+        },
+        None => future.await,
+    }
+}
+
+fn rpc_code(e: &error::Error) -> Option<Code> {
+    match e {
+        error::Error::Rpc { error, .. } => Some(error.code()),
+        _ => None,
+    }
+}
+
+/// Whether the request itself is at fault, so that sending it again can't succeed.
+fn is_content_error(e: &error::Error) -> bool {
+    match e {
+        error::Error::DecodeProtobuf { .. } => true,
+        _ => rpc_code(e) == Some(Code::InvalidArgument),
+    }
+}
+
+fn is_retryable(e: &error::Error) -> bool {
+    match e {
+        error::Error::StreamClosed { .. } => true,
+        _ => matches!(
+            rpc_code(e),
+            Some(Code::Unavailable | Code::ResourceExhausted | Code::DeadlineExceeded)
+        ),
+    }
+}
+
+async fn open_stream(
+    client: &mut ArrowMetricsServiceClient<Channel>,
+) -> error::Result<ClientStream> {
+    let (tx, rx) = mpsc::channel(1);
+    let statuses = client
+        .arrow_metrics(ReceiverStream::new(rx))
+        .await
+        .context(error::RpcSnafu)?
+        .into_inner();
+    let now = Instant::now();
+    Ok(ClientStream {
+        producer: Producer::default(),
+        batches: tx,
+        statuses,
+        opened: now,
+        last_used: now,
+    })
+}
+
+async fn send_and_wait(
+    stream: &mut ClientStream,
+    batch: BatchArrowRecords,
+) -> error::Result<BatchStatus> {
+    let batch_id = batch.batch_id;
+    let closed = error::StreamClosedSnafu { batch_id };
+    stream.batches.send(batch).await.ok().context(closed)?;
+    loop {
+        let status = stream
+            .statuses
+            .message()
+            .await
+            .context(error::RpcSnafu)?
+            .context(closed)?;
+        if status.batch_id == batch_id {
+            return Ok(status);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use crate::client::{ClientConfig, DiskQueue, MetricsClient, RetryConfig};
+    use crate::opentelemetry::{
+        ArrowMetricsService, ArrowMetricsServiceServer, BatchArrowRecords, BatchStatus, StatusCode,
+    };
+    use crate::server::auth::{AuthError, Authenticator, Tenant};
+    use crate::server::{MetricsServer, MetricsSink, ReceivedMetrics};
+    use opentelemetry_proto::tonic::collector::metrics::v1::metrics_service_server::{
+        MetricsService, MetricsServiceServer,
+    };
+    use opentelemetry_proto::tonic::collector::metrics::v1::{
+        ExportMetricsServiceRequest, ExportMetricsServiceResponse,
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+    use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+    use tonic::metadata::MetadataMap;
+    use tonic::transport::{Channel, Server};
+    use tonic::{Request, Response, Status, Streaming};
+
+    #[derive(Clone, Default)]
+    struct Counter(Arc<AtomicUsize>);
+
+    impl Counter {
+        fn get(&self) -> usize {
+            self.0.load(Ordering::SeqCst)
+        }
+    }
+
+    #[tonic::async_trait]
+    impl Authenticator for Counter {
+        async fn authenticate_stream(&self, _: &MetadataMap) -> Result<Option<Tenant>, AuthError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(None)
+        }
+    }
+
+    struct Deny;
+
+    #[tonic::async_trait]
+    impl Authenticator for Deny {
+        async fn authenticate_stream(&self, _: &MetadataMap) -> Result<Option<Tenant>, AuthError> {
+            Err(AuthError::Unauthenticated("no credentials".to_string()))
+        }
+    }
+
+    /// Fails the first export as unavailable.
+    #[tonic::async_trait]
+    impl MetricsSink for Counter {
+        async fn export(&self, _: ReceivedMetrics) -> Result<(), Status> {
+            match self.0.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(Status::unavailable("not ready")),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    #[tonic::async_trait]
+    impl MetricsService for Counter {
+        async fn export(
+            &self,
+            _: Request<ExportMetricsServiceRequest>,
+        ) -> Result<Response<ExportMetricsServiceResponse>, Status> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(Response::new(ExportMetricsServiceResponse::default()))
+        }
+    }
+
+    /// Acknowledges every batch but never ends a stream, keeping its senders.
+    #[derive(Default)]
+    struct Stalled(Mutex<Vec<mpsc::Sender<Result<BatchStatus, Status>>>>);
+
+    #[tonic::async_trait]
+    impl ArrowMetricsService for Stalled {
+        type ArrowMetricsStream = ReceiverStream<Result<BatchStatus, Status>>;
+
+        async fn arrow_metrics(
+            &self,
+            request: Request<Streaming<BatchArrowRecords>>,
+        ) -> Result<Response<Self::ArrowMetricsStream>, Status> {
+            let mut batches = request.into_inner();
+            let (tx, rx) = mpsc::channel(8);
+            self.0.lock().unwrap().push(tx.clone());
+            tokio::spawn(async move {
+                while let Ok(Some(batch)) = batches.message().await {
+                    let status = BatchStatus {
+                        batch_id: batch.batch_id,
+                        status_code: StatusCode::Ok as i32,
+                        status_message: String::new(),
+                    };
+                    let _ = tx.send(Ok(status)).await;
+                }
+            });
+            Ok(Response::new(ReceiverStream::new(rx)))
+        }
+    }
+
+    async fn connect(
+        server: tonic::transport::server::Router,
+        config: ClientConfig,
+    ) -> MetricsClient {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(server.serve_with_incoming(TcpListenerStream::new(listener)));
+        let channel = Channel::from_shared(format!("http://{addr}"))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        MetricsClient::new(channel, config)
+    }
+
+    fn config(max_stream_lifetime: Option<Duration>) -> ClientConfig {
+        ClientConfig {
+            max_stream_lifetime,
+            retry: RetryConfig {
+                initial_backoff: Duration::from_millis(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_rotation() {
+        let (streams, batches) = (Counter::default(), Counter::default());
+        let server = MetricsServer::new(streams.clone(), batches.clone());
+        let router = Server::builder().add_service(ArrowMetricsServiceServer::new(server));
+        let mut client = connect(router, config(Some(Duration::ZERO))).await;
+        let request = ExportMetricsServiceRequest::default();
+        for _ in 0..3 {
+            client.export(&request).await.unwrap();
+        }
+        client.rotate().await.unwrap();
+        // the first batch is retried once
+        assert_eq!(streams.get(), 4);
+        assert_eq!(batches.get(), 4);
+        assert!(!client.is_fallback());
+    }
+
+    #[tokio::test]
+    async fn test_stalled_rotation() {
+        let stalled = Arc::new(Stalled::default());
+        let router =
+            Server::builder().add_service(ArrowMetricsServiceServer::from_arc(stalled.clone()));
+        let config = ClientConfig {
+            timeout: Some(Duration::from_millis(50)),
+            ..config(Some(Duration::ZERO))
+        };
+        let mut client = connect(router, config).await;
+        let request = ExportMetricsServiceRequest::default();
+        // draining the expired stream times out, and the request goes to a new stream.
+        client.export(&request).await.unwrap();
+        client.export(&request).await.unwrap();
+        assert_eq!(stalled.0.lock().unwrap().len(), 2);
+        assert!(client.rotate().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_otlp_fallback() {
+        let otlp = Counter::default();
+        let router = Server::builder().add_service(MetricsServiceServer::new(otlp.clone()));
+        let mut client = connect(router, config(None)).await;
+        let request = ExportMetricsServiceRequest::default();
+        client.export(&request).await.unwrap();
+        client.export(&request).await.unwrap();
+        assert!(client.is_fallback());
+        assert_eq!(otlp.get(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_queue_replay() {
+        let dir = std::env::temp_dir().join(format!("otap-client-queue-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let request = ExportMetricsServiceRequest::default();
+
+        // nothing listens on the port, the request stays queued.
+        let channel = Channel::from_static("http://127.0.0.1:1").connect_lazy();
+        let mut offline = MetricsClient::new(channel, ClientConfig::default())
+            .with_queue(DiskQueue::open(&dir).unwrap());
+        offline.config.retry.max_retries = 0;
+        assert!(offline.export(&request).await.is_err());
+        assert!(offline.export(&request).await.is_err());
+        assert_eq!(offline.queue().unwrap().len(), 2);
+        drop(offline);
+
+        let batches = Counter::default();
+        let server = MetricsServer::new(Counter::default(), batches.clone());
+        let router = Server::builder().add_service(ArrowMetricsServiceServer::new(server));
+        let mut client = connect(router, config(None))
+            .await
+            .with_queue(DiskQueue::open(&dir).unwrap());
+        client.flush().await.unwrap();
+        assert!(client.queue().unwrap().is_empty());
+        // the first export is retried once
+        assert_eq!(batches.get(), 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_queue_keeps_unauthenticated() {
+        let dir = std::env::temp_dir().join(format!("otap-client-auth-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let server = MetricsServer::new(Deny, Counter::default());
+        let router = Server::builder().add_service(ArrowMetricsServiceServer::new(server));
+        let mut client = connect(router, config(None))
+            .await
+            .with_queue(DiskQueue::open(&dir).unwrap());
+        let request = ExportMetricsServiceRequest::default();
+        assert!(client.export(&request).await.is_err());
+        assert!(client.export(&request).await.is_err());
+        assert_eq!(client.queue().unwrap().len(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
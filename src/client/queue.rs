@@ -0,0 +1,194 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::error;
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use prost::Message;
+use snafu::{ensure, ResultExt};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const ENTRY_EXTENSION: &str = "otlp";
+const TMP_EXTENSION: &str = "tmp";
+
+/// Write-ahead queue of metrics requests in a local directory, one file per request.
+///
+/// Requests are stored as OTLP rather than encoded batches, since the IPC state of a batch
+/// is bound to the stream it was first sent on. Entries are named after their sequence
+/// number, so the queue is replayed in order after a restart.
+#[derive(Debug)]
+pub struct DiskQueue {
+    dir: PathBuf,
+    /// Size in bytes of every pending entry.
+    entries: BTreeMap<u64, u64>,
+    bytes: u64,
+    max_bytes: Option<u64>,
+    next_seq: u64,
+}
+
+impl DiskQueue {
+    /// Opens the queue in given directory, creating it if needed. Entries left by a previous
+    /// process are pending, and partially written ones are discarded.
+    pub fn open(dir: impl Into<PathBuf>) -> error::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).context(error::WriteMessageSnafu)?;
+        let mut entries = BTreeMap::new();
+        for entry in fs::read_dir(&dir).context(error::ReadMessageSnafu)? {
+            let entry = entry.context(error::ReadMessageSnafu)?;
+            let path = entry.path();
+            let seq = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.parse::<u64>().ok());
+            match (seq, path.extension().and_then(|e| e.to_str())) {
+                (Some(seq), Some(ENTRY_EXTENSION)) => {
+                    let size = entry.metadata().context(error::ReadMessageSnafu)?.len();
+                    entries.insert(seq, size);
+                }
+                (_, Some(TMP_EXTENSION)) => {
+                    fs::remove_file(&path).context(error::WriteMessageSnafu)?;
+                }
+                _ => {}
+            }
+        }
+        let next_seq = entries.last_key_value().map_or(0, |(seq, _)| seq + 1);
+        let bytes = entries.values().sum();
+        Ok(Self {
+            dir,
+            entries,
+            bytes,
+            max_bytes: None,
+            next_seq,
+        })
+    }
+
+    /// Bounds the total size of pending entries. Pushing beyond it fails with
+    /// [crate::Error::QueueFull] until entries are delivered.
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Persists the request and returns its sequence number. The entry is complete on disk
+    /// once this returns.
+    pub fn push(&mut self, request: &ExportMetricsServiceRequest) -> error::Result<u64> {
+        let bytes = request.encode_to_vec();
+        if let Some(max_bytes) = self.max_bytes {
+            ensure!(
+                self.bytes + bytes.len() as u64 <= max_bytes,
+                error::QueueFullSnafu { max_bytes }
+            );
+        }
+        let seq = self.next_seq;
+        let tmp = self.path(seq, TMP_EXTENSION);
+        let mut file = File::create(&tmp).context(error::WriteMessageSnafu)?;
+        file.write_all(&bytes).context(error::WriteMessageSnafu)?;
+        file.sync_all().context(error::WriteMessageSnafu)?;
+        fs::rename(&tmp, self.path(seq, ENTRY_EXTENSION)).context(error::WriteMessageSnafu)?;
+        // the rename itself is only durable once the directory is synced.
+        File::open(&self.dir)
+            .and_then(|dir| dir.sync_all())
+            .context(error::WriteMessageSnafu)?;
+        self.entries.insert(seq, bytes.len() as u64);
+        self.bytes += bytes.len() as u64;
+        self.next_seq += 1;
+        Ok(seq)
+    }
+
+    /// Sequence number of the oldest pending entry.
+    pub fn first(&self) -> Option<u64> {
+        self.entries.first_key_value().map(|(seq, _)| *seq)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Total size in bytes of pending entries.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn read(&self, seq: u64) -> error::Result<ExportMetricsServiceRequest> {
+        let bytes = fs::read(self.path(seq, ENTRY_EXTENSION)).context(error::ReadMessageSnafu)?;
+        ExportMetricsServiceRequest::decode(bytes.as_slice()).context(error::DecodeProtobufSnafu)
+    }
+
+    /// Deletes an entry once it was delivered.
+    pub fn remove(&mut self, seq: u64) -> error::Result<()> {
+        if let Some(size) = self.entries.remove(&seq) {
+            self.bytes -= size;
+            fs::remove_file(self.path(seq, ENTRY_EXTENSION)).context(error::WriteMessageSnafu)?;
+        }
+        Ok(())
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, seq: u64, extension: &str) -> PathBuf {
+        self.dir.join(format!("{seq:020}.{extension}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::queue::DiskQueue;
+    use crate::error::Error;
+    use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+    use opentelemetry_proto::tonic::metrics::v1::ResourceMetrics;
+    use std::fs;
+
+    #[test]
+    fn test_reopen() {
+        let dir = std::env::temp_dir().join(format!("otap-queue-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let request = |schema_url: &str| ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                schema_url: schema_url.to_string(),
+                ..Default::default()
+            }],
+        };
+
+        let mut queue = DiskQueue::open(&dir).unwrap();
+        assert_eq!(queue.push(&request("a")).unwrap(), 0);
+        assert_eq!(queue.push(&request("b")).unwrap(), 1);
+        queue.remove(0).unwrap();
+        fs::write(dir.join("00000000000000000002.tmp"), b"partial").unwrap();
+        drop(queue);
+
+        let mut queue = DiskQueue::open(&dir).unwrap();
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.first(), Some(1));
+        assert_eq!(queue.read(1).unwrap(), request("b"));
+        assert_eq!(queue.push(&request("c")).unwrap(), 2);
+        assert!(!dir.join("00000000000000000002.tmp").exists());
+
+        // "b" and "c" take 5 bytes each.
+        let mut queue = DiskQueue::open(&dir).unwrap().with_max_bytes(12);
+        assert_eq!(queue.bytes(), 10);
+        assert!(matches!(
+            queue.push(&request("d")),
+            Err(Error::QueueFull { max_bytes: 12, .. })
+        ));
+        queue.remove(1).unwrap();
+        assert_eq!(queue.push(&request("d")).unwrap(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,268 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Semantic comparison of OTLP requests.
+//!
+//! Requests are compared in their [OTLP/JSON](crate::export::json) form after
+//! canonicalization, so that:
+//! - attributes (including nested key-value lists) are compared regardless of their order,
+//! - resource and scope groups are compared regardless of their order,
+//! - all NaNs are equal, since they share the `"NaN"` encoding,
+//! - default and absent fields are equal, since both are omitted.
+//!
+//! Metrics, data points and exemplars keep their order.
+
+use crate::error;
+use crate::export::json::Json;
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use serde_json::Value;
+use snafu::ResultExt;
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// Arrays of key-value pairs, sorted by key.
+const ATTRIBUTE_KEYS: [&str; 2] = ["attributes", "filteredAttributes"];
+/// Arrays of resource and scope groups, sorted by their canonical JSON.
+const GROUP_KEYS: [&str; 6] = [
+    "resourceMetrics",
+    "scopeMetrics",
+    "resourceLogs",
+    "scopeLogs",
+    "resourceSpans",
+    "scopeSpans",
+];
+
+/// One difference between two requests, located by its path in the canonical OTLP/JSON
+/// form, e.g. `resourceMetrics[0].scopeMetrics[1].metrics[0].name`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    pub path: String,
+    /// Value on the left side, `None` if absent there.
+    pub left: Option<Value>,
+    /// Value on the right side, `None` if absent there.
+    pub right: Option<Value>,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "."
+        } else {
+            &self.path
+        };
+        writeln!(f, "{path}")?;
+        match &self.left {
+            Some(v) => writeln!(f, "  - {v}")?,
+            None => writeln!(f, "  - <absent>")?,
+        }
+        match &self.right {
+            Some(v) => write!(f, "  + {v}"),
+            None => write!(f, "  + <absent>"),
+        }
+    }
+}
+
+/// Returns the differences between two metrics requests, empty if they are semantically equal.
+pub fn diff_metrics(
+    left: &ExportMetricsServiceRequest,
+    right: &ExportMetricsServiceRequest,
+) -> error::Result<Vec<Difference>> {
+    let left = serde_json::to_value(Json(left)).context(error::SerializeJsonSnafu)?;
+    let right = serde_json::to_value(Json(right)).context(error::SerializeJsonSnafu)?;
+    Ok(diff_json(left, right))
+}
+
+/// Returns true if two metrics requests are semantically equal.
+pub fn metrics_eq(
+    left: &ExportMetricsServiceRequest,
+    right: &ExportMetricsServiceRequest,
+) -> error::Result<bool> {
+    diff_metrics(left, right).map(|d| d.is_empty())
+}
+
+/// Returns the differences between two OTLP/JSON requests of any signal.
+pub fn diff_json(mut left: Value, mut right: Value) -> Vec<Difference> {
+    canonicalize(&mut left, None);
+    canonicalize(&mut right, None);
+    let mut differences = vec![];
+    diff_value(&mut String::new(), &left, &right, &mut differences);
+    differences
+}
+
+/// Formats differences as a human-readable report, one entry per difference.
+pub fn format_differences(differences: &[Difference]) -> String {
+    differences
+        .iter()
+        .map(|d| d.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn canonicalize(value: &mut Value, key: Option<&str>) {
+    match value {
+        Value::Object(map) => {
+            for (k, v) in map.iter_mut() {
+                canonicalize(v, Some(k));
+            }
+            if key == Some("kvlistValue")
+                && let Some(Value::Array(values)) = map.get_mut("values")
+            {
+                sort_by_key(values);
+            }
+        }
+        Value::Array(values) => {
+            for v in values.iter_mut() {
+                canonicalize(v, None);
+            }
+            match key {
+                Some(k) if ATTRIBUTE_KEYS.contains(&k) => sort_by_key(values),
+                // children are canonical already, so their JSON is a stable sort key.
+                Some(k) if GROUP_KEYS.contains(&k) => values.sort_by_cached_key(|v| v.to_string()),
+                _ => {}
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Sorts key-value pairs by key, then by value for duplicate keys.
+fn sort_by_key(values: &mut [Value]) {
+    values.sort_by_cached_key(|kv| {
+        let key = kv.get("key").and_then(Value::as_str).unwrap_or_default();
+        (key.to_string(), kv.to_string())
+    });
+}
+
+fn diff_value(path: &mut String, left: &Value, right: &Value, out: &mut Vec<Difference>) {
+    match (left, right) {
+        (Value::Object(l), Value::Object(r)) => {
+            let keys: BTreeSet<_> = l.keys().chain(r.keys()).collect();
+            for key in keys {
+                let len = path.len();
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(key);
+                match (l.get(key), r.get(key)) {
+                    (Some(lv), Some(rv)) => diff_value(path, lv, rv, out),
+                    (lv, rv) => out.push(Difference {
+                        path: path.clone(),
+                        left: lv.cloned(),
+                        right: rv.cloned(),
+                    }),
+                }
+                path.truncate(len);
+            }
+        }
+        (Value::Array(l), Value::Array(r)) => {
+            for idx in 0..l.len().max(r.len()) {
+                let len = path.len();
+                path.push_str(&format!("[{idx}]"));
+                match (l.get(idx), r.get(idx)) {
+                    (Some(lv), Some(rv)) => diff_value(path, lv, rv, out),
+                    (lv, rv) => out.push(Difference {
+                        path: path.clone(),
+                        left: lv.cloned(),
+                        right: rv.cloned(),
+                    }),
+                }
+                path.truncate(len);
+            }
+        }
+        (l, r) if l != r => out.push(Difference {
+            path: path.clone(),
+            left: Some(l.clone()),
+            right: Some(r.clone()),
+        }),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compare::{diff_metrics, format_differences, metrics_eq};
+    use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+    use opentelemetry_proto::tonic::common::v1::any_value::Value;
+    use opentelemetry_proto::tonic::common::v1::{AnyValue, KeyValue};
+    use opentelemetry_proto::tonic::metrics::v1::{
+        metric, number_data_point, Gauge, Metric, NumberDataPoint, ResourceMetrics, ScopeMetrics,
+    };
+    use opentelemetry_proto::tonic::resource::v1::Resource;
+
+    fn kv(key: &str, value: i64) -> KeyValue {
+        KeyValue {
+            key: key.to_string(),
+            value: Some(AnyValue {
+                value: Some(Value::IntValue(value)),
+            }),
+        }
+    }
+
+    fn resource_metrics(resource: Vec<KeyValue>, name: &str, value: f64) -> ResourceMetrics {
+        ResourceMetrics {
+            resource: Some(Resource {
+                attributes: resource,
+                dropped_attributes_count: 0,
+            }),
+            scope_metrics: vec![ScopeMetrics {
+                scope: None,
+                metrics: vec![Metric {
+                    name: name.to_string(),
+                    data: Some(metric::Data::Gauge(Gauge {
+                        data_points: vec![NumberDataPoint {
+                            value: Some(number_data_point::Value::AsDouble(value)),
+                            ..Default::default()
+                        }],
+                    })),
+                    ..Default::default()
+                }],
+                schema_url: String::new(),
+            }],
+            schema_url: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_semantic_equality() {
+        let left = ExportMetricsServiceRequest {
+            resource_metrics: vec![
+                resource_metrics(vec![kv("a", 1), kv("b", 2)], "m1", f64::NAN),
+                resource_metrics(vec![kv("c", 3)], "m2", 1.0),
+            ],
+        };
+        let right = ExportMetricsServiceRequest {
+            resource_metrics: vec![
+                resource_metrics(vec![kv("c", 3)], "m2", 1.0),
+                resource_metrics(vec![kv("b", 2), kv("a", 1)], "m1", -f64::NAN),
+            ],
+        };
+        assert!(metrics_eq(&left, &right).unwrap());
+
+        let right = ExportMetricsServiceRequest {
+            resource_metrics: vec![resource_metrics(vec![kv("a", 1)], "m3", 1.0)],
+        };
+        let differences = diff_metrics(&left, &right).unwrap();
+        let paths: Vec<_> = differences.iter().map(|d| d.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "resourceMetrics[0].resource.attributes[1]",
+                "resourceMetrics[0].scopeMetrics[0].metrics[0].gauge.dataPoints[0].asDouble",
+                "resourceMetrics[0].scopeMetrics[0].metrics[0].name",
+                "resourceMetrics[1]",
+            ]
+        );
+        assert!(format_differences(&differences).contains(
+            "resourceMetrics[0].scopeMetrics[0].metrics[0].name\n  - \"m1\"\n  + \"m3\""
+        ));
+    }
+}
@@ -11,4 +11,7 @@
 // limitations under the License.
 
 pub mod decoder;
+pub mod filter;
 pub mod record_message;
+pub mod signal;
+pub mod stats;
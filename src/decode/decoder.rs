@@ -10,15 +10,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::decode::filter::MetricFilter;
 use crate::decode::record_message::RecordMessage;
+use crate::decode::signal::{SignalRequest, SignalType};
+use crate::decode::stats::{BatchStats, StreamDictionaries};
 use crate::error;
+use crate::hpack::{self, Headers};
 use crate::opentelemetry::{ArrowPayload, ArrowPayloadType, BatchArrowRecords};
-use crate::otlp::metric::metrics_from;
+use crate::otlp::metric::{regroup_metrics, visit_metrics_from, MetricsVisitor};
 use crate::otlp::related_data::RelatedData;
 use arrow::array::RecordBatch;
 use arrow::error::ArrowError;
 use arrow::ipc::reader::StreamReader;
 use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use opentelemetry_proto::tonic::metrics::v1::ResourceMetrics;
 use snafu::{ensure, OptionExt, ResultExt};
 use std::collections::HashMap;
 use std::io::Cursor;
@@ -26,6 +31,7 @@ use std::io::Cursor;
 pub struct StreamConsumer {
     payload_type: ArrowPayloadType,
     stream_reader: StreamReader<Cursor<Vec<u8>>>,
+    dictionaries: StreamDictionaries,
 }
 
 impl StreamConsumer {
@@ -36,6 +42,7 @@ impl StreamConsumer {
         Ok(Self {
             payload_type: payload,
             stream_reader,
+            dictionaries: StreamDictionaries::default(),
         })
     }
 
@@ -51,11 +58,57 @@ impl StreamConsumer {
 #[derive(Default)]
 pub struct Consumer {
     stream_consumers: HashMap<String, StreamConsumer>,
+    last_batch_stats: Option<BatchStats>,
+    stats: bool,
+    regroup: bool,
+    filter: MetricFilter,
+    headers_decoder: hpack::Decoder,
+    last_batch_headers: Headers,
 }
 
 impl Consumer {
+    /// Merges resource and scope groups that repeat within a decoded batch, see
+    /// [crate::regroup_metrics]. Disabled by default.
+    pub fn with_regroup(mut self, regroup: bool) -> Self {
+        self.regroup = regroup;
+        self
+    }
+
+    /// Collects the dictionary counts and OTLP size of [Consumer::last_batch_stats], which
+    /// compare every dictionary with the previous one and size every decoded resource.
+    /// Disabled by default, the other statistics are always kept.
+    pub fn with_stats(mut self, stats: bool) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Drops the metrics matched by the filter while decoding, skipping their data points.
+    pub fn with_filter(mut self, filter: MetricFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
     fn consume_bar(&mut self, bar: &mut BatchArrowRecords) -> error::Result<Vec<RecordMessage>> {
+        let mut stats = BatchStats::new(bar.batch_id);
+        self.last_batch_stats = None;
+        let result = self.read_payloads(bar, &mut stats);
+        if result.is_ok() {
+            self.last_batch_stats = Some(stats);
+        } else {
+            // payloads read before the failure still advanced the IPC streams.
+            #[cfg(feature = "trace")]
+            crate::telemetry::record_payloads(&stats);
+        }
+        result
+    }
+
+    fn read_payloads(
+        &mut self,
+        bar: &mut BatchArrowRecords,
+        stats: &mut BatchStats,
+    ) -> error::Result<Vec<RecordMessage>> {
         let mut records = Vec::with_capacity(bar.arrow_payloads.len());
+        let track_dictionaries = self.stats;
 
         for payload in std::mem::take(&mut bar.arrow_payloads) {
             let ArrowPayload {
@@ -63,22 +116,39 @@ impl Consumer {
                 r#type,
                 record,
             } = payload;
+            let ipc_bytes = record.len();
             let payload_type = ArrowPayloadType::try_from(r#type)
                 .map_err(|_| error::UnsupportedPayloadTypeSnafu { actual: r#type }.build())?;
+            #[cfg(feature = "trace")]
+            let _span = tracing::trace_span!(
+                "consume_payload",
+                ?payload_type,
+                schema_id = schema_id.as_str(),
+                ipc_bytes
+            )
+            .entered();
+            let payload_context = error::DecodePayloadSnafu {
+                payload_type,
+                schema_id: schema_id.clone(),
+            };
 
+            let mut schema_reset = false;
             let stream_consumer = match self.stream_consumers.get_mut(&schema_id) {
                 None => {
                     // stream consumer does not exist, remove all stream consumer with
                     // the same payload_type since schema already changed for that payload.
+                    let num_stream_consumers = self.stream_consumers.len();
                     let new_stream_consumer: HashMap<String, StreamConsumer> =
                         (std::mem::take(&mut self.stream_consumers))
                             .into_iter()
                             .filter(|(_, v)| v.payload_type != payload_type)
                             .collect::<HashMap<_, _>>();
+                    schema_reset = new_stream_consumer.len() < num_stream_consumers;
                     self.stream_consumers = new_stream_consumer;
-                    self.stream_consumers
-                        .entry(schema_id.clone())
-                        .or_insert(StreamConsumer::new(payload_type, record)?)
+                    self.stream_consumers.entry(schema_id.clone()).or_insert(
+                        StreamConsumer::new(payload_type, record)
+                            .context(payload_context.clone())?,
+                    )
                 }
                 Some(s) => {
                     // stream consumer exists for given schema id, just reset the bytes.
@@ -89,7 +159,16 @@ impl Consumer {
 
             if let Some(rs) = stream_consumer.next() {
                 // the encoder side ensures there should be only one record here.
-                let record = rs.context(error::ReadRecordBatchSnafu)?;
+                let record = rs
+                    .context(error::ReadRecordBatchSnafu)
+                    .context(payload_context)?;
+                stats.add_payload(
+                    payload_type,
+                    ipc_bytes,
+                    &record,
+                    schema_reset,
+                    track_dictionaries.then_some(&mut stream_consumer.dictionaries),
+                );
                 records.push(RecordMessage {
                     batch_id: bar.batch_id,
                     schema_id,
@@ -103,10 +182,160 @@ impl Consumer {
         Ok(records)
     }
 
+    /// Decodes the headers of the batch. Headers share HPACK state across the stream, so
+    /// every entry point decodes them first, before the batch can be rejected for any reason.
+    fn consume_headers(&mut self, bar: &BatchArrowRecords) -> error::Result<()> {
+        #[cfg(feature = "trace")]
+        let start = std::time::Instant::now();
+        match self.headers_decoder.decode(&bar.headers) {
+            Ok(headers) => {
+                self.last_batch_headers = headers;
+                Ok(())
+            }
+            Err(e) => {
+                self.last_batch_headers.clear();
+                let result = Err(e);
+                #[cfg(feature = "trace")]
+                crate::telemetry::record_outcome(start.elapsed(), &result);
+                result
+            }
+        }
+    }
+
+    /// Reads the Arrow record of each payload in the batch without decoding them to OTLP,
+    /// updating the IPC reader state of this consumer as [Consumer::consume_batches] does.
+    pub fn consume_records(
+        &mut self,
+        records: &mut BatchArrowRecords,
+    ) -> error::Result<Vec<RecordMessage>> {
+        self.consume_headers(records)?;
+        #[cfg(feature = "trace")]
+        let start = std::time::Instant::now();
+        let result = self.consume_bar(records);
+        // successfully read records are recorded once decoded by [Consumer::decode_records].
+        #[cfg(feature = "trace")]
+        if result.is_err() {
+            crate::telemetry::record_outcome(start.elapsed(), &result);
+        }
+        result
+    }
+
+    /// Headers of the last consumed batch, decoded from `BatchArrowRecords.headers`.
+    pub fn last_batch_headers(&self) -> &Headers {
+        &self.last_batch_headers
+    }
+
+    /// Statistics of the last consumed batch. Only set once the whole batch was read, and
+    /// [BatchStats::otlp_bytes] only if it was decoded by [Consumer::consume_batches] with
+    /// [Consumer::with_stats].
+    pub fn last_batch_stats(&self) -> Option<&BatchStats> {
+        self.last_batch_stats.as_ref()
+    }
+
     pub fn consume_batches(
         &mut self,
         records: &mut BatchArrowRecords,
     ) -> error::Result<ExportMetricsServiceRequest> {
+        self.consume_headers(records)?;
+        self.consume_metrics(records)
+    }
+
+    fn consume_metrics(
+        &mut self,
+        records: &mut BatchArrowRecords,
+    ) -> error::Result<ExportMetricsServiceRequest> {
+        let mut request = ExportMetricsServiceRequest::default();
+        self.consume(records, &mut |rm| {
+            request.resource_metrics.push(rm);
+            Ok(())
+        })?;
+        if self.regroup {
+            regroup_metrics(&mut request);
+        }
+        Ok(request)
+    }
+
+    /// Decodes records read by [Consumer::consume_records] from one metrics batch, applying the
+    /// filter and regrouping of this consumer like [Consumer::consume_batches] does.
+    pub fn decode_records(
+        &mut self,
+        records: &[RecordMessage],
+    ) -> error::Result<ExportMetricsServiceRequest> {
+        #[cfg(feature = "trace")]
+        let start = std::time::Instant::now();
+        let mut request = ExportMetricsServiceRequest::default();
+        let result = self.decode_record_messages(records, &mut |rm| {
+            request.resource_metrics.push(rm);
+            Ok(())
+        });
+        #[cfg(feature = "trace")]
+        crate::telemetry::record_consume(start.elapsed(), self.last_batch_stats(), &result);
+        result?;
+        if self.regroup {
+            regroup_metrics(&mut request);
+        }
+        Ok(request)
+    }
+
+    /// Decodes a batch of any signal, dispatching on the type of its main payload, so that one
+    /// stream handler can serve all signals.
+    pub fn consume_signal(
+        &mut self,
+        records: &mut BatchArrowRecords,
+    ) -> error::Result<SignalRequest> {
+        self.consume_headers(records)?;
+        match SignalType::of_batch(records)? {
+            SignalType::Metrics => self.consume_metrics(records).map(SignalRequest::Metrics),
+            // logs and traces can't be decoded yet.
+            SignalType::Logs | SignalType::Traces => error::UnsupportedPayloadTypeSnafu {
+                actual: records.arrow_payloads[0].r#type,
+            }
+            .fail(),
+        }
+    }
+
+    /// Decodes the batch like [Consumer::consume_batches], but hands each `ResourceMetrics` to
+    /// the visitor as soon as it is assembled instead of collecting them into one request.
+    ///
+    /// Related records (attributes, data points, exemplars) are still read up front, so this
+    /// does not lower peak memory. Resources visited before an error are not rolled back, and
+    /// [Consumer::with_regroup] does not apply.
+    pub fn consume_batches_with(
+        &mut self,
+        records: &mut BatchArrowRecords,
+        visitor: &mut impl MetricsVisitor,
+    ) -> error::Result<()> {
+        self.consume_headers(records)?;
+        self.consume(records, visitor)
+    }
+
+    fn consume(
+        &mut self,
+        records: &mut BatchArrowRecords,
+        visitor: &mut impl MetricsVisitor,
+    ) -> error::Result<()> {
+        #[cfg(feature = "trace")]
+        let _span = tracing::debug_span!(
+            "consume_batches",
+            batch_id = records.batch_id,
+            payloads = records.arrow_payloads.len()
+        )
+        .entered();
+        #[cfg(feature = "trace")]
+        let start = std::time::Instant::now();
+
+        let result = self.decode_batch(records, visitor);
+
+        #[cfg(feature = "trace")]
+        crate::telemetry::record_consume(start.elapsed(), self.last_batch_stats(), &result);
+        result
+    }
+
+    fn decode_batch(
+        &mut self,
+        records: &mut BatchArrowRecords,
+        visitor: &mut impl MetricsVisitor,
+    ) -> error::Result<()> {
         ensure!(!records.arrow_payloads.is_empty(), error::EmptyBatchSnafu);
 
         let main_record_type = records.arrow_payloads[0].r#type;
@@ -119,10 +348,7 @@ impl Consumer {
         match payload_type {
             ArrowPayloadType::UnivariateMetrics => {
                 let record_message = self.consume_bar(records)?;
-                let (mut related_data, metric_record) =
-                    RelatedData::from_record_messages(&record_message)?;
-                let metric_rec_idx = metric_record.context(error::MetricRecordNotFoundSnafu)?;
-                metrics_from(&record_message[metric_rec_idx].record, &mut related_data)
+                self.decode_record_messages(&record_message, visitor)
             }
 
             ArrowPayloadType::Logs => error::UnsupportedPayloadTypeSnafu {
@@ -139,11 +365,88 @@ impl Consumer {
             .fail(),
         }
     }
+
+    fn decode_record_messages(
+        &mut self,
+        records: &[RecordMessage],
+        visitor: &mut impl MetricsVisitor,
+    ) -> error::Result<()> {
+        let main_record = records.first().context(error::EmptyBatchSnafu)?;
+        ensure!(
+            main_record.payload_type == ArrowPayloadType::UnivariateMetrics,
+            error::UnsupportedPayloadTypeSnafu {
+                actual: main_record.payload_type as i32,
+            }
+        );
+        if !self.stats {
+            return decode_filtered_metrics(records, &self.filter, visitor);
+        }
+        // size of the equivalent ExportMetricsServiceRequest, summed per resource.
+        let mut otlp_bytes = 0;
+        decode_filtered_metrics(records, &self.filter, &mut |rm: ResourceMetrics| {
+            otlp_bytes += prost::encoding::message::encoded_len(1, &rm);
+            visitor.visit_resource_metrics(rm)
+        })?;
+        if let Some(stats) = &mut self.last_batch_stats {
+            stats.otlp_bytes = Some(otlp_bytes);
+        }
+        Ok(())
+    }
+}
+
+/// Builds [ExportMetricsServiceRequest] from the records of one metrics batch.
+pub fn decode_metrics(records: &[RecordMessage]) -> error::Result<ExportMetricsServiceRequest> {
+    let mut request = ExportMetricsServiceRequest::default();
+    decode_metrics_with(records, &mut |rm| {
+        request.resource_metrics.push(rm);
+        Ok(())
+    })?;
+    Ok(request)
+}
+
+/// Decodes the records of one metrics batch like [decode_metrics], handing each
+/// `ResourceMetrics` to the visitor as soon as it is assembled.
+pub fn decode_metrics_with(
+    records: &[RecordMessage],
+    visitor: &mut impl MetricsVisitor,
+) -> error::Result<()> {
+    decode_filtered_metrics(records, &MetricFilter::default(), visitor)
+}
+
+fn decode_filtered_metrics(
+    records: &[RecordMessage],
+    filter: &MetricFilter,
+    visitor: &mut impl MetricsVisitor,
+) -> error::Result<()> {
+    #[cfg(feature = "trace")]
+    let _span = tracing::trace_span!("decode_metrics", records = records.len()).entered();
+    let (mut related_data, metric_record) = RelatedData::from_record_messages(records, filter)?;
+    let metric_rec_idx = metric_record.context(error::MetricRecordNotFoundSnafu)?;
+    let metric_record = &records[metric_rec_idx];
+    visit_metrics_from(&metric_record.record, &mut related_data, visitor)
+        .context(metric_record.decode_context())
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::test_util::{create_record_batch, create_test_schema};
+    use crate::decode::decoder::Consumer;
+    use crate::decode::filter::MetricFilter;
+    use crate::decode::signal::{SignalRequest, SignalType};
+    use crate::encode::encoder::Producer;
+    use crate::error::{EmptyBatchSnafu, Error};
+    use crate::opentelemetry::ArrowPayloadType;
+    use crate::otlp::metric::{regroup_metrics, visit_metrics_from};
+    use crate::otlp::related_data::RelatedData;
+    use crate::test_util::{create_gauge_batch, create_record_batch, create_test_schema};
+    use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+    use opentelemetry_proto::tonic::common::v1::{
+        any_value, AnyValue, InstrumentationScope, KeyValue,
+    };
+    use opentelemetry_proto::tonic::metrics::v1::{
+        metric, number_data_point, Gauge, Metric, NumberDataPoint, ResourceMetrics, ScopeMetrics,
+    };
+    use opentelemetry_proto::tonic::resource::v1::Resource;
+    use prost::Message;
     use std::io::Cursor;
     use std::sync::Arc;
 
@@ -171,4 +474,238 @@ mod tests {
         *reader.get_mut() = Cursor::new(std::mem::take(writer.get_mut()));
         assert_eq!(batch2, reader.next().unwrap().unwrap());
     }
+
+    #[test]
+    fn test_regroup() {
+        let resource_metrics = |schema_url: &str, scope: &str, name: &str| ResourceMetrics {
+            resource: None,
+            scope_metrics: vec![ScopeMetrics {
+                scope: Some(InstrumentationScope {
+                    name: scope.to_string(),
+                    ..Default::default()
+                }),
+                metrics: vec![Metric {
+                    name: name.to_string(),
+                    data: Some(metric::Data::Gauge(Gauge::default())),
+                    ..Default::default()
+                }],
+                schema_url: String::new(),
+            }],
+            schema_url: schema_url.to_string(),
+        };
+        // resources interleave, and so do the scopes of the first resource.
+        let request = ExportMetricsServiceRequest {
+            resource_metrics: vec![
+                resource_metrics("r1", "s1", "a"),
+                resource_metrics("r2", "s1", "b"),
+                resource_metrics("r1", "s2", "c"),
+                resource_metrics("r1", "s1", "d"),
+            ],
+        };
+        let mut batch = Producer::default().produce_batches(&request).unwrap();
+        let mut consumer = Consumer::default().with_regroup(true);
+        let decoded = consumer.consume_batches(&mut batch).unwrap();
+
+        let groups: Vec<_> = decoded
+            .resource_metrics
+            .iter()
+            .map(|rm| {
+                let scopes: Vec<_> = rm
+                    .scope_metrics
+                    .iter()
+                    .map(|sm| {
+                        let names: Vec<_> = sm.metrics.iter().map(|m| m.name.as_str()).collect();
+                        (sm.scope.as_ref().unwrap().name.as_str(), names)
+                    })
+                    .collect();
+                (rm.schema_url.as_str(), scopes)
+            })
+            .collect();
+        assert_eq!(
+            groups,
+            vec![
+                ("r1", vec![("s1", vec!["a", "d"]), ("s2", vec!["c"])]),
+                ("r2", vec![("s1", vec!["b"])]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_regroup_unordered_attributes() {
+        let attribute = |key: &str| KeyValue {
+            key: key.to_string(),
+            value: Some(AnyValue {
+                value: Some(any_value::Value::StringValue(key.to_string())),
+            }),
+        };
+        let resource_metrics = |keys: [&str; 2], name: &str| ResourceMetrics {
+            resource: Some(Resource {
+                attributes: keys.map(attribute).to_vec(),
+                ..Default::default()
+            }),
+            scope_metrics: vec![ScopeMetrics {
+                scope: Some(InstrumentationScope {
+                    attributes: keys.map(attribute).to_vec(),
+                    ..Default::default()
+                }),
+                metrics: vec![Metric {
+                    name: name.to_string(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mut request = ExportMetricsServiceRequest {
+            resource_metrics: vec![
+                resource_metrics(["a", "b"], "x"),
+                resource_metrics(["b", "a"], "y"),
+            ],
+        };
+        regroup_metrics(&mut request);
+
+        assert_eq!(request.resource_metrics.len(), 1);
+        let scope_metrics = &request.resource_metrics[0].scope_metrics;
+        assert_eq!(scope_metrics.len(), 1);
+        let names: Vec<_> = scope_metrics[0].metrics.iter().map(|m| &m.name).collect();
+        assert_eq!(names, ["x", "y"]);
+    }
+
+    #[test]
+    fn test_consume_with_visitor() {
+        let request = ExportMetricsServiceRequest {
+            resource_metrics: ["a", "b", "c"]
+                .map(|name| ResourceMetrics {
+                    scope_metrics: vec![ScopeMetrics {
+                        metrics: vec![Metric {
+                            name: name.to_string(),
+                            data: Some(metric::Data::Gauge(Gauge::default())),
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                })
+                .to_vec(),
+        };
+        let expected = Consumer::default()
+            .consume_batches(&mut Producer::default().produce_batches(&request).unwrap())
+            .unwrap();
+
+        let mut visited = vec![];
+        let mut consumer = Consumer::default().with_stats(true);
+        let mut batch = Producer::default().produce_batches(&request).unwrap();
+        consumer
+            .consume_batches_with(&mut batch, &mut |rm| {
+                visited.push(rm);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(visited, expected.resource_metrics);
+        assert_eq!(
+            consumer.last_batch_stats().unwrap().otlp_bytes,
+            Some(expected.encoded_len())
+        );
+    }
+
+    #[test]
+    fn test_related_data_read_up_front() {
+        let point = NumberDataPoint {
+            value: Some(number_data_point::Value::AsInt(1)),
+            ..Default::default()
+        };
+        let request = ExportMetricsServiceRequest {
+            resource_metrics: ["a", "b", "c"]
+                .map(|name| ResourceMetrics {
+                    scope_metrics: vec![ScopeMetrics {
+                        metrics: vec![Metric {
+                            name: name.to_string(),
+                            data: Some(metric::Data::Gauge(Gauge {
+                                data_points: vec![point.clone(); 2],
+                            })),
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    }],
+                    schema_url: name.to_string(),
+                    ..Default::default()
+                })
+                .to_vec(),
+        };
+        let mut batch = Producer::default().produce_batches(&request).unwrap();
+        let records = Consumer::default().consume_records(&mut batch).unwrap();
+
+        // every data point is held before the first resource is assembled.
+        let (mut related_data, idx) =
+            RelatedData::from_record_messages(&records, &MetricFilter::default()).unwrap();
+        assert_eq!(related_data.number_data_points_store.len(), 6);
+
+        // the first resource is handed over once the second one starts, whose points are
+        // still held along with the third's when the visitor stops decoding.
+        let mut visited = 0;
+        visit_metrics_from(
+            &records[idx.unwrap()].record,
+            &mut related_data,
+            &mut |_| {
+                visited += 1;
+                EmptyBatchSnafu.fail()
+            },
+        )
+        .unwrap_err();
+        assert_eq!(visited, 1);
+        assert_eq!(related_data.number_data_points_store.len(), 4);
+    }
+
+    #[test]
+    fn test_consume_signal() {
+        let mut consumer = Consumer::default();
+        let mut batch = create_gauge_batch(&mut Producer::default(), &["a"], &[1]);
+        let request = consumer.consume_signal(&mut batch).unwrap();
+        assert_eq!(request.signal_type(), SignalType::Metrics);
+        assert!(matches!(request, SignalRequest::Metrics(r) if r.resource_metrics.len() == 1));
+
+        let mut batch = create_gauge_batch(&mut Producer::default(), &["a"], &[1]);
+        batch.arrow_payloads[0].r#type = ArrowPayloadType::Logs as i32;
+        assert_eq!(SignalType::of_batch(&batch).unwrap(), SignalType::Logs);
+        assert!(matches!(
+            consumer.consume_signal(&mut batch),
+            Err(Error::UnsupportedPayloadType { .. })
+        ));
+    }
+
+    #[test]
+    fn test_headers_of_rejected_batches() {
+        let request = ExportMetricsServiceRequest::default();
+        let mut producer = Producer::default();
+        let mut consumer = Consumer::default();
+        let tenant = |consumer: &Consumer| consumer.last_batch_headers()["x-tenant"].clone();
+
+        let mut batch = producer
+            .produce_batches_with_headers(&request, &[("x-tenant", "a")])
+            .unwrap();
+        consumer.consume_batches(&mut batch).unwrap();
+        assert_eq!(tenant(&consumer), vec!["a"]);
+
+        // rejected as empty, but its headers still update the HPACK table.
+        let mut batch = producer
+            .produce_records_with_headers(vec![], &[("x-tenant", "b")])
+            .unwrap();
+        assert!(matches!(
+            consumer.consume_batches(&mut batch),
+            Err(Error::EmptyBatch { .. })
+        ));
+        assert_eq!(tenant(&consumer), vec!["b"]);
+
+        // sent as an index into the dynamic table.
+        let mut batch = producer
+            .produce_batches_with_headers(&request, &[("x-tenant", "b")])
+            .unwrap();
+        assert_eq!(batch.headers.len(), 1);
+        consumer.consume_signal(&mut batch).unwrap();
+        assert_eq!(tenant(&consumer), vec!["b"]);
+
+        batch.headers = vec![0xff, 0x80];
+        assert!(consumer.consume_records(&mut batch).is_err());
+        assert!(consumer.last_batch_headers().is_empty());
+    }
 }
@@ -0,0 +1,349 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::otlp::metric::MetricType;
+use opentelemetry_proto::tonic::common::v1::any_value::Value;
+use opentelemetry_proto::tonic::common::v1::KeyValue;
+
+/// Metrics to drop while decoding, see [crate::Consumer::with_filter]. A metric is dropped if
+/// any of the matchers matches it. Data points of dropped metrics are skipped without being
+/// decoded.
+#[derive(Clone, Debug, Default)]
+pub struct MetricFilter {
+    matchers: Vec<MetricMatcher>,
+}
+
+impl MetricFilter {
+    pub fn drop(mut self, matcher: MetricMatcher) -> Self {
+        self.matchers.push(matcher);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matchers.is_empty()
+    }
+
+    pub(crate) fn excludes(
+        &self,
+        name: &str,
+        metric_type: MetricType,
+        unit: &str,
+        resource_attributes: &[KeyValue],
+    ) -> bool {
+        self.matchers
+            .iter()
+            .any(|m| m.matches(name, metric_type, unit, resource_attributes))
+    }
+}
+
+/// Matches metrics meeting all of its conditions, or all metrics if none is set.
+#[derive(Clone, Debug, Default)]
+pub struct MetricMatcher {
+    name: Option<String>,
+    metric_type: Option<MetricType>,
+    unit: Option<String>,
+    resource_attribute: Option<(String, String)>,
+}
+
+impl MetricMatcher {
+    /// Matches names against a pattern where `*` stands for any sequence of characters, e.g.
+    /// `http.server.*`.
+    pub fn with_name(mut self, pattern: impl Into<String>) -> Self {
+        self.name = Some(pattern.into());
+        self
+    }
+
+    pub fn with_type(mut self, metric_type: MetricType) -> Self {
+        self.metric_type = Some(metric_type);
+        self
+    }
+
+    pub fn with_unit(mut self, unit: impl Into<String>) -> Self {
+        self.unit = Some(unit.into());
+        self
+    }
+
+    /// Matches metrics whose resource has a string attribute of given key and value.
+    pub fn with_resource_attribute(
+        mut self,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Self {
+        self.resource_attribute = Some((key.into(), value.into()));
+        self
+    }
+
+    fn matches(
+        &self,
+        name: &str,
+        metric_type: MetricType,
+        unit: &str,
+        resource_attributes: &[KeyValue],
+    ) -> bool {
+        self.name
+            .as_ref()
+            .map_or(true, |pattern| glob_match(pattern, name))
+            && self.metric_type.map_or(true, |t| t == metric_type)
+            && self.unit.as_ref().map_or(true, |u| u == unit)
+            && self
+                .resource_attribute
+                .as_ref()
+                .map_or(true, |(key, value)| {
+                    resource_attributes.iter().any(|kv| {
+                        kv.key == *key
+                            && matches!(
+                                kv.value.as_ref().and_then(|v| v.value.as_ref()),
+                                Some(Value::StringValue(v)) if v == value
+                            )
+                    })
+                })
+    }
+}
+
+/// Matches `value` against `pattern`, where `*` matches any sequence of characters.
+fn glob_match(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // safety: split always yields at least one part.
+    let first = parts.next().unwrap();
+    let Some(mut rest) = value.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<_> = parts.collect();
+    let Some(last) = parts.pop() else {
+        // no wildcard
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decode::decoder::Consumer;
+    use crate::decode::filter::{glob_match, MetricFilter, MetricMatcher};
+    use crate::encode::encoder::Producer;
+    use crate::otlp::metric::MetricType;
+    use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+    use opentelemetry_proto::tonic::common::v1::{any_value, AnyValue, KeyValue};
+    use opentelemetry_proto::tonic::metrics::v1::exponential_histogram_data_point::Buckets;
+    use opentelemetry_proto::tonic::metrics::v1::{
+        exemplar, metric, number_data_point, Exemplar, ExponentialHistogram,
+        ExponentialHistogramDataPoint, Gauge, Histogram, HistogramDataPoint, Metric,
+        NumberDataPoint, ResourceMetrics, ScopeMetrics, Sum, Summary, SummaryDataPoint,
+    };
+    use opentelemetry_proto::tonic::resource::v1::Resource;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("http.*", "http.server.duration"));
+        assert!(glob_match("*.duration", "http.server.duration"));
+        assert!(glob_match("http.*.duration", "http.server.duration"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*a", "abba"));
+        assert!(!glob_match("a*b*a", "aba_"));
+        assert!(!glob_match("http.*", "rpc.server.duration"));
+        assert!(!glob_match("http", "http.server"));
+    }
+
+    #[test]
+    fn test_filter() {
+        let attributes = vec![KeyValue {
+            key: "id".to_string(),
+            value: Some(AnyValue {
+                value: Some(any_value::Value::IntValue(1)),
+            }),
+        }];
+        let number = || {
+            vec![NumberDataPoint {
+                attributes: attributes.clone(),
+                time_unix_nano: 1,
+                ..Default::default()
+            }]
+        };
+        let metric = |name: &str, unit: &str, data| Metric {
+            name: name.to_string(),
+            unit: unit.to_string(),
+            data: Some(data),
+            ..Default::default()
+        };
+        let resource_metrics = |env: &str| ResourceMetrics {
+            resource: Some(Resource {
+                attributes: vec![KeyValue {
+                    key: "env".to_string(),
+                    value: Some(AnyValue {
+                        value: Some(any_value::Value::StringValue(env.to_string())),
+                    }),
+                }],
+                ..Default::default()
+            }),
+            scope_metrics: vec![ScopeMetrics {
+                scope: Some(Default::default()),
+                metrics: vec![
+                    metric(
+                        "http.server.requests",
+                        "1",
+                        metric::Data::Sum(Sum {
+                            data_points: number(),
+                            ..Default::default()
+                        }),
+                    ),
+                    metric(
+                        "cpu.usage",
+                        "1",
+                        metric::Data::Gauge(Gauge {
+                            data_points: number(),
+                        }),
+                    ),
+                    metric(
+                        "memory.usage",
+                        "By",
+                        metric::Data::Gauge(Gauge {
+                            data_points: number(),
+                        }),
+                    ),
+                    metric(
+                        "rpc.duration",
+                        "s",
+                        metric::Data::Histogram(Histogram {
+                            data_points: vec![HistogramDataPoint {
+                                attributes: attributes.clone(),
+                                count: 1,
+                                ..Default::default()
+                            }],
+                            ..Default::default()
+                        }),
+                    ),
+                    metric(
+                        "gc.pause",
+                        "s",
+                        metric::Data::Summary(Summary {
+                            data_points: vec![SummaryDataPoint {
+                                attributes: attributes.clone(),
+                                count: 1,
+                                ..Default::default()
+                            }],
+                        }),
+                    ),
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let request = ExportMetricsServiceRequest {
+            resource_metrics: vec![resource_metrics("test"), resource_metrics("prod")],
+        };
+
+        let filter = MetricFilter::default()
+            .drop(MetricMatcher::default().with_name("http.*"))
+            .drop(MetricMatcher::default().with_unit("By"))
+            .drop(MetricMatcher::default().with_type(MetricType::Summary))
+            .drop(MetricMatcher::default().with_resource_attribute("env", "test"));
+        let mut batch = Producer::default().produce_batches(&request).unwrap();
+        let mut consumer = Consumer::default().with_filter(filter);
+        let decoded = consumer.consume_batches(&mut batch).unwrap();
+
+        // the "test" resource is dropped as a whole.
+        let mut expected = request.resource_metrics[1].clone();
+        let metrics = &mut expected.scope_metrics[0].metrics;
+        metrics.retain(|m| ["cpu.usage", "rpc.duration"].contains(&m.name.as_str()));
+        assert_eq!(decoded.resource_metrics, vec![expected]);
+    }
+
+    #[test]
+    fn test_filter_exemplars() {
+        let attributes = |id: i64| {
+            vec![KeyValue {
+                key: "id".to_string(),
+                value: Some(AnyValue {
+                    value: Some(any_value::Value::IntValue(id)),
+                }),
+            }]
+        };
+        let exemplars = |value: f64| {
+            vec![Exemplar {
+                time_unix_nano: 1,
+                value: Some(exemplar::Value::AsDouble(value)),
+                ..Default::default()
+            }]
+        };
+        let exp_histogram = |id: i64| {
+            metric::Data::ExponentialHistogram(ExponentialHistogram {
+                data_points: vec![ExponentialHistogramDataPoint {
+                    attributes: attributes(id),
+                    count: 3,
+                    scale: 2,
+                    zero_count: 1,
+                    positive: Some(Buckets {
+                        offset: -1,
+                        bucket_counts: vec![1, 1],
+                    }),
+                    negative: Some(Default::default()),
+                    exemplars: exemplars(id as f64),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            })
+        };
+        let gauge = |id: i64| {
+            metric::Data::Gauge(Gauge {
+                data_points: vec![NumberDataPoint {
+                    attributes: attributes(id),
+                    value: Some(number_data_point::Value::AsInt(id)),
+                    exemplars: exemplars(id as f64),
+                    ..Default::default()
+                }],
+            })
+        };
+        // kept points follow dropped ones, so they only get their own attributes and
+        // exemplars if ids are followed across skipped rows.
+        let metrics = [
+            ("latency", exp_histogram(1)),
+            ("drop.latency", exp_histogram(2)),
+            ("latency.max", exp_histogram(3)),
+            ("usage", gauge(4)),
+            ("drop.usage", gauge(5)),
+            ("usage.max", gauge(6)),
+        ]
+        .map(|(name, data)| Metric {
+            name: name.to_string(),
+            data: Some(data),
+            ..Default::default()
+        });
+        let request = ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                resource: Some(Default::default()),
+                scope_metrics: vec![ScopeMetrics {
+                    scope: Some(Default::default()),
+                    metrics: metrics.to_vec(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+
+        let filter = MetricFilter::default().drop(MetricMatcher::default().with_name("drop.*"));
+        let mut batch = Producer::default().produce_batches(&request).unwrap();
+        let mut consumer = Consumer::default().with_filter(filter);
+        let decoded = consumer.consume_batches(&mut batch).unwrap();
+
+        let mut expected = request.clone();
+        expected.resource_metrics[0].scope_metrics[0]
+            .metrics
+            .retain(|m| !m.name.starts_with("drop."));
+        assert_eq!(decoded, expected);
+    }
+}
@@ -10,15 +10,40 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::error;
 use crate::opentelemetry::ArrowPayloadType;
 use arrow::array::RecordBatch;
 
 /// Wrapper for [RecordBatch].
 pub struct RecordMessage {
-    #[allow(unused)]
     pub(crate) batch_id: i64,
-    #[allow(unused)]
     pub(crate) schema_id: String,
     pub(crate) payload_type: ArrowPayloadType,
     pub(crate) record: RecordBatch,
 }
+
+impl RecordMessage {
+    pub fn batch_id(&self) -> i64 {
+        self.batch_id
+    }
+
+    pub fn schema_id(&self) -> &str {
+        &self.schema_id
+    }
+
+    pub fn payload_type(&self) -> ArrowPayloadType {
+        self.payload_type
+    }
+
+    pub fn record(&self) -> &RecordBatch {
+        &self.record
+    }
+
+    /// Context attached to errors raised while decoding this payload.
+    pub(crate) fn decode_context(&self) -> error::DecodePayloadSnafu<ArrowPayloadType, String> {
+        error::DecodePayloadSnafu {
+            payload_type: self.payload_type,
+            schema_id: self.schema_id.clone(),
+        }
+    }
+}
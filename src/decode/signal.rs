@@ -0,0 +1,92 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::error;
+use crate::opentelemetry::{ArrowPayloadType, BatchArrowRecords};
+use opentelemetry_proto::tonic::collector::logs::v1::ExportLogsServiceRequest;
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+use snafu::ensure;
+
+/// Telemetry signal carried by an OTAP batch.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum SignalType {
+    Metrics,
+    Logs,
+    Traces,
+}
+
+impl SignalType {
+    /// Returns the signal of given main payload type, `None` for related payload types.
+    pub fn from_main_payload_type(payload_type: ArrowPayloadType) -> Option<Self> {
+        match payload_type {
+            ArrowPayloadType::UnivariateMetrics | ArrowPayloadType::MultivariateMetrics => {
+                Some(Self::Metrics)
+            }
+            ArrowPayloadType::Logs => Some(Self::Logs),
+            ArrowPayloadType::Spans => Some(Self::Traces),
+            _ => None,
+        }
+    }
+
+    /// Returns the signal of a batch from the type of its first payload, which must be the
+    /// main record of the batch.
+    pub fn of_batch(batch: &BatchArrowRecords) -> error::Result<Self> {
+        ensure!(!batch.arrow_payloads.is_empty(), error::EmptyBatchSnafu);
+        let main_record_type = batch.arrow_payloads[0].r#type;
+        ArrowPayloadType::try_from(main_record_type)
+            .ok()
+            .and_then(Self::from_main_payload_type)
+            .ok_or_else(|| {
+                error::UnsupportedPayloadTypeSnafu {
+                    actual: main_record_type,
+                }
+                .build()
+            })
+    }
+}
+
+/// OTLP request of any signal, as returned by [crate::Consumer::consume_signal].
+#[derive(Clone, PartialEq, Debug)]
+pub enum SignalRequest {
+    Metrics(ExportMetricsServiceRequest),
+    Logs(ExportLogsServiceRequest),
+    Traces(ExportTraceServiceRequest),
+}
+
+impl SignalRequest {
+    pub fn signal_type(&self) -> SignalType {
+        match self {
+            SignalRequest::Metrics(_) => SignalType::Metrics,
+            SignalRequest::Logs(_) => SignalType::Logs,
+            SignalRequest::Traces(_) => SignalType::Traces,
+        }
+    }
+}
+
+impl From<ExportMetricsServiceRequest> for SignalRequest {
+    fn from(request: ExportMetricsServiceRequest) -> Self {
+        SignalRequest::Metrics(request)
+    }
+}
+
+impl From<ExportLogsServiceRequest> for SignalRequest {
+    fn from(request: ExportLogsServiceRequest) -> Self {
+        SignalRequest::Logs(request)
+    }
+}
+
+impl From<ExportTraceServiceRequest> for SignalRequest {
+    fn from(request: ExportTraceServiceRequest) -> Self {
+        SignalRequest::Traces(request)
+    }
+}
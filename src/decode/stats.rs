@@ -0,0 +1,299 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::opentelemetry::ArrowPayloadType;
+use arrow::array::{Array, ArrayRef, AsArray, RecordBatch};
+use arrow::datatypes::DataType;
+use std::collections::BTreeMap;
+
+/// Size statistics of one [crate::opentelemetry::BatchArrowRecords].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BatchStats {
+    pub batch_id: i64,
+    pub payloads: BTreeMap<ArrowPayloadType, PayloadStats>,
+    /// Encoded size of the equivalent OTLP protobuf request, only known once the batch
+    /// has been decoded.
+    pub otlp_bytes: Option<usize>,
+}
+
+/// Statistics of all payloads of one [ArrowPayloadType].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PayloadStats {
+    pub payloads: usize,
+    /// Size of the Arrow IPC messages of the payloads, including dictionary batches.
+    pub ipc_bytes: usize,
+    pub rows: usize,
+    /// Non-null keys of all dictionary encoded columns. Only counted if dictionaries are
+    /// tracked, see [BatchStats::add_payload].
+    pub dictionary_keys: usize,
+    /// Values added to the dictionaries of all dictionary encoded columns. Dictionaries are
+    /// accumulated across batches of the same IPC stream, so values sent in earlier batches
+    /// are not counted again.
+    pub dictionary_values: usize,
+    /// Number of times the payload schema changed and a new IPC stream was started.
+    pub schema_resets: usize,
+}
+
+impl BatchStats {
+    pub fn new(batch_id: i64) -> Self {
+        Self {
+            batch_id,
+            ..Default::default()
+        }
+    }
+
+    /// Adds a payload read from `ipc_bytes` of IPC messages, given the dictionaries of the
+    /// previous payload of the same IPC stream. Dictionary counts are skipped without them,
+    /// as they compare every dictionary with the previous one.
+    pub fn add_payload(
+        &mut self,
+        payload_type: ArrowPayloadType,
+        ipc_bytes: usize,
+        record: &RecordBatch,
+        schema_reset: bool,
+        dictionaries: Option<&mut StreamDictionaries>,
+    ) {
+        let stats = self.payloads.entry(payload_type).or_default();
+        stats.payloads += 1;
+        stats.ipc_bytes += ipc_bytes;
+        stats.rows += record.num_rows();
+        stats.schema_resets += schema_reset as usize;
+
+        let Some(dictionaries) = dictionaries else {
+            return;
+        };
+        let mut current = vec![];
+        for column in record.columns() {
+            collect_dictionaries(column, &mut current);
+        }
+        for (idx, (keys, values)) in current.iter().enumerate() {
+            stats.dictionary_keys += keys;
+            stats.dictionary_values += match dictionaries.values.get(idx) {
+                Some(prev) if is_prefix(prev, values) => values.len() - prev.len(),
+                _ => values.len(),
+            };
+        }
+        dictionaries.values = current.into_iter().map(|(_, values)| values).collect();
+    }
+
+    /// Total size of the OTAP payloads.
+    pub fn otap_bytes(&self) -> usize {
+        self.payloads.values().map(|p| p.ipc_bytes).sum()
+    }
+
+    pub fn schema_resets(&self) -> usize {
+        self.payloads.values().map(|p| p.schema_resets).sum()
+    }
+
+    /// Ratio of the OTLP protobuf size to the OTAP size, higher is better.
+    pub fn compression_ratio(&self) -> Option<f64> {
+        let otap_bytes = self.otap_bytes();
+        match self.otlp_bytes {
+            Some(otlp_bytes) if otap_bytes > 0 => Some(otlp_bytes as f64 / otap_bytes as f64),
+            _ => None,
+        }
+    }
+
+    /// Accumulates statistics of another batch, e.g. to measure a whole stream.
+    pub fn merge(&mut self, other: &BatchStats) {
+        self.batch_id = other.batch_id;
+        for (payload_type, stats) in &other.payloads {
+            let s = self.payloads.entry(*payload_type).or_default();
+            s.payloads += stats.payloads;
+            s.ipc_bytes += stats.ipc_bytes;
+            s.rows += stats.rows;
+            s.dictionary_keys += stats.dictionary_keys;
+            s.dictionary_values += stats.dictionary_values;
+            s.schema_resets += stats.schema_resets;
+        }
+        self.otlp_bytes = match (self.otlp_bytes, other.otlp_bytes) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, b) => a.or(b),
+        };
+    }
+}
+
+impl PayloadStats {
+    /// Share of dictionary keys referring to a value already present in the dictionary,
+    /// i.e. not added by the same batch.
+    pub fn dictionary_hit_ratio(&self) -> Option<f64> {
+        if self.dictionary_keys == 0 {
+            return None;
+        }
+        let hits = self.dictionary_keys.saturating_sub(self.dictionary_values);
+        Some(hits as f64 / self.dictionary_keys as f64)
+    }
+}
+
+/// Dictionaries of the last payload of one IPC stream, in column order.
+#[derive(Debug, Default)]
+pub struct StreamDictionaries {
+    values: Vec<ArrayRef>,
+}
+
+/// Collects the non-null key count and the values of all dictionary encoded columns.
+fn collect_dictionaries(array: &ArrayRef, out: &mut Vec<(usize, ArrayRef)>) {
+    match array.data_type() {
+        DataType::Dictionary(_, _) => {
+            let dict = array.as_any_dictionary();
+            let keys = dict.keys().len() - dict.keys().null_count();
+            out.push((keys, dict.values().clone()));
+        }
+        DataType::Struct(_) => {
+            for column in array.as_struct().columns() {
+                collect_dictionaries(column, out);
+            }
+        }
+        DataType::List(_) => collect_dictionaries(array.as_list::<i32>().values(), out),
+        _ => {}
+    }
+}
+
+/// Whether `values` extends `prev`, as a delta dictionary batch does.
+fn is_prefix(prev: &ArrayRef, values: &ArrayRef) -> bool {
+    prev.len() <= values.len() && values.slice(0, prev.len()).as_ref() == prev.as_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decode::decoder::Consumer;
+    use crate::decode::stats::{BatchStats, StreamDictionaries};
+    use crate::encode::encoder::Producer;
+    use crate::opentelemetry::ArrowPayloadType;
+    use crate::test_util::create_gauge_batch;
+    use arrow::array::{ArrayRef, DictionaryArray, RecordBatch, StringArray, UInt8Array};
+    use arrow::datatypes::UInt8Type;
+    use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+    use opentelemetry_proto::tonic::metrics::v1::{
+        metric, Gauge, Metric, ResourceMetrics, ScopeMetrics,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn test_consumer_stats() {
+        let mut producer = Producer::default();
+        let mut consumer = Consumer::default().with_stats(true);
+        let mut batch = create_gauge_batch(&mut producer, &["a", "b", "c"], &[1, 2, 3]);
+        let ipc_bytes = batch
+            .arrow_payloads
+            .iter()
+            .map(|p| p.record.len())
+            .sum::<usize>();
+        let _ = consumer.consume_batches(&mut batch).unwrap();
+
+        let stats = consumer.last_batch_stats().unwrap();
+        assert_eq!(ipc_bytes, stats.otap_bytes());
+        assert_eq!(0, stats.schema_resets());
+        assert!(stats.compression_ratio().unwrap() > 0.0);
+        assert_eq!(3, stats.payloads[&ArrowPayloadType::UnivariateMetrics].rows);
+        assert_eq!(3, stats.payloads[&ArrowPayloadType::NumberDataPoints].rows);
+
+        // without detailed stats, payloads are still counted.
+        let mut consumer = Consumer::default();
+        let mut batch = producer.produce_batches(&request(&["a", "b"])).unwrap();
+        let _ = consumer.consume_batches(&mut batch).unwrap();
+        let stats = consumer.last_batch_stats().unwrap();
+        let metrics = &stats.payloads[&ArrowPayloadType::UnivariateMetrics];
+        assert_eq!(
+            (2, 0, 0),
+            (
+                metrics.rows,
+                metrics.dictionary_keys,
+                metrics.dictionary_values
+            )
+        );
+        assert_eq!(None, stats.otlp_bytes);
+    }
+
+    fn request(names: &[&str]) -> ExportMetricsServiceRequest {
+        ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                scope_metrics: vec![ScopeMetrics {
+                    metrics: names
+                        .iter()
+                        .map(|name| Metric {
+                            name: name.to_string(),
+                            data: Some(metric::Data::Gauge(Gauge::default())),
+                            ..Default::default()
+                        })
+                        .collect(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn test_stream_dictionary_values() {
+        let request = request(&["a", "b", "c"]);
+        let mut producer = Producer::default();
+        let mut consumer = Consumer::default().with_stats(true);
+        let mut dictionary_values = vec![];
+        for _ in 0..2 {
+            let mut batch = producer.produce_batches(&request).unwrap();
+            let _ = consumer.consume_batches(&mut batch).unwrap();
+            let stats = consumer.last_batch_stats().unwrap();
+            let metrics = &stats.payloads[&ArrowPayloadType::UnivariateMetrics];
+            dictionary_values.push(metrics.dictionary_values);
+        }
+        // names are only new to the first batch of the stream.
+        assert!(dictionary_values[0] >= 3);
+        assert_eq!(0, dictionary_values[1]);
+    }
+
+    #[test]
+    fn test_dictionary_hit_ratio() {
+        let names: DictionaryArray<UInt8Type> =
+            vec![Some("a"), Some("b"), Some("a"), None, Some("a")]
+                .into_iter()
+                .collect();
+        let record =
+            RecordBatch::try_from_iter(vec![("name", Arc::new(names) as ArrayRef)]).unwrap();
+        let mut dictionaries = StreamDictionaries::default();
+        let mut stats = BatchStats::new(0);
+        stats.add_payload(
+            ArrowPayloadType::UnivariateMetrics,
+            100,
+            &record,
+            true,
+            Some(&mut dictionaries),
+        );
+
+        let metrics = &stats.payloads[&ArrowPayloadType::UnivariateMetrics];
+        assert_eq!(4, metrics.dictionary_keys);
+        assert_eq!(2, metrics.dictionary_values);
+        assert_eq!(Some(0.5), metrics.dictionary_hit_ratio());
+        assert_eq!(1, stats.schema_resets());
+        assert_eq!(None, stats.compression_ratio());
+
+        // a delta extends the dictionary of the stream, only its new value is counted.
+        let keys = UInt8Array::from(vec![0, 1, 2, 2]);
+        let values = Arc::new(StringArray::from(vec!["a", "b", "c"]));
+        let names = DictionaryArray::new(keys, values);
+        let record =
+            RecordBatch::try_from_iter(vec![("name", Arc::new(names) as ArrayRef)]).unwrap();
+        let mut stats = BatchStats::new(1);
+        stats.add_payload(
+            ArrowPayloadType::UnivariateMetrics,
+            100,
+            &record,
+            false,
+            Some(&mut dictionaries),
+        );
+
+        let metrics = &stats.payloads[&ArrowPayloadType::UnivariateMetrics];
+        assert_eq!(4, metrics.dictionary_keys);
+        assert_eq!(1, metrics.dictionary_values);
+        assert_eq!(Some(0.75), metrics.dictionary_hit_ratio());
+    }
+}
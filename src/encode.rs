@@ -0,0 +1,38 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub(crate) mod adaptive;
+pub(crate) mod attributes;
+mod data_points;
+pub mod encoder;
+mod exemplar;
+pub mod metric;
+pub mod sort;
+
+use crate::error;
+use snafu::OptionExt;
+
+/// Returns the value of an id counter and increments it, failing once ids of type `T` are
+/// exhausted.
+pub(crate) fn next_id<T: TryFrom<usize>>(
+    counter: &mut usize,
+    name: &'static str,
+) -> error::Result<T> {
+    let id = T::try_from(*counter)
+        .ok()
+        .context(error::TooManyItemsSnafu {
+            name,
+            max: *counter,
+        })?;
+    *counter += 1;
+    Ok(id)
+}
@@ -0,0 +1,279 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::error;
+use crate::schema::consts;
+use arrow::array::{Array, ArrayRef, AsArray, RecordBatch, RecordBatchOptions, StructArray};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Field, FieldRef, Fields, Schema};
+use snafu::ResultExt;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Columns left out of a record when all of their values are null, as decoders treat them as
+/// optional.
+const OPTIONAL_COLUMNS: &[&str] = &[
+    consts::AGGREGATION_TEMPORALITY,
+    consts::IS_MONOTONIC,
+    consts::ATTRIBUTE_STR,
+    consts::ATTRIBUTE_INT,
+    consts::ATTRIBUTE_DOUBLE,
+    consts::ATTRIBUTE_BOOL,
+    consts::ATTRIBUTE_BYTES,
+    consts::ATTRIBUTE_SER,
+    consts::INT_VALUE,
+    consts::DOUBLE_VALUE,
+    consts::HISTOGRAM_SUM,
+    consts::HISTOGRAM_MIN,
+    consts::HISTOGRAM_MAX,
+    consts::SPAN_ID,
+    consts::TRACE_ID,
+];
+
+/// Encoding of a string column, from the most to the least compact.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+enum StringEncoding {
+    #[default]
+    Dictionary8,
+    Dictionary16,
+    Plain,
+}
+
+impl StringEncoding {
+    fn for_cardinality(cardinality: usize) -> Self {
+        if cardinality <= u8::MAX as usize + 1 {
+            StringEncoding::Dictionary8
+        } else if cardinality <= u16::MAX as usize + 1 {
+            StringEncoding::Dictionary16
+        } else {
+            StringEncoding::Plain
+        }
+    }
+
+    fn data_type(self) -> DataType {
+        let key = match self {
+            StringEncoding::Dictionary8 => DataType::UInt8,
+            StringEncoding::Dictionary16 => DataType::UInt16,
+            StringEncoding::Plain => return DataType::Utf8,
+        };
+        DataType::Dictionary(Box::new(key), Box::new(DataType::Utf8))
+    }
+}
+
+/// Adapts the schema of the records of one payload type the way the Go encoder does: string
+/// columns start as dictionaries with `UInt8` keys, move to `UInt16` keys and finally to plain
+/// strings when a batch overflows the keys, and all-null optional columns are omitted.
+///
+/// Encodings only move forward for the lifetime of the stream, so that a schema change, and
+/// thus a new `schema_id`, only happens on overflow or when optional columns come and go.
+#[derive(Default)]
+pub(crate) struct AdaptiveSchema {
+    /// Encoding of every string column seen so far, by path of the column in the record.
+    string_encodings: HashMap<String, StringEncoding>,
+}
+
+impl AdaptiveSchema {
+    pub fn adapt(&mut self, record: &RecordBatch) -> error::Result<RecordBatch> {
+        let schema = record.schema();
+        let mut next_dict_id = 0;
+        let mut fields = Vec::with_capacity(record.num_columns());
+        let mut columns = Vec::with_capacity(record.num_columns());
+        for (field, column) in schema.fields().iter().zip(record.columns()) {
+            if field.is_nullable()
+                && column.null_count() == column.len()
+                && OPTIONAL_COLUMNS.contains(&field.name().as_str())
+            {
+                continue;
+            }
+            let (field, column) =
+                self.adapt_column(field.name().clone(), field, column, &mut next_dict_id)?;
+            fields.push(field);
+            columns.push(column);
+        }
+        let schema = Schema::new_with_metadata(fields, schema.metadata().clone());
+        let options = RecordBatchOptions::new().with_row_count(Some(record.num_rows()));
+        RecordBatch::try_new_with_options(Arc::new(schema), columns, &options)
+            .context(error::BuildRecordBatchSnafu)
+    }
+
+    fn adapt_column(
+        &mut self,
+        path: String,
+        field: &FieldRef,
+        column: &ArrayRef,
+        next_dict_id: &mut i64,
+    ) -> error::Result<(FieldRef, ArrayRef)> {
+        match field.data_type() {
+            DataType::Utf8 => {
+                let encoding = self.string_encodings.entry(path).or_default();
+                *encoding = (*encoding).max(StringEncoding::for_cardinality(cardinality(column)));
+                if *encoding == StringEncoding::Plain {
+                    return Ok((field.clone(), column.clone()));
+                }
+                let data_type = encoding.data_type();
+                let column = cast(column, &data_type).context(error::BuildRecordBatchSnafu)?;
+                // dictionary ids are written as is, so they must be unique within the record.
+                let field = Field::new_dict(
+                    field.name(),
+                    data_type,
+                    field.is_nullable(),
+                    *next_dict_id,
+                    false,
+                )
+                .with_metadata(field.metadata().clone());
+                *next_dict_id += 1;
+                Ok((Arc::new(field), column))
+            }
+            DataType::Struct(_) => {
+                let array = column.as_struct();
+                let mut fields = Vec::with_capacity(array.num_columns());
+                let mut columns = Vec::with_capacity(array.num_columns());
+                for (child_field, child) in array.fields().iter().zip(array.columns()) {
+                    let child_path = format!("{path}.{}", child_field.name());
+                    let (child_field, child) =
+                        self.adapt_column(child_path, child_field, child, next_dict_id)?;
+                    fields.push(child_field);
+                    columns.push(child);
+                }
+                let fields = Fields::from(fields);
+                let array = StructArray::try_new(fields.clone(), columns, array.nulls().cloned())
+                    .context(error::BuildRecordBatchSnafu)?;
+                let field = Field::new(field.name(), DataType::Struct(fields), field.is_nullable())
+                    .with_metadata(field.metadata().clone());
+                Ok((Arc::new(field), Arc::new(array)))
+            }
+            _ => Ok((field.clone(), column.clone())),
+        }
+    }
+}
+
+/// Number of distinct non-null values of a string column, counted up to the first value that
+/// doesn't fit in `UInt16` dictionary keys.
+fn cardinality(column: &ArrayRef) -> usize {
+    let mut values = HashSet::new();
+    for value in column.as_string::<i32>().iter().flatten() {
+        if values.insert(value) && values.len() > u16::MAX as usize + 1 {
+            break;
+        }
+    }
+    values.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compare::metrics_eq;
+    use crate::decode::decoder::Consumer;
+    use crate::encode::adaptive::AdaptiveSchema;
+    use crate::encode::encoder::Producer;
+    use crate::opentelemetry::ArrowPayloadType;
+    use crate::schema::consts;
+    use arrow::array::{ArrayRef, Int32Array, RecordBatch, StringArray};
+    use arrow::datatypes::DataType;
+    use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+    use opentelemetry_proto::tonic::common::v1::{any_value, AnyValue, KeyValue};
+    use opentelemetry_proto::tonic::metrics::v1::{
+        metric, number_data_point, Gauge, Metric, NumberDataPoint, ResourceMetrics, ScopeMetrics,
+    };
+    use std::sync::Arc;
+
+    fn record(cardinality: usize) -> RecordBatch {
+        let names: StringArray = (0..cardinality).map(|i| Some(format!("m{i}"))).collect();
+        let temporality = Int32Array::new_null(cardinality);
+        RecordBatch::try_from_iter_with_nullable(vec![
+            (consts::NAME, Arc::new(names) as ArrayRef, false),
+            (
+                consts::AGGREGATION_TEMPORALITY,
+                Arc::new(temporality) as ArrayRef,
+                true,
+            ),
+        ])
+        .unwrap()
+    }
+
+    fn dictionary(key: DataType) -> DataType {
+        DataType::Dictionary(Box::new(key), Box::new(DataType::Utf8))
+    }
+
+    #[test]
+    fn test_adapt() {
+        let mut adaptive = AdaptiveSchema::default();
+        let cases = [
+            (3, dictionary(DataType::UInt8)),
+            (300, dictionary(DataType::UInt16)),
+            // encodings never move back
+            (3, dictionary(DataType::UInt16)),
+            (70_000, DataType::Utf8),
+            (3, DataType::Utf8),
+        ];
+        for (cardinality, expected) in cases {
+            let adapted = adaptive.adapt(&record(cardinality)).unwrap();
+            let schema = adapted.schema();
+            assert_eq!(schema.fields().len(), 1);
+            assert_eq!(schema.field(0).data_type(), &expected);
+            assert_eq!(adapted.num_rows(), cardinality);
+        }
+    }
+
+    fn request(data_points: usize) -> ExportMetricsServiceRequest {
+        let data_points = (0..data_points)
+            .map(|i| NumberDataPoint {
+                attributes: vec![KeyValue {
+                    key: "key".to_string(),
+                    value: Some(AnyValue {
+                        value: Some(any_value::Value::StringValue(format!("value{i}"))),
+                    }),
+                }],
+                time_unix_nano: i as u64,
+                value: Some(number_data_point::Value::AsInt(i as i64)),
+                ..Default::default()
+            })
+            .collect();
+        ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                resource: Some(Default::default()),
+                scope_metrics: vec![ScopeMetrics {
+                    scope: Some(Default::default()),
+                    metrics: vec![Metric {
+                        name: "gauge".to_string(),
+                        data: Some(metric::Data::Gauge(Gauge { data_points })),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn test_dictionary_overflow_round_trip() {
+        let mut producer = Producer::default();
+        let mut consumer = Consumer::default();
+        let mut schema_ids = vec![];
+        for data_points in [3, 300, 70_000, 3] {
+            let request = request(data_points);
+            let mut batch = producer.produce_batches(&request).unwrap();
+            let payload = batch
+                .arrow_payloads
+                .iter()
+                .find(|p| p.r#type == ArrowPayloadType::NumberDpAttrs as i32)
+                .unwrap();
+            schema_ids.push(payload.schema_id.clone());
+            let decoded = consumer.consume_batches(&mut batch).unwrap();
+            assert!(metrics_eq(&decoded, &request).unwrap());
+        }
+        // a new schema on each overflow, none once the column is plain.
+        assert_ne!(schema_ids[0], schema_ids[1]);
+        assert_ne!(schema_ids[1], schema_ids[2]);
+        assert_eq!(schema_ids[2], schema_ids[3]);
+    }
+}
@@ -0,0 +1,330 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::encode::sort::AttributeOrder;
+use crate::error;
+use crate::otlp::attributes::cbor;
+use crate::otlp::attributes::parent_id::ParentId;
+use crate::otlp::attributes::store::AttributeValueType;
+use crate::schema::consts;
+use arrow::array::{
+    Array, ArrayRef, BinaryBuilder, BooleanBuilder, Float64Builder, Int64Builder, RecordBatch,
+    StringBuilder, UInt8Array,
+};
+use arrow::datatypes::{DataType, Field, Schema};
+use opentelemetry_proto::tonic::common::v1::any_value::Value;
+use opentelemetry_proto::tonic::common::v1::{AnyValue, KeyValue};
+use snafu::ResultExt;
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+pub(crate) type Attributes16Builder = AttributesBuilder<u16>;
+pub(crate) type Attributes32Builder = AttributesBuilder<u32>;
+
+/// An attribute appended to the builder, written once rows are sorted.
+struct AttributeRow<T> {
+    parent_id: T,
+    key: String,
+    value_type: AttributeValueType,
+    /// The value as the decoder sees it, i.e. after deserialization for serialized values.
+    value: Value,
+    serialized: Option<Vec<u8>>,
+    /// Serialized value with negative zeros made positive, see [AttributeRow::cmp_value].
+    sort_key: Option<Vec<u8>>,
+}
+
+impl<T: Ord> AttributeRow<T> {
+    /// Orders values so that values equal for the decoder, which compares them with `==`, are
+    /// next to each other: -0.0 and 0.0 are ordered as equal, and NaNs are never equal anyway.
+    fn cmp_value(&self, other: &Self) -> Ordering {
+        (self.value_type as u8)
+            .cmp(&(other.value_type as u8))
+            .then_with(|| match (&self.value, &other.value) {
+                (Value::StringValue(a), Value::StringValue(b)) => a.cmp(b),
+                (Value::IntValue(a), Value::IntValue(b)) => a.cmp(b),
+                (Value::DoubleValue(a), Value::DoubleValue(b)) => {
+                    positive_zero(*a).total_cmp(&positive_zero(*b))
+                }
+                (Value::BoolValue(a), Value::BoolValue(b)) => a.cmp(b),
+                (Value::BytesValue(a), Value::BytesValue(b)) => a.cmp(b),
+                _ => self.sort_key.cmp(&other.sort_key),
+            })
+    }
+
+    fn cmp_by(&self, other: &Self, order: AttributeOrder) -> Ordering {
+        match order {
+            AttributeOrder::KeyValueParent => self
+                .key
+                .cmp(&other.key)
+                .then_with(|| self.cmp_value(other))
+                .then_with(|| self.parent_id.cmp(&other.parent_id)),
+            AttributeOrder::ParentKey => self
+                .parent_id
+                .cmp(&other.parent_id)
+                .then_with(|| self.key.cmp(&other.key)),
+            AttributeOrder::Insertion => Ordering::Equal,
+        }
+    }
+}
+
+/// Builds an attributes record, the counterpart of [crate::otlp::attributes::store::AttributeStore].
+///
+/// Parent ids use [crate::otlp::attributes::parent_id::ParentIdEncoding::ParentIdDeltaGroupEncoding],
+/// computed once rows are sorted in the [AttributeOrder] of the builder.
+pub(crate) struct AttributesBuilder<T> {
+    order: AttributeOrder,
+    rows: Vec<AttributeRow<T>>,
+
+    prev_parent_id: T,
+    prev_key: Option<String>,
+    prev_value: Option<Value>,
+}
+
+impl<T> Default for AttributesBuilder<T>
+where
+    T: ParentId,
+{
+    fn default() -> Self {
+        Self::new(AttributeOrder::default())
+    }
+}
+
+impl<T> AttributesBuilder<T>
+where
+    T: ParentId,
+{
+    pub fn new(order: AttributeOrder) -> Self {
+        Self {
+            order,
+            rows: vec![],
+            prev_parent_id: T::default(),
+            prev_key: None,
+            prev_value: None,
+        }
+    }
+}
+
+impl<T> AttributesBuilder<T>
+where
+    T: ParentId + Ord,
+    T::Array: From<Vec<T>> + Array,
+{
+    /// Appends the attributes of given parent. Empty values are skipped since they are
+    /// dropped by the decoder anyway.
+    pub fn append(&mut self, parent_id: T, attributes: &[KeyValue]) -> error::Result<()> {
+        for kv in attributes {
+            let Some(value) = kv.value.as_ref().and_then(|v| v.value.as_ref()) else {
+                continue;
+            };
+            let value_type = match value {
+                Value::StringValue(_) => AttributeValueType::Str,
+                Value::IntValue(_) => AttributeValueType::Int,
+                Value::DoubleValue(_) => AttributeValueType::Double,
+                Value::BoolValue(_) => AttributeValueType::Bool,
+                Value::BytesValue(_) => AttributeValueType::Bytes,
+                Value::ArrayValue(_) => AttributeValueType::Slice,
+                Value::KvlistValue(_) => AttributeValueType::Map,
+            };
+            // Serialized values are compared as the decoder sees them after deserialization.
+            let (value, serialized, sort_key) = match value {
+                Value::ArrayValue(_) | Value::KvlistValue(_) => {
+                    let mut value = kv.value.clone().unwrap();
+                    let bytes = cbor::encode(&value);
+                    positive_zeros(&mut value);
+                    let sort_key = cbor::encode(&value);
+                    (
+                        cbor::decode(&bytes)?.value.unwrap(),
+                        Some(bytes),
+                        Some(sort_key),
+                    )
+                }
+                _ => (value.clone(), None, None),
+            };
+            self.rows.push(AttributeRow {
+                parent_id,
+                key: kv.key.clone(),
+                value_type,
+                value,
+                serialized,
+                sort_key,
+            });
+        }
+        Ok(())
+    }
+
+    /// Mirrors [crate::otlp::attributes::decoder::AttrsParentIdDecoder::decode].
+    fn encode_parent_id(&mut self, parent_id: T, key: &str, value: &Value) -> T {
+        if self.prev_key.as_deref() == Some(key) && self.prev_value.as_ref() == Some(value) {
+            let delta = parent_id - self.prev_parent_id;
+            self.prev_parent_id = parent_id;
+            delta
+        } else {
+            self.prev_key = Some(key.to_string());
+            self.prev_value = Some(value.clone());
+            self.prev_parent_id = parent_id;
+            parent_id
+        }
+    }
+
+    /// Returns the attributes record, or `None` if no attribute was appended.
+    pub fn finish(mut self) -> error::Result<Option<RecordBatch>> {
+        if self.rows.is_empty() {
+            return Ok(None);
+        }
+        let mut rows = std::mem::take(&mut self.rows);
+        let order = self.order;
+        rows.sort_by(|a, b| a.cmp_by(b, order));
+
+        let mut parent_id = Vec::with_capacity(rows.len());
+        let mut key = StringBuilder::new();
+        let mut value_type = Vec::with_capacity(rows.len());
+        let mut str = StringBuilder::new();
+        let mut int = Int64Builder::new();
+        let mut double = Float64Builder::new();
+        let mut bool = BooleanBuilder::new();
+        let mut bytes = BinaryBuilder::new();
+        let mut ser = BinaryBuilder::new();
+        for row in rows {
+            parent_id.push(self.encode_parent_id(row.parent_id, &row.key, &row.value));
+            key.append_value(&row.key);
+            value_type.push(row.value_type as u8);
+            match &row.value {
+                Value::StringValue(v) => str.append_value(v),
+                _ => str.append_null(),
+            }
+            match &row.value {
+                Value::IntValue(v) => int.append_value(*v),
+                _ => int.append_null(),
+            }
+            match &row.value {
+                Value::DoubleValue(v) => double.append_value(*v),
+                _ => double.append_null(),
+            }
+            match &row.value {
+                Value::BoolValue(v) => bool.append_value(*v),
+                _ => bool.append_null(),
+            }
+            match &row.value {
+                Value::BytesValue(v) => bytes.append_value(v),
+                _ => bytes.append_null(),
+            }
+            ser.append_option(row.serialized);
+        }
+
+        let schema = Schema::new(vec![
+            Field::new(consts::PARENT_ID, T::arrow_data_type(), false),
+            Field::new(consts::ATTRIBUTE_KEY, DataType::Utf8, false),
+            Field::new(consts::ATTRIBUTE_TYPE, DataType::UInt8, false),
+            Field::new(consts::ATTRIBUTE_STR, DataType::Utf8, true),
+            Field::new(consts::ATTRIBUTE_INT, DataType::Int64, true),
+            Field::new(consts::ATTRIBUTE_DOUBLE, DataType::Float64, true),
+            Field::new(consts::ATTRIBUTE_BOOL, DataType::Boolean, true),
+            Field::new(consts::ATTRIBUTE_BYTES, DataType::Binary, true),
+            Field::new(consts::ATTRIBUTE_SER, DataType::Binary, true),
+        ]);
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(T::Array::from(parent_id)),
+            Arc::new(key.finish()),
+            Arc::new(UInt8Array::from(value_type)),
+            Arc::new(str.finish()),
+            Arc::new(int.finish()),
+            Arc::new(double.finish()),
+            Arc::new(bool.finish()),
+            Arc::new(bytes.finish()),
+            Arc::new(ser.finish()),
+        ];
+        RecordBatch::try_new(Arc::new(schema), columns)
+            .context(error::BuildRecordBatchSnafu)
+            .map(Some)
+    }
+}
+
+fn positive_zero(v: f64) -> f64 {
+    if v == 0.0 {
+        0.0
+    } else {
+        v
+    }
+}
+
+fn positive_zeros(value: &mut AnyValue) {
+    match &mut value.value {
+        Some(Value::DoubleValue(v)) => *v = positive_zero(*v),
+        Some(Value::ArrayValue(array)) => array.values.iter_mut().for_each(positive_zeros),
+        Some(Value::KvlistValue(list)) => list
+            .values
+            .iter_mut()
+            .filter_map(|kv| kv.value.as_mut())
+            .for_each(positive_zeros),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encode::attributes::Attributes16Builder;
+    use crate::encode::sort::AttributeOrder;
+    use crate::otlp::attributes::store::for_each_attribute;
+    use opentelemetry_proto::tonic::common::v1::any_value::Value;
+    use opentelemetry_proto::tonic::common::v1::{AnyValue, ArrayValue, KeyValue};
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    fn double() -> impl Strategy<Value = f64> {
+        prop_oneof![
+            Just(0.0),
+            Just(-0.0),
+            Just(f64::NAN),
+            Just(-f64::NAN),
+            Just(1.0),
+        ]
+    }
+
+    fn value() -> impl Strategy<Value = Value> {
+        prop_oneof![
+            double().prop_map(Value::DoubleValue),
+            vec(double(), 1..3).prop_map(|values| Value::ArrayValue(ArrayValue {
+                values: values
+                    .into_iter()
+                    .map(|v| AnyValue {
+                        value: Some(Value::DoubleValue(v)),
+                    })
+                    .collect(),
+            })),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn test_signed_zeros_and_nans(values in vec(value(), 1..16)) {
+            let mut builder = Attributes16Builder::new(AttributeOrder::KeyValueParent);
+            let mut expected = vec![];
+            for (parent_id, value) in values.into_iter().enumerate() {
+                let kv = KeyValue {
+                    key: "k".to_string(),
+                    value: Some(AnyValue { value: Some(value) }),
+                };
+                builder.append(parent_id as u16, &[kv.clone()]).unwrap();
+                expected.push(format!("{parent_id} {:?}", kv.value.unwrap().value.unwrap()));
+            }
+            let record = builder.finish().unwrap().unwrap();
+            let mut decoded = vec![];
+            for_each_attribute(&record, |parent_id: u16, _, value| {
+                decoded.push(format!("{parent_id} {value:?}"));
+            })
+            .unwrap();
+            decoded.sort();
+            expected.sort();
+            prop_assert_eq!(decoded, expected);
+        }
+    }
+}
@@ -0,0 +1,491 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::encode::attributes::Attributes32Builder;
+use crate::encode::exemplar::ExemplarsBuilder;
+use crate::encode::next_id;
+use crate::encode::sort::{sort_data_points, DataPointOrder, SortStrategy};
+use crate::error;
+use crate::opentelemetry::ArrowPayloadType;
+use crate::schema::consts;
+use arrow::array::{
+    Array, ArrayRef, Float64Array, Float64Builder, Int32Array, Int64Builder, ListArray,
+    ListBuilder, RecordBatch, StructArray, TimestampNanosecondArray, UInt16Array, UInt32Array,
+    UInt64Array, UInt64Builder,
+};
+use arrow::buffer::OffsetBuffer;
+use arrow::datatypes::{DataType, Field, Fields};
+use opentelemetry_proto::tonic::metrics::v1::exponential_histogram_data_point::Buckets;
+use opentelemetry_proto::tonic::metrics::v1::number_data_point::Value;
+use opentelemetry_proto::tonic::metrics::v1::{
+    ExponentialHistogramDataPoint, HistogramDataPoint, NumberDataPoint, SummaryDataPoint,
+};
+use snafu::ResultExt;
+use std::sync::Arc;
+
+/// Columns shared by all data point records.
+#[derive(Default)]
+struct DataPointColumns {
+    id: Vec<u32>,
+    parent_id: Vec<u16>,
+    start_time_unix_nano: Vec<i64>,
+    time_unix_nano: Vec<i64>,
+    flags: Vec<u32>,
+
+    next_id: usize,
+    prev_parent_id: u16,
+}
+
+impl DataPointColumns {
+    /// Appends a data point of the metric with given id and returns the data point id.
+    fn append(
+        &mut self,
+        metric_id: u16,
+        start_time_unix_nano: u64,
+        time_unix_nano: u64,
+        flags: u32,
+    ) -> error::Result<u32> {
+        let id: u32 = next_id(&mut self.next_id, "data points")?;
+        // both ids are delta encoded.
+        self.id.push((id > 0) as u32);
+        self.parent_id.push(metric_id - self.prev_parent_id);
+        self.prev_parent_id = metric_id;
+        self.start_time_unix_nano.push(start_time_unix_nano as i64);
+        self.time_unix_nano.push(time_unix_nano as i64);
+        self.flags.push(flags);
+        Ok(id)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.id.is_empty()
+    }
+
+    /// Builds the record from the shared columns followed by `columns`.
+    fn finish(self, columns: Vec<(&str, ArrayRef)>) -> error::Result<RecordBatch> {
+        let shared: Vec<(&str, ArrayRef, bool)> = vec![
+            (consts::ID, Arc::new(UInt32Array::from(self.id)), true),
+            (
+                consts::PARENT_ID,
+                Arc::new(UInt16Array::from(self.parent_id)),
+                false,
+            ),
+            (
+                consts::START_TIME_UNIX_NANO,
+                Arc::new(TimestampNanosecondArray::from(self.start_time_unix_nano)),
+                true,
+            ),
+            (
+                consts::TIME_UNIX_NANO,
+                Arc::new(TimestampNanosecondArray::from(self.time_unix_nano)),
+                true,
+            ),
+            (consts::FLAGS, Arc::new(UInt32Array::from(self.flags)), true),
+        ];
+        RecordBatch::try_from_iter_with_nullable(
+            shared
+                .into_iter()
+                .chain(columns.into_iter().map(|(name, array)| (name, array, true))),
+        )
+        .context(error::BuildRecordBatchSnafu)
+    }
+}
+
+/// Collects the non-empty records produced by a data point builder.
+fn collect_records(
+    records: impl IntoIterator<Item = (ArrowPayloadType, Option<RecordBatch>)>,
+) -> Vec<(ArrowPayloadType, RecordBatch)> {
+    records
+        .into_iter()
+        .filter_map(|(payload_type, record)| record.map(|r| (payload_type, r)))
+        .collect()
+}
+
+#[derive(Default)]
+pub(crate) struct NumberDataPointsBuilder {
+    order: DataPointOrder,
+    columns: DataPointColumns,
+    int_value: Int64Builder,
+    double_value: Float64Builder,
+    attributes: Attributes32Builder,
+    exemplars: ExemplarsBuilder,
+}
+
+impl NumberDataPointsBuilder {
+    pub fn new(sort: SortStrategy) -> Self {
+        Self {
+            order: sort.data_points,
+            attributes: Attributes32Builder::new(sort.attributes),
+            exemplars: ExemplarsBuilder::new(sort.attributes),
+            ..Default::default()
+        }
+    }
+
+    pub fn append(&mut self, metric_id: u16, data_points: &[NumberDataPoint]) -> error::Result<()> {
+        let data_points = sort_data_points(data_points, self.order, |dp| {
+            (dp.start_time_unix_nano, dp.time_unix_nano)
+        });
+        for dp in data_points {
+            let id = self.columns.append(
+                metric_id,
+                dp.start_time_unix_nano,
+                dp.time_unix_nano,
+                dp.flags,
+            )?;
+            match dp.value {
+                Some(Value::AsInt(v)) => {
+                    self.int_value.append_value(v);
+                    self.double_value.append_null();
+                }
+                Some(Value::AsDouble(v)) => {
+                    self.int_value.append_null();
+                    self.double_value.append_value(v);
+                }
+                None => {
+                    self.int_value.append_null();
+                    self.double_value.append_null();
+                }
+            }
+            self.attributes.append(id, &dp.attributes)?;
+            self.exemplars.append(id, &dp.exemplars)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> error::Result<Vec<(ArrowPayloadType, RecordBatch)>> {
+        if self.columns.is_empty() {
+            return Ok(vec![]);
+        }
+        let record = self.columns.finish(vec![
+            (consts::INT_VALUE, Arc::new(self.int_value.finish())),
+            (consts::DOUBLE_VALUE, Arc::new(self.double_value.finish())),
+        ])?;
+        let (exemplars, exemplar_attrs) = self.exemplars.finish()?;
+        Ok(collect_records([
+            (ArrowPayloadType::NumberDataPoints, Some(record)),
+            (ArrowPayloadType::NumberDpAttrs, self.attributes.finish()?),
+            (ArrowPayloadType::NumberDpExemplars, exemplars),
+            (ArrowPayloadType::NumberDpExemplarAttrs, exemplar_attrs),
+        ]))
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct HistogramDataPointsBuilder {
+    order: DataPointOrder,
+    columns: DataPointColumns,
+    count: Vec<u64>,
+    sum: Float64Builder,
+    bucket_counts: ListBuilder<UInt64Builder>,
+    explicit_bounds: ListBuilder<Float64Builder>,
+    min: Float64Builder,
+    max: Float64Builder,
+    attributes: Attributes32Builder,
+    exemplars: ExemplarsBuilder,
+}
+
+impl HistogramDataPointsBuilder {
+    pub fn new(sort: SortStrategy) -> Self {
+        Self {
+            order: sort.data_points,
+            attributes: Attributes32Builder::new(sort.attributes),
+            exemplars: ExemplarsBuilder::new(sort.attributes),
+            ..Default::default()
+        }
+    }
+
+    pub fn append(
+        &mut self,
+        metric_id: u16,
+        data_points: &[HistogramDataPoint],
+    ) -> error::Result<()> {
+        let data_points = sort_data_points(data_points, self.order, |dp| {
+            (dp.start_time_unix_nano, dp.time_unix_nano)
+        });
+        for dp in data_points {
+            let id = self.columns.append(
+                metric_id,
+                dp.start_time_unix_nano,
+                dp.time_unix_nano,
+                dp.flags,
+            )?;
+            self.count.push(dp.count);
+            self.sum.append_option(dp.sum);
+            self.bucket_counts
+                .append_value(dp.bucket_counts.iter().copied().map(Some));
+            self.explicit_bounds
+                .append_value(dp.explicit_bounds.iter().copied().map(Some));
+            self.min.append_option(dp.min);
+            self.max.append_option(dp.max);
+            self.attributes.append(id, &dp.attributes)?;
+            self.exemplars.append(id, &dp.exemplars)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> error::Result<Vec<(ArrowPayloadType, RecordBatch)>> {
+        if self.columns.is_empty() {
+            return Ok(vec![]);
+        }
+        let record = self.columns.finish(vec![
+            (
+                consts::HISTOGRAM_COUNT,
+                Arc::new(UInt64Array::from(self.count)),
+            ),
+            (consts::HISTOGRAM_SUM, Arc::new(self.sum.finish())),
+            (
+                consts::HISTOGRAM_BUCKET_COUNTS,
+                Arc::new(self.bucket_counts.finish()),
+            ),
+            (
+                consts::HISTOGRAM_EXPLICIT_BOUNDS,
+                Arc::new(self.explicit_bounds.finish()),
+            ),
+            (consts::HISTOGRAM_MIN, Arc::new(self.min.finish())),
+            (consts::HISTOGRAM_MAX, Arc::new(self.max.finish())),
+        ])?;
+        let (exemplars, exemplar_attrs) = self.exemplars.finish()?;
+        Ok(collect_records([
+            (ArrowPayloadType::HistogramDataPoints, Some(record)),
+            (
+                ArrowPayloadType::HistogramDpAttrs,
+                self.attributes.finish()?,
+            ),
+            (ArrowPayloadType::HistogramDpExemplars, exemplars),
+            (ArrowPayloadType::HistogramDpExemplarAttrs, exemplar_attrs),
+        ]))
+    }
+}
+
+/// Positive or negative buckets of exponential histograms.
+#[derive(Default)]
+struct BucketsBuilder {
+    offset: Vec<i32>,
+    bucket_counts: ListBuilder<UInt64Builder>,
+}
+
+impl BucketsBuilder {
+    fn append(&mut self, buckets: Option<&Buckets>) {
+        let buckets = buckets.cloned().unwrap_or_default();
+        self.offset.push(buckets.offset);
+        self.bucket_counts
+            .append_value(buckets.bucket_counts.into_iter().map(Some));
+    }
+
+    fn finish(mut self) -> ArrayRef {
+        let bucket_counts = self.bucket_counts.finish();
+        let fields = Fields::from(vec![
+            Field::new(consts::EXP_HISTOGRAM_OFFSET, DataType::Int32, true),
+            Field::new(
+                consts::EXP_HISTOGRAM_BUCKET_COUNTS,
+                bucket_counts.data_type().clone(),
+                true,
+            ),
+        ]);
+        Arc::new(StructArray::new(
+            fields,
+            vec![
+                Arc::new(Int32Array::from(self.offset)),
+                Arc::new(bucket_counts),
+            ],
+            None,
+        ))
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct ExpHistogramDataPointsBuilder {
+    order: DataPointOrder,
+    columns: DataPointColumns,
+    count: Vec<u64>,
+    sum: Float64Builder,
+    scale: Vec<i32>,
+    zero_count: Vec<u64>,
+    positive: BucketsBuilder,
+    negative: BucketsBuilder,
+    min: Float64Builder,
+    max: Float64Builder,
+    attributes: Attributes32Builder,
+    exemplars: ExemplarsBuilder,
+}
+
+impl ExpHistogramDataPointsBuilder {
+    pub fn new(sort: SortStrategy) -> Self {
+        Self {
+            order: sort.data_points,
+            attributes: Attributes32Builder::new(sort.attributes),
+            exemplars: ExemplarsBuilder::new(sort.attributes),
+            ..Default::default()
+        }
+    }
+
+    pub fn append(
+        &mut self,
+        metric_id: u16,
+        data_points: &[ExponentialHistogramDataPoint],
+    ) -> error::Result<()> {
+        let data_points = sort_data_points(data_points, self.order, |dp| {
+            (dp.start_time_unix_nano, dp.time_unix_nano)
+        });
+        for dp in data_points {
+            let id = self.columns.append(
+                metric_id,
+                dp.start_time_unix_nano,
+                dp.time_unix_nano,
+                dp.flags,
+            )?;
+            self.count.push(dp.count);
+            self.sum.append_option(dp.sum);
+            self.scale.push(dp.scale);
+            self.zero_count.push(dp.zero_count);
+            self.positive.append(dp.positive.as_ref());
+            self.negative.append(dp.negative.as_ref());
+            self.min.append_option(dp.min);
+            self.max.append_option(dp.max);
+            self.attributes.append(id, &dp.attributes)?;
+            self.exemplars.append(id, &dp.exemplars)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> error::Result<Vec<(ArrowPayloadType, RecordBatch)>> {
+        if self.columns.is_empty() {
+            return Ok(vec![]);
+        }
+        let record = self.columns.finish(vec![
+            (
+                consts::HISTOGRAM_COUNT,
+                Arc::new(UInt64Array::from(self.count)),
+            ),
+            (consts::HISTOGRAM_SUM, Arc::new(self.sum.finish())),
+            (
+                consts::EXP_HISTOGRAM_SCALE,
+                Arc::new(Int32Array::from(self.scale)),
+            ),
+            (
+                consts::EXP_HISTOGRAM_ZERO_COUNT,
+                Arc::new(UInt64Array::from(self.zero_count)),
+            ),
+            (consts::EXP_HISTOGRAM_POSITIVE, self.positive.finish()),
+            (consts::EXP_HISTOGRAM_NEGATIVE, self.negative.finish()),
+            (consts::HISTOGRAM_MIN, Arc::new(self.min.finish())),
+            (consts::HISTOGRAM_MAX, Arc::new(self.max.finish())),
+        ])?;
+        let (exemplars, exemplar_attrs) = self.exemplars.finish()?;
+        Ok(collect_records([
+            (ArrowPayloadType::ExpHistogramDataPoints, Some(record)),
+            (
+                ArrowPayloadType::ExpHistogramDpAttrs,
+                self.attributes.finish()?,
+            ),
+            (ArrowPayloadType::ExpHistogramDpExemplars, exemplars),
+            (
+                ArrowPayloadType::ExpHistogramDpExemplarAttrs,
+                exemplar_attrs,
+            ),
+        ]))
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct SummaryDataPointsBuilder {
+    order: DataPointOrder,
+    columns: DataPointColumns,
+    count: Vec<u64>,
+    sum: Vec<f64>,
+    quantile_offsets: Vec<i32>,
+    quantile: Vec<f64>,
+    quantile_value: Vec<f64>,
+    attributes: Attributes32Builder,
+}
+
+impl SummaryDataPointsBuilder {
+    pub fn new(sort: SortStrategy) -> Self {
+        Self {
+            order: sort.data_points,
+            attributes: Attributes32Builder::new(sort.attributes),
+            ..Default::default()
+        }
+    }
+
+    pub fn append(
+        &mut self,
+        metric_id: u16,
+        data_points: &[SummaryDataPoint],
+    ) -> error::Result<()> {
+        let data_points = sort_data_points(data_points, self.order, |dp| {
+            (dp.start_time_unix_nano, dp.time_unix_nano)
+        });
+        for dp in data_points {
+            let id = self.columns.append(
+                metric_id,
+                dp.start_time_unix_nano,
+                dp.time_unix_nano,
+                dp.flags,
+            )?;
+            self.count.push(dp.count);
+            self.sum.push(dp.sum);
+            if self.quantile_offsets.is_empty() {
+                self.quantile_offsets.push(0);
+            }
+            for q in &dp.quantile_values {
+                self.quantile.push(q.quantile);
+                self.quantile_value.push(q.value);
+            }
+            self.quantile_offsets.push(self.quantile.len() as i32);
+            self.attributes.append(id, &dp.attributes)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> error::Result<Vec<(ArrowPayloadType, RecordBatch)>> {
+        if self.columns.is_empty() {
+            return Ok(vec![]);
+        }
+        let quantile_values = StructArray::from(vec![
+            (
+                Arc::new(Field::new(
+                    consts::SUMMARY_QUANTILE,
+                    DataType::Float64,
+                    true,
+                )),
+                Arc::new(Float64Array::from(self.quantile)) as ArrayRef,
+            ),
+            (
+                Arc::new(Field::new(consts::SUMMARY_VALUE, DataType::Float64, true)),
+                Arc::new(Float64Array::from(self.quantile_value)) as ArrayRef,
+            ),
+        ]);
+        let quantile = ListArray::try_new(
+            Arc::new(Field::new(
+                "item",
+                quantile_values.data_type().clone(),
+                true,
+            )),
+            OffsetBuffer::new(self.quantile_offsets.into()),
+            Arc::new(quantile_values),
+            None,
+        )
+        .context(error::BuildRecordBatchSnafu)?;
+        let record = self.columns.finish(vec![
+            (
+                consts::SUMMARY_COUNT,
+                Arc::new(UInt64Array::from(self.count)),
+            ),
+            (consts::SUMMARY_SUM, Arc::new(Float64Array::from(self.sum))),
+            (consts::SUMMARY_QUANTILE_VALUES, Arc::new(quantile)),
+        ])?;
+        Ok(collect_records([
+            (ArrowPayloadType::SummaryDataPoints, Some(record)),
+            (ArrowPayloadType::SummaryDpAttrs, self.attributes.finish()?),
+        ]))
+    }
+}
@@ -0,0 +1,342 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::decode::stats::{BatchStats, StreamDictionaries};
+use crate::encode::adaptive::AdaptiveSchema;
+use crate::encode::metric::encode_metrics_with;
+use crate::encode::sort::SortStrategy;
+use crate::error;
+use crate::hpack;
+use crate::opentelemetry::{ArrowPayload, ArrowPayloadType, BatchArrowRecords};
+use arrow::array::RecordBatch;
+use arrow::datatypes::SchemaRef;
+use arrow::ipc::writer::StreamWriter;
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use prost::Message;
+use snafu::ResultExt;
+use std::collections::HashMap;
+
+/// IPC stream of one payload type, the counterpart of [crate::decode::decoder::StreamConsumer].
+struct StreamProducer {
+    schema_id: String,
+    schema: SchemaRef,
+    stream_writer: StreamWriter<Vec<u8>>,
+    dictionaries: StreamDictionaries,
+}
+
+impl StreamProducer {
+    fn new(schema_id: String, schema: SchemaRef) -> error::Result<Self> {
+        let stream_writer =
+            StreamWriter::try_new(vec![], &schema).context(error::BuildStreamWriterSnafu)?;
+        Ok(Self {
+            schema_id,
+            schema,
+            stream_writer,
+            dictionaries: StreamDictionaries::default(),
+        })
+    }
+
+    /// Writes the record and returns the IPC messages written since the last call, including
+    /// the schema and dictionaries as needed.
+    fn write(&mut self, record: &RecordBatch) -> error::Result<Vec<u8>> {
+        self.stream_writer
+            .write(record)
+            .context(error::WriteRecordBatchSnafu)?;
+        Ok(std::mem::take(self.stream_writer.get_mut()))
+    }
+}
+
+/// Encodes OTLP requests into [BatchArrowRecords] of one OTAP stream, keeping one IPC stream
+/// per payload type alive across batches so that schemas and dictionaries are only sent when
+/// they change. Must be paired with one [crate::decode::decoder::Consumer] on the receiving side.
+#[derive(Default)]
+pub struct Producer {
+    next_batch_id: i64,
+    next_schema_id: u64,
+    stream_producers: HashMap<ArrowPayloadType, StreamProducer>,
+    adaptive_schemas: HashMap<ArrowPayloadType, AdaptiveSchema>,
+    last_batch_stats: Option<BatchStats>,
+    headers_encoder: hpack::Encoder,
+    sort: SortStrategy,
+}
+
+impl Producer {
+    /// Writes rows in given order, [SortStrategy::default] otherwise.
+    pub fn with_sort(mut self, sort: SortStrategy) -> Self {
+        self.sort = sort;
+        self
+    }
+
+    fn produce_bar(
+        &mut self,
+        records: Vec<(ArrowPayloadType, RecordBatch)>,
+        headers: &[(&str, &str)],
+    ) -> error::Result<BatchArrowRecords> {
+        let batch_id = self.next_batch_id;
+        let mut stats = BatchStats::new(batch_id);
+        self.last_batch_stats = None;
+
+        let mut arrow_payloads = Vec::with_capacity(records.len());
+        for (payload_type, record) in records {
+            // IPC streams can't change their schema, including field order, nullability and
+            // metadata.
+            let schema_changed = self
+                .stream_producers
+                .get(&payload_type)
+                .map(|p| p.schema != record.schema());
+            if schema_changed != Some(false) {
+                // a new schema starts a new IPC stream with a new schema id.
+                let schema_id = self.next_schema_id.to_string();
+                self.next_schema_id += 1;
+                let stream_producer = StreamProducer::new(schema_id, record.schema())?;
+                self.stream_producers.insert(payload_type, stream_producer);
+            }
+            // safety: inserted above if absent
+            let stream_producer = self.stream_producers.get_mut(&payload_type).unwrap();
+            let bytes = stream_producer.write(&record)?;
+            stats.add_payload(
+                payload_type,
+                bytes.len(),
+                &record,
+                schema_changed == Some(true),
+                Some(&mut stream_producer.dictionaries),
+            );
+            arrow_payloads.push(ArrowPayload {
+                schema_id: stream_producer.schema_id.clone(),
+                r#type: payload_type as i32,
+                record: bytes,
+            });
+        }
+
+        self.next_batch_id += 1;
+        self.last_batch_stats = Some(stats);
+        Ok(BatchArrowRecords {
+            batch_id,
+            arrow_payloads,
+            headers: self.headers_encoder.encode(headers),
+        })
+    }
+
+    /// Writes already encoded records as the next batch of the stream, without converting
+    /// from OTLP. The main record of the signal must come first. Records are written with
+    /// their schema as is.
+    pub fn produce_records(
+        &mut self,
+        records: Vec<(ArrowPayloadType, RecordBatch)>,
+    ) -> error::Result<BatchArrowRecords> {
+        self.produce_bar(records, &[])
+    }
+
+    /// Like [Producer::produce_records], also attaching HPACK-encoded headers to the batch.
+    pub fn produce_records_with_headers(
+        &mut self,
+        records: Vec<(ArrowPayloadType, RecordBatch)>,
+        headers: &[(&str, &str)],
+    ) -> error::Result<BatchArrowRecords> {
+        self.produce_bar(records, headers)
+    }
+
+    pub fn produce_batches(
+        &mut self,
+        request: &ExportMetricsServiceRequest,
+    ) -> error::Result<BatchArrowRecords> {
+        self.produce_batches_with_headers(request, &[])
+    }
+
+    /// Like [Producer::produce_batches], also attaching HPACK-encoded headers to the batch.
+    pub fn produce_batches_with_headers(
+        &mut self,
+        request: &ExportMetricsServiceRequest,
+        headers: &[(&str, &str)],
+    ) -> error::Result<BatchArrowRecords> {
+        let records = encode_metrics_with(request, self.sort)?
+            .into_iter()
+            .map(|(payload_type, record)| {
+                let adaptive = self.adaptive_schemas.entry(payload_type).or_default();
+                Ok((payload_type, adaptive.adapt(&record)?))
+            })
+            .collect::<error::Result<_>>()?;
+        let batch = self.produce_bar(records, headers)?;
+        if let Some(stats) = &mut self.last_batch_stats {
+            stats.otlp_bytes = Some(request.encoded_len());
+        }
+        Ok(batch)
+    }
+
+    /// Statistics of the last produced batch.
+    pub fn last_batch_stats(&self) -> Option<&BatchStats> {
+        self.last_batch_stats.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compare::metrics_eq;
+    use crate::decode::decoder::Consumer;
+    use crate::test_util::arb;
+    use arrow::array::{ArrayRef, Int64Array, StringArray};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use opentelemetry_proto::tonic::metrics::v1::metric::Data;
+    use opentelemetry_proto::tonic::metrics::v1::{ResourceMetrics, ScopeMetrics};
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use std::sync::Arc;
+
+    /// Normalizes a request the way the OTAP encoding does, since metrics records have one row
+    /// per metric: resources and scopes without any metric are dropped, and absent resources
+    /// and scopes become empty ones. Empty resources and scopes with metrics are kept as is.
+    fn normalize(request: &ExportMetricsServiceRequest) -> ExportMetricsServiceRequest {
+        let resource_metrics = request
+            .resource_metrics
+            .iter()
+            .filter_map(|rm| {
+                let scope_metrics: Vec<_> = rm
+                    .scope_metrics
+                    .iter()
+                    .filter(|sm| !sm.metrics.is_empty())
+                    .map(|sm| ScopeMetrics {
+                        scope: Some(sm.scope.clone().unwrap_or_default()),
+                        ..sm.clone()
+                    })
+                    .collect();
+                (!scope_metrics.is_empty()).then(|| ResourceMetrics {
+                    resource: Some(rm.resource.clone().unwrap_or_default()),
+                    scope_metrics,
+                    schema_url: rm.schema_url.clone(),
+                })
+            })
+            .collect();
+        ExportMetricsServiceRequest { resource_metrics }
+    }
+
+    /// Sorts data points the way [SortStrategy::default] does.
+    fn sort_data_points(request: &mut ExportMetricsServiceRequest) {
+        fn sort<D>(data_points: &mut [D], key: fn(&D) -> (u64, u64)) {
+            data_points.sort_by_key(key);
+        }
+        let metrics = request
+            .resource_metrics
+            .iter_mut()
+            .flat_map(|rm| &mut rm.scope_metrics)
+            .flat_map(|sm| &mut sm.metrics);
+        for metric in metrics {
+            match &mut metric.data {
+                Some(Data::Gauge(d)) => sort(&mut d.data_points, |dp| {
+                    (dp.start_time_unix_nano, dp.time_unix_nano)
+                }),
+                Some(Data::Sum(d)) => sort(&mut d.data_points, |dp| {
+                    (dp.start_time_unix_nano, dp.time_unix_nano)
+                }),
+                Some(Data::Histogram(d)) => sort(&mut d.data_points, |dp| {
+                    (dp.start_time_unix_nano, dp.time_unix_nano)
+                }),
+                Some(Data::ExponentialHistogram(d)) => sort(&mut d.data_points, |dp| {
+                    (dp.start_time_unix_nano, dp.time_unix_nano)
+                }),
+                Some(Data::Summary(d)) => sort(&mut d.data_points, |dp| {
+                    (dp.start_time_unix_nano, dp.time_unix_nano)
+                }),
+                None => {}
+            }
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn test_round_trip(requests in vec(arb::metrics_request(), 1..4)) {
+            let mut producer = Producer::default().with_sort(SortStrategy::UNSORTED);
+            let mut consumer = Consumer::default();
+            let mut sorted_producer = Producer::default();
+            let mut sorted_consumer = Consumer::default();
+            for request in &requests {
+                let expected = normalize(request);
+
+                // the default order only changes the order of attributes and data points.
+                let mut batch = sorted_producer.produce_batches(request).unwrap();
+                let decoded = sorted_consumer.consume_batches(&mut batch).unwrap();
+                let mut sorted = expected.clone();
+                sort_data_points(&mut sorted);
+                prop_assert!(metrics_eq(&decoded, &sorted).unwrap());
+
+                let mut batch = producer.produce_batches(request).unwrap();
+                let decoded = consumer.consume_batches(&mut batch).unwrap();
+                prop_assert_eq!(
+                    decoded.resource_metrics.len(),
+                    expected.resource_metrics.len()
+                );
+                for (decoded, expected) in decoded
+                    .resource_metrics
+                    .iter()
+                    .zip(&expected.resource_metrics)
+                {
+                    prop_assert_eq!(decoded, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_schema_changes() {
+        let a = Field::new("a", DataType::Int64, false);
+        let b = Field::new("b", DataType::Utf8, true);
+        let schemas = [
+            Schema::new(vec![a.clone(), b.clone()]),
+            Schema::new(vec![b.clone(), a.clone()]),
+            Schema::new(vec![a.clone().with_nullable(true), b.clone()]),
+            Schema::new(vec![a.clone(), b.clone()])
+                .with_metadata([("k".to_string(), "v".to_string())].into()),
+        ];
+        let mut producer = Producer::default();
+        let mut consumer = Consumer::default();
+        for (i, schema) in schemas.into_iter().enumerate() {
+            let columns: Vec<ArrayRef> = schema
+                .fields()
+                .iter()
+                .map(|f| match f.data_type() {
+                    DataType::Int64 => Arc::new(Int64Array::from(vec![1])) as ArrayRef,
+                    _ => Arc::new(StringArray::from(vec!["x"])),
+                })
+                .collect();
+            let record = RecordBatch::try_new(Arc::new(schema), columns).unwrap();
+            // all schemas share one canonical id, yet each starts a new IPC stream.
+            let mut batch = producer
+                .produce_records(vec![(ArrowPayloadType::ResourceAttrs, record.clone())])
+                .unwrap();
+            assert_eq!(batch.arrow_payloads[0].schema_id, i.to_string());
+            let records = consumer.consume_records(&mut batch).unwrap();
+            assert_eq!(records[0].record, record);
+        }
+    }
+
+    #[test]
+    fn test_headers() {
+        let mut producer = Producer::default();
+        let mut consumer = Consumer::default();
+        let request = ExportMetricsServiceRequest::default();
+        for tenant in ["a", "b", "b"] {
+            let headers = [("authorization", "Bearer secret"), ("x-tenant", tenant)];
+            let mut batch = producer
+                .produce_batches_with_headers(&request, &headers)
+                .unwrap();
+            consumer.consume_batches(&mut batch).unwrap();
+            let decoded = consumer.last_batch_headers();
+            assert_eq!(decoded["authorization"], vec!["Bearer secret"]);
+            assert_eq!(decoded["x-tenant"], vec![tenant]);
+        }
+        let mut batch = producer.produce_batches(&request).unwrap();
+        consumer.consume_batches(&mut batch).unwrap();
+        assert!(consumer.last_batch_headers().is_empty());
+    }
+}
@@ -0,0 +1,132 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::encode::attributes::Attributes32Builder;
+use crate::encode::next_id;
+use crate::encode::sort::AttributeOrder;
+use crate::error;
+use crate::schema::consts;
+use arrow::array::{
+    ArrayRef, BinaryBuilder, Float64Builder, Int64Builder, RecordBatch, TimestampNanosecondArray,
+    UInt32Array,
+};
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use opentelemetry_proto::tonic::metrics::v1::exemplar::Value;
+use opentelemetry_proto::tonic::metrics::v1::Exemplar;
+use snafu::ResultExt;
+use std::sync::Arc;
+
+/// Builds an exemplars record and the record of their filtered attributes, the counterpart of
+/// [crate::otlp::exemplar::ExemplarsStore].
+#[derive(Default)]
+pub(crate) struct ExemplarsBuilder {
+    id: Vec<u32>,
+    parent_id: Vec<u32>,
+    time_unix_nano: Vec<i64>,
+    int_value: Int64Builder,
+    double_value: Float64Builder,
+    span_id: BinaryBuilder,
+    trace_id: BinaryBuilder,
+    attributes: Attributes32Builder,
+
+    next_id: usize,
+    prev_parent_id: u32,
+    prev_value: Option<Value>,
+}
+
+impl ExemplarsBuilder {
+    pub fn new(attribute_order: AttributeOrder) -> Self {
+        Self {
+            attributes: Attributes32Builder::new(attribute_order),
+            ..Default::default()
+        }
+    }
+
+    /// Appends the exemplars of the data point with given id. Data points must be appended
+    /// in ascending id order.
+    pub fn append(&mut self, parent_id: u32, exemplars: &[Exemplar]) -> error::Result<()> {
+        for exemplar in exemplars {
+            let id: u32 = next_id(&mut self.next_id, "exemplars")?;
+            // ids are delta encoded.
+            self.id.push((id > 0) as u32);
+            let delta = self.encode_parent_id(parent_id, exemplar.value.as_ref());
+            self.parent_id.push(delta);
+            self.time_unix_nano.push(exemplar.time_unix_nano as i64);
+            match exemplar.value {
+                Some(Value::AsInt(v)) => {
+                    self.int_value.append_value(v);
+                    self.double_value.append_null();
+                }
+                Some(Value::AsDouble(v)) => {
+                    self.int_value.append_null();
+                    self.double_value.append_value(v);
+                }
+                None => {
+                    self.int_value.append_null();
+                    self.double_value.append_null();
+                }
+            }
+            // empty ids are written as nulls, the decoder only accepts ids of full length.
+            self.span_id
+                .append_option((!exemplar.span_id.is_empty()).then_some(&exemplar.span_id));
+            self.trace_id
+                .append_option((!exemplar.trace_id.is_empty()).then_some(&exemplar.trace_id));
+            self.attributes.append(id, &exemplar.filtered_attributes)?;
+        }
+        Ok(())
+    }
+
+    /// Mirrors the delta group encoding of exemplar parent ids, grouped by value.
+    fn encode_parent_id(&mut self, parent_id: u32, value: Option<&Value>) -> u32 {
+        let encoded = match value {
+            Some(v) if self.prev_value.as_ref() != Some(v) => {
+                self.prev_value = Some(*v);
+                parent_id
+            }
+            _ => parent_id - self.prev_parent_id,
+        };
+        self.prev_parent_id = parent_id;
+        encoded
+    }
+
+    /// Returns the exemplars record and the attributes record, if any.
+    pub fn finish(mut self) -> error::Result<(Option<RecordBatch>, Option<RecordBatch>)> {
+        if self.id.is_empty() {
+            return Ok((None, None));
+        }
+        let schema = Schema::new(vec![
+            Field::new(consts::ID, DataType::UInt32, true),
+            Field::new(consts::PARENT_ID, DataType::UInt32, false),
+            Field::new(
+                consts::TIME_UNIX_NANO,
+                DataType::Timestamp(TimeUnit::Nanosecond, None),
+                true,
+            ),
+            Field::new(consts::INT_VALUE, DataType::Int64, true),
+            Field::new(consts::DOUBLE_VALUE, DataType::Float64, true),
+            Field::new(consts::SPAN_ID, DataType::Binary, true),
+            Field::new(consts::TRACE_ID, DataType::Binary, true),
+        ]);
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(UInt32Array::from(self.id)),
+            Arc::new(UInt32Array::from(self.parent_id)),
+            Arc::new(TimestampNanosecondArray::from(self.time_unix_nano)),
+            Arc::new(self.int_value.finish()),
+            Arc::new(self.double_value.finish()),
+            Arc::new(self.span_id.finish()),
+            Arc::new(self.trace_id.finish()),
+        ];
+        let exemplars = RecordBatch::try_new(Arc::new(schema), columns)
+            .context(error::BuildRecordBatchSnafu)?;
+        Ok((Some(exemplars), self.attributes.finish()?))
+    }
+}
@@ -0,0 +1,262 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::encode::attributes::Attributes16Builder;
+use crate::encode::data_points::{
+    ExpHistogramDataPointsBuilder, HistogramDataPointsBuilder, NumberDataPointsBuilder,
+    SummaryDataPointsBuilder,
+};
+use crate::encode::next_id;
+use crate::encode::sort::SortStrategy;
+use crate::error;
+use crate::opentelemetry::ArrowPayloadType;
+use crate::otlp::metric::MetricType;
+use crate::schema::consts;
+use arrow::array::{
+    ArrayRef, BooleanBuilder, Int32Builder, RecordBatch, StringBuilder, StructArray, UInt16Array,
+    UInt32Array, UInt8Array,
+};
+use arrow::datatypes::{DataType, Field};
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use opentelemetry_proto::tonic::metrics::v1::metric::Data;
+use snafu::ResultExt;
+use std::sync::Arc;
+
+/// Builds the `UnivariateMetrics` record, one row per metric, with resource and scope
+/// denormalized into struct columns.
+#[derive(Default)]
+struct MetricsBuilder {
+    resource_id: Vec<u16>,
+    resource_dropped_attributes_count: Vec<u32>,
+    resource_schema_url: StringBuilder,
+    scope_name: StringBuilder,
+    scope_version: StringBuilder,
+    scope_dropped_attributes_count: Vec<u32>,
+    scope_id: Vec<u16>,
+    schema_url: StringBuilder,
+    id: Vec<u16>,
+    metric_type: Vec<u8>,
+    name: StringBuilder,
+    description: StringBuilder,
+    unit: StringBuilder,
+    aggregation_temporality: Int32Builder,
+    is_monotonic: BooleanBuilder,
+}
+
+impl MetricsBuilder {
+    fn finish(mut self) -> error::Result<RecordBatch> {
+        let resource = StructArray::from(vec![
+            (
+                Arc::new(Field::new(consts::ID, DataType::UInt16, true)),
+                Arc::new(UInt16Array::from(self.resource_id)) as ArrayRef,
+            ),
+            (
+                Arc::new(Field::new(
+                    consts::DROPPED_ATTRIBUTES_COUNT,
+                    DataType::UInt32,
+                    true,
+                )),
+                Arc::new(UInt32Array::from(self.resource_dropped_attributes_count)) as ArrayRef,
+            ),
+            (
+                Arc::new(Field::new(consts::SCHEMA_URL, DataType::Utf8, true)),
+                Arc::new(self.resource_schema_url.finish()) as ArrayRef,
+            ),
+        ]);
+        let scope = StructArray::from(vec![
+            (
+                Arc::new(Field::new(consts::NAME, DataType::Utf8, true)),
+                Arc::new(self.scope_name.finish()) as ArrayRef,
+            ),
+            (
+                Arc::new(Field::new(consts::VERSION, DataType::Utf8, true)),
+                Arc::new(self.scope_version.finish()) as ArrayRef,
+            ),
+            (
+                Arc::new(Field::new(
+                    consts::DROPPED_ATTRIBUTES_COUNT,
+                    DataType::UInt32,
+                    true,
+                )),
+                Arc::new(UInt32Array::from(self.scope_dropped_attributes_count)) as ArrayRef,
+            ),
+            (
+                Arc::new(Field::new(consts::ID, DataType::UInt16, true)),
+                Arc::new(UInt16Array::from(self.scope_id)) as ArrayRef,
+            ),
+        ]);
+        let columns: Vec<(&str, ArrayRef, bool)> = vec![
+            (consts::RESOURCE, Arc::new(resource), true),
+            (consts::SCOPE, Arc::new(scope), true),
+            (consts::SCHEMA_URL, Arc::new(self.schema_url.finish()), true),
+            (consts::ID, Arc::new(UInt16Array::from(self.id)), false),
+            (
+                consts::METRIC_TYPE,
+                Arc::new(UInt8Array::from(self.metric_type)),
+                false,
+            ),
+            (consts::NAME, Arc::new(self.name.finish()), false),
+            (
+                consts::DESCRIPTION,
+                Arc::new(self.description.finish()),
+                true,
+            ),
+            (consts::UNIT, Arc::new(self.unit.finish()), true),
+            (
+                consts::AGGREGATION_TEMPORALITY,
+                Arc::new(self.aggregation_temporality.finish()),
+                true,
+            ),
+            (
+                consts::IS_MONOTONIC,
+                Arc::new(self.is_monotonic.finish()),
+                true,
+            ),
+        ];
+        RecordBatch::try_from_iter_with_nullable(columns).context(error::BuildRecordBatchSnafu)
+    }
+}
+
+/// Encodes a metrics request to the records of one OTAP batch, the inverse of
+/// [crate::decode::decoder::decode_metrics]. The `UnivariateMetrics` record always comes first,
+/// other records are only present if they have rows.
+///
+/// Resources and scopes without any metric can't be represented and are dropped. Rows are
+/// written in the default [SortStrategy].
+pub fn encode_metrics(
+    request: &ExportMetricsServiceRequest,
+) -> error::Result<Vec<(ArrowPayloadType, RecordBatch)>> {
+    encode_metrics_with(request, SortStrategy::default())
+}
+
+/// Like [encode_metrics], writing rows in given order.
+pub fn encode_metrics_with(
+    request: &ExportMetricsServiceRequest,
+    sort: SortStrategy,
+) -> error::Result<Vec<(ArrowPayloadType, RecordBatch)>> {
+    let mut metrics = MetricsBuilder::default();
+    let mut resource_attrs = Attributes16Builder::new(sort.attributes);
+    let mut scope_attrs = Attributes16Builder::new(sort.attributes);
+    let mut number_data_points = NumberDataPointsBuilder::new(sort);
+    let mut histogram_data_points = HistogramDataPointsBuilder::new(sort);
+    let mut exp_histogram_data_points = ExpHistogramDataPointsBuilder::new(sort);
+    let mut summary_data_points = SummaryDataPointsBuilder::new(sort);
+
+    let (mut next_resource_id, mut next_scope_id, mut next_metric_id) = (0, 0, 0);
+    // resource, scope and metric ids are delta encoded.
+    let (mut prev_resource_id, mut prev_scope_id, mut prev_metric_id) = (0, 0, 0);
+
+    for resource_metrics in &request.resource_metrics {
+        if resource_metrics
+            .scope_metrics
+            .iter()
+            .all(|s| s.metrics.is_empty())
+        {
+            continue;
+        }
+        let resource_id: u16 = next_id(&mut next_resource_id, "resources")?;
+        let resource = resource_metrics.resource.clone().unwrap_or_default();
+        resource_attrs.append(resource_id, &resource.attributes)?;
+
+        for scope_metrics in &resource_metrics.scope_metrics {
+            if scope_metrics.metrics.is_empty() {
+                continue;
+            }
+            let scope_id: u16 = next_id(&mut next_scope_id, "scopes")?;
+            let scope = scope_metrics.scope.clone().unwrap_or_default();
+            scope_attrs.append(scope_id, &scope.attributes)?;
+
+            for metric in &scope_metrics.metrics {
+                let row = metrics.id.len();
+                let metric_id: u16 = next_id(&mut next_metric_id, "metrics")?;
+
+                metrics.resource_id.push(resource_id - prev_resource_id);
+                prev_resource_id = resource_id;
+                metrics
+                    .resource_dropped_attributes_count
+                    .push(resource.dropped_attributes_count);
+                metrics
+                    .resource_schema_url
+                    .append_value(&resource_metrics.schema_url);
+
+                metrics.scope_name.append_value(&scope.name);
+                metrics.scope_version.append_value(&scope.version);
+                metrics
+                    .scope_dropped_attributes_count
+                    .push(scope.dropped_attributes_count);
+                metrics.scope_id.push(scope_id - prev_scope_id);
+                prev_scope_id = scope_id;
+                metrics.schema_url.append_value(&scope_metrics.schema_url);
+
+                metrics.id.push(metric_id - prev_metric_id);
+                prev_metric_id = metric_id;
+                metrics.name.append_value(&metric.name);
+                metrics.description.append_value(&metric.description);
+                metrics.unit.append_value(&metric.unit);
+
+                let (metric_type, aggregation_temporality, is_monotonic) = match &metric.data {
+                    Some(Data::Gauge(gauge)) => {
+                        number_data_points.append(metric_id, &gauge.data_points)?;
+                        (MetricType::Gauge, None, None)
+                    }
+                    Some(Data::Sum(sum)) => {
+                        number_data_points.append(metric_id, &sum.data_points)?;
+                        (
+                            MetricType::Sum,
+                            Some(sum.aggregation_temporality),
+                            Some(sum.is_monotonic),
+                        )
+                    }
+                    Some(Data::Histogram(histogram)) => {
+                        histogram_data_points.append(metric_id, &histogram.data_points)?;
+                        (
+                            MetricType::Histogram,
+                            Some(histogram.aggregation_temporality),
+                            None,
+                        )
+                    }
+                    Some(Data::ExponentialHistogram(histogram)) => {
+                        exp_histogram_data_points.append(metric_id, &histogram.data_points)?;
+                        (
+                            MetricType::ExponentialHistogram,
+                            Some(histogram.aggregation_temporality),
+                            None,
+                        )
+                    }
+                    Some(Data::Summary(summary)) => {
+                        summary_data_points.append(metric_id, &summary.data_points)?;
+                        (MetricType::Summary, None, None)
+                    }
+                    None => return error::EmptyMetricTypeSnafu { row }.fail(),
+                };
+                metrics.metric_type.push(metric_type as u8);
+                metrics
+                    .aggregation_temporality
+                    .append_option(aggregation_temporality);
+                metrics.is_monotonic.append_option(is_monotonic);
+            }
+        }
+    }
+
+    let mut records = vec![(ArrowPayloadType::UnivariateMetrics, metrics.finish()?)];
+    if let Some(record) = resource_attrs.finish()? {
+        records.push((ArrowPayloadType::ResourceAttrs, record));
+    }
+    if let Some(record) = scope_attrs.finish()? {
+        records.push((ArrowPayloadType::ScopeAttrs, record));
+    }
+    records.extend(number_data_points.finish()?);
+    records.extend(summary_data_points.finish()?);
+    records.extend(histogram_data_points.finish()?);
+    records.extend(exp_histogram_data_points.finish()?);
+    Ok(records)
+}
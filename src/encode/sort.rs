@@ -0,0 +1,63 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/// Order of the rows of attribute records.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AttributeOrder {
+    /// By key, then value, then parent id, as the Go encoder does. Maximizes the runs of
+    /// delta-encoded parent ids and of equal keys and values.
+    #[default]
+    KeyValueParent,
+    /// By parent id, then key, keeping the attributes of each parent together.
+    ParentKey,
+    /// In the order of the request.
+    Insertion,
+}
+
+/// Order of the data points of each metric.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DataPointOrder {
+    /// By start time, then time, as the Go encoder does.
+    #[default]
+    Timestamps,
+    /// In the order of the request.
+    Insertion,
+}
+
+/// Order of the rows written by the encoder. It affects the size of the payloads once
+/// compressed, and the order of attributes and data points in decoded requests.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SortStrategy {
+    pub attributes: AttributeOrder,
+    pub data_points: DataPointOrder,
+}
+
+impl SortStrategy {
+    /// Keeps the order of the request, so that requests round-trip exactly.
+    pub const UNSORTED: SortStrategy = SortStrategy {
+        attributes: AttributeOrder::Insertion,
+        data_points: DataPointOrder::Insertion,
+    };
+}
+
+/// Returns the data points of one metric in given order. The sort is stable.
+pub(crate) fn sort_data_points<D>(
+    data_points: &[D],
+    order: DataPointOrder,
+    timestamps: impl Fn(&D) -> (u64, u64),
+) -> Vec<&D> {
+    let mut sorted: Vec<_> = data_points.iter().collect();
+    if order == DataPointOrder::Timestamps {
+        sorted.sort_by_key(|dp| timestamps(dp));
+    }
+    sorted
+}
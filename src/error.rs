@@ -10,11 +10,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::opentelemetry::ArrowPayloadType;
 use crate::otlp::attributes::store::AttributeValueType;
 use crate::otlp::metric::MetricType;
 use arrow::datatypes::DataType;
 use arrow::error::ArrowError;
 use num_enum::TryFromPrimitiveError;
+use prost::DecodeError;
 use snafu::{Location, Snafu};
 use std::backtrace::Backtrace;
 
@@ -39,51 +41,70 @@ pub enum Error {
         location: Location,
     },
 
-    #[snafu(display("Cannot recognize metric type: {}", metric_type))]
+    #[snafu(display("Cannot recognize metric type: {} at row {}", metric_type, row))]
     UnrecognizedMetricType {
         metric_type: i32,
+        row: usize,
         #[snafu(source)]
         error: TryFromPrimitiveError<MetricType>,
         #[snafu(implicit)]
         location: Location,
     },
-    #[snafu(display("Unable to handle empty metric type"))]
+    #[snafu(display("Unable to handle empty metric type at row {}", row))]
     EmptyMetricType {
+        row: usize,
         #[snafu(implicit)]
         location: Location,
     },
 
-    #[snafu(display("Cannot recognize attribute value type"))]
+    #[snafu(display("Cannot recognize attribute value type at row {}", row))]
     UnrecognizedAttributeValueType {
+        row: usize,
         #[snafu(source)]
         error: TryFromPrimitiveError<AttributeValueType>,
         #[snafu(implicit)]
         location: Location,
     },
 
-    #[snafu(display("Currently attribute store value type: {} is not supported", type_name))]
+    #[snafu(display(
+        "Currently attribute store value type: {} is not supported, row: {}",
+        type_name,
+        row
+    ))]
     UnsupportedAttributeValue {
         type_name: String,
+        row: usize,
         #[snafu(implicit)]
         location: Location,
     },
 
-    #[snafu(display("Invalid exemplar data, message: {}", message))]
+    #[snafu(display("Invalid exemplar data at row {}, message: {}", row, message))]
     InvalidExemplarData {
+        row: usize,
         message: String,
         #[snafu(implicit)]
         location: Location,
     },
 
-    #[snafu(display("Invalid span id in exemplar data, message: {}", message))]
+    #[snafu(display(
+        "Invalid span id in exemplar data at row {}, message: {}",
+        row,
+        message
+    ))]
     InvalidSpanId {
+        row: usize,
         message: String,
         #[snafu(implicit)]
         location: Location,
     },
 
-    #[snafu(display("Invalid trace id in exemplar data, message: {}", message))]
+    #[snafu(display(
+        "Invalid trace id in exemplar data at row {}, message: {}",
+        row,
+        message
+    ))]
     InvalidTraceId {
+        row: usize,
         message: String,
         #[snafu(implicit)]
         location: Location,
@@ -152,4 +173,184 @@ pub enum Error {
         #[snafu(implicit)]
         location: Location,
     },
+
+    #[snafu(display("Unsupported data type in schema, given: {}", data_type))]
+    UnsupportedSchemaDataType {
+        data_type: DataType,
+        #[snafu(implicit)]
+        location: Location,
+    },
+
+    #[snafu(display("Failed to serialize OTLP request to JSON"))]
+    SerializeJson {
+        #[snafu(source)]
+        error: serde_json::Error,
+        #[snafu(implicit)]
+        location: Location,
+    },
+
+    #[snafu(display("Failed to write message"))]
+    WriteMessage {
+        #[snafu(source)]
+        error: std::io::Error,
+        #[snafu(implicit)]
+        location: Location,
+    },
+
+    #[snafu(display("Failed to read message"))]
+    ReadMessage {
+        #[snafu(source)]
+        error: std::io::Error,
+        #[snafu(implicit)]
+        location: Location,
+    },
+
+    #[snafu(display("Message of {} bytes exceeds the limit of {} bytes", size, max))]
+    MessageTooLarge {
+        size: u64,
+        max: usize,
+        #[snafu(implicit)]
+        location: Location,
+    },
+
+    #[snafu(display("Failed to decode protobuf message"))]
+    DecodeProtobuf {
+        #[snafu(source)]
+        error: DecodeError,
+        #[snafu(implicit)]
+        location: Location,
+    },
+
+    #[snafu(display("Invalid capture file, message: {}", message))]
+    InvalidCaptureFile {
+        message: String,
+        #[snafu(implicit)]
+        location: Location,
+    },
+
+    #[snafu(display("Invalid serialized attribute value, message: {}", message))]
+    InvalidSerializedValue {
+        message: String,
+        #[snafu(implicit)]
+        location: Location,
+    },
+
+    #[snafu(display("Failed to build record batch"))]
+    BuildRecordBatch {
+        #[snafu(source)]
+        error: ArrowError,
+        #[snafu(implicit)]
+        location: Location,
+    },
+
+    #[snafu(display("Failed to build stream writer"))]
+    BuildStreamWriter {
+        #[snafu(source)]
+        error: ArrowError,
+        #[snafu(implicit)]
+        location: Location,
+    },
+
+    #[snafu(display("Failed to write record batch"))]
+    WriteRecordBatch {
+        #[snafu(source)]
+        error: ArrowError,
+        #[snafu(implicit)]
+        location: Location,
+    },
+
+    #[snafu(display("Invalid HPACK header block, message: {}", message))]
+    InvalidHpack {
+        message: String,
+        #[snafu(implicit)]
+        location: Location,
+    },
+
+    #[snafu(display("OTAP gRPC call failed"))]
+    Rpc {
+        #[snafu(source(from(tonic::Status, Box::new)))]
+        error: Box<tonic::Status>,
+        #[snafu(implicit)]
+        location: Location,
+    },
+
+    #[snafu(display("OTAP stream closed before batch {} was acknowledged", batch_id))]
+    StreamClosed {
+        batch_id: i64,
+        #[snafu(implicit)]
+        location: Location,
+    },
+
+    #[snafu(display("Queue is full, max bytes: {}", max_bytes))]
+    QueueFull {
+        max_bytes: u64,
+        #[snafu(implicit)]
+        location: Location,
+    },
+
+    #[snafu(display("Too many {} in one batch, max: {}", name, max))]
+    TooManyItems {
+        name: &'static str,
+        max: usize,
+        #[snafu(implicit)]
+        location: Location,
+    },
+
+    #[snafu(display(
+        "Failed to decode {:?} payload, schema id: {}",
+        payload_type,
+        schema_id
+    ))]
+    DecodePayload {
+        payload_type: ArrowPayloadType,
+        schema_id: String,
+        #[snafu(source(from(Error, Box::new)))]
+        error: Box<Error>,
+        #[snafu(implicit)]
+        location: Location,
+    },
+}
+
+impl Error {
+    /// Short snake case name of the error variant, suitable as a metric label. Errors
+    /// wrapped with payload context report the kind of the underlying error.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::ColumnNotFound { .. } => "column_not_found",
+            Error::ColumnDataTypeMismatch { .. } => "column_data_type_mismatch",
+            Error::UnrecognizedMetricType { .. } => "unrecognized_metric_type",
+            Error::EmptyMetricType { .. } => "empty_metric_type",
+            Error::UnrecognizedAttributeValueType { .. } => "unrecognized_attribute_value_type",
+            Error::UnsupportedAttributeValue { .. } => "unsupported_attribute_value",
+            Error::InvalidExemplarData { .. } => "invalid_exemplar_data",
+            Error::InvalidSpanId { .. } => "invalid_span_id",
+            Error::InvalidTraceId { .. } => "invalid_trace_id",
+            Error::InvalidQuantileType { .. } => "invalid_quantile_type",
+            Error::InvalidListArray { .. } => "invalid_list_array",
+            Error::UnsupportedPayloadType { .. } => "unsupported_payload_type",
+            Error::BuildStreamReader { .. } => "build_stream_reader",
+            Error::ReadRecordBatch { .. } => "read_record_batch",
+            Error::EmptyBatch { .. } => "empty_batch",
+            Error::MetricRecordNotFound { .. } => "metric_record_not_found",
+            Error::UnsupportedStringColumnType { .. } => "unsupported_string_column_type",
+            Error::UnsupportedStringDictKeyType { .. } => "unsupported_string_dict_key_type",
+            Error::UnsupportedSchemaDataType { .. } => "unsupported_schema_data_type",
+            Error::SerializeJson { .. } => "serialize_json",
+            Error::WriteMessage { .. } => "write_message",
+            Error::ReadMessage { .. } => "read_message",
+            Error::MessageTooLarge { .. } => "message_too_large",
+            Error::DecodeProtobuf { .. } => "decode_protobuf",
+            Error::InvalidCaptureFile { .. } => "invalid_capture_file",
+            Error::InvalidSerializedValue { .. } => "invalid_serialized_value",
+            Error::BuildRecordBatch { .. } => "build_record_batch",
+            Error::BuildStreamWriter { .. } => "build_stream_writer",
+            Error::WriteRecordBatch { .. } => "write_record_batch",
+            Error::InvalidHpack { .. } => "invalid_hpack",
+            Error::Rpc { .. } => "rpc",
+            Error::StreamClosed { .. } => "stream_closed",
+            Error::QueueFull { .. } => "queue_full",
+            Error::TooManyItems { .. } => "too_many_items",
+            Error::DecodePayload { error, .. } => error.kind(),
+        }
+    }
 }
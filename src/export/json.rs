@@ -0,0 +1,447 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! OTLP/JSON encoding of decoded requests.
+//!
+//! The serde support shipped with `opentelemetry-proto` does not follow the
+//! [OTLP/JSON](https://opentelemetry.io/docs/specs/otlp/#json-protobuf-encoding) mapping
+//! for all fields (e.g. 64-bit integers and bytes values), so requests are serialized
+//! through the [Json] wrapper here instead.
+
+use crate::error;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use opentelemetry_proto::tonic::common::v1::{
+    any_value, AnyValue, ArrayValue, InstrumentationScope, KeyValue, KeyValueList,
+};
+use opentelemetry_proto::tonic::metrics::v1::exponential_histogram_data_point::Buckets;
+use opentelemetry_proto::tonic::metrics::v1::summary_data_point::ValueAtQuantile;
+use opentelemetry_proto::tonic::metrics::v1::{
+    exemplar, metric, number_data_point, Exemplar, ExponentialHistogram,
+    ExponentialHistogramDataPoint, Gauge, Histogram, HistogramDataPoint, Metric, NumberDataPoint,
+    ResourceMetrics, ScopeMetrics, Sum, Summary, SummaryDataPoint,
+};
+use opentelemetry_proto::tonic::resource::v1::Resource;
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+use snafu::ResultExt;
+use std::io::Write;
+
+/// Serializes the request to a compact OTLP/JSON string.
+pub fn to_json(request: &ExportMetricsServiceRequest) -> error::Result<String> {
+    serde_json::to_string(&Json(request)).context(error::SerializeJsonSnafu)
+}
+
+/// Serializes the request to a pretty-printed OTLP/JSON string.
+pub fn to_json_pretty(request: &ExportMetricsServiceRequest) -> error::Result<String> {
+    serde_json::to_string_pretty(&Json(request)).context(error::SerializeJsonSnafu)
+}
+
+/// Writes the request as a single line of OTLP/JSON, which is the layout expected by
+/// the collector's `otlpjsonfile` receiver.
+pub fn write_json_line<W: Write>(
+    writer: &mut W,
+    request: &ExportMetricsServiceRequest,
+) -> error::Result<()> {
+    serde_json::to_writer(&mut *writer, &Json(request)).context(error::SerializeJsonSnafu)?;
+    writer.write_all(b"\n").context(error::WriteMessageSnafu)
+}
+
+/// Wrapper that serializes OTLP messages following the OTLP/JSON mapping: lowerCamelCase
+/// field names, hex encoded trace/span ids, base64 encoded bytes, enums as integers and
+/// 64-bit integers as decimal strings. Fields with default values are omitted.
+pub struct Json<'a, T: ?Sized>(pub &'a T);
+
+/// 64-bit integers are encoded as decimal strings.
+struct Int64<T>(T);
+
+impl<T: ToString> Serialize for Int64<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0.to_string())
+    }
+}
+
+/// Non-finite doubles are encoded as `"NaN"`, `"Infinity"` and `"-Infinity"`.
+struct Double(f64);
+
+impl Serialize for Double {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let v = self.0;
+        if v.is_nan() {
+            serializer.serialize_str("NaN")
+        } else if v.is_infinite() && v.is_sign_positive() {
+            serializer.serialize_str("Infinity")
+        } else if v.is_infinite() {
+            serializer.serialize_str("-Infinity")
+        } else {
+            serializer.serialize_f64(v)
+        }
+    }
+}
+
+struct Int64List<'a>(&'a [u64]);
+
+impl<'a> Serialize for Int64List<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter().map(Int64))
+    }
+}
+
+struct DoubleList<'a>(&'a [f64]);
+
+impl<'a> Serialize for DoubleList<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter().copied().map(Double))
+    }
+}
+
+/// Helper that skips entries holding default values.
+struct Fields<M>(M);
+
+impl<M: SerializeMap> Fields<M> {
+    fn str(&mut self, key: &str, value: &str) -> Result<(), M::Error> {
+        if value.is_empty() {
+            return Ok(());
+        }
+        self.0.serialize_entry(key, value)
+    }
+
+    fn u32(&mut self, key: &str, value: u32) -> Result<(), M::Error> {
+        if value == 0 {
+            return Ok(());
+        }
+        self.0.serialize_entry(key, &value)
+    }
+
+    fn i32(&mut self, key: &str, value: i32) -> Result<(), M::Error> {
+        if value == 0 {
+            return Ok(());
+        }
+        self.0.serialize_entry(key, &value)
+    }
+
+    fn bool(&mut self, key: &str, value: bool) -> Result<(), M::Error> {
+        if !value {
+            return Ok(());
+        }
+        self.0.serialize_entry(key, &value)
+    }
+
+    fn u64(&mut self, key: &str, value: u64) -> Result<(), M::Error> {
+        if value == 0 {
+            return Ok(());
+        }
+        self.0.serialize_entry(key, &Int64(value))
+    }
+
+    fn f64(&mut self, key: &str, value: f64) -> Result<(), M::Error> {
+        if value == 0.0 {
+            return Ok(());
+        }
+        self.0.serialize_entry(key, &Double(value))
+    }
+
+    fn f64_opt(&mut self, key: &str, value: Option<f64>) -> Result<(), M::Error> {
+        match value {
+            Some(v) => self.0.serialize_entry(key, &Double(v)),
+            None => Ok(()),
+        }
+    }
+
+    fn hex(&mut self, key: &str, value: &[u8]) -> Result<(), M::Error> {
+        if value.is_empty() {
+            return Ok(());
+        }
+        const DIGITS: &[u8; 16] = b"0123456789abcdef";
+        let hex = value
+            .iter()
+            .flat_map(|b| [DIGITS[(b >> 4) as usize], DIGITS[(b & 0xf) as usize]])
+            .map(char::from)
+            .collect::<String>();
+        self.0.serialize_entry(key, &hex)
+    }
+
+    fn list<T>(&mut self, key: &str, value: &[T]) -> Result<(), M::Error>
+    where
+        for<'b> Json<'b, [T]>: Serialize,
+    {
+        if value.is_empty() {
+            return Ok(());
+        }
+        self.0.serialize_entry(key, &Json(value))
+    }
+
+    fn msg<T>(&mut self, key: &str, value: Option<&T>) -> Result<(), M::Error>
+    where
+        for<'b> Json<'b, T>: Serialize,
+    {
+        match value {
+            Some(v) => self.0.serialize_entry(key, &Json(v)),
+            None => Ok(()),
+        }
+    }
+
+    fn entry<V: Serialize + ?Sized>(&mut self, key: &str, value: &V) -> Result<(), M::Error> {
+        self.0.serialize_entry(key, value)
+    }
+
+    fn end(self) -> Result<M::Ok, M::Error> {
+        self.0.end()
+    }
+}
+
+macro_rules! impl_json {
+    ($ty:ty, |$v:ident, $f:ident| $body:block) => {
+        impl<'a> Serialize for Json<'a, $ty> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let $v = self.0;
+                let mut $f = Fields(serializer.serialize_map(None)?);
+                $body
+                $f.end()
+            }
+        }
+
+        impl<'a> Serialize for Json<'a, [$ty]> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_seq(self.0.iter().map(Json))
+            }
+        }
+    };
+}
+
+impl_json!(ExportMetricsServiceRequest, |v, f| {
+    f.list("resourceMetrics", &v.resource_metrics)?;
+});
+
+impl_json!(ResourceMetrics, |v, f| {
+    f.msg("resource", v.resource.as_ref())?;
+    f.list("scopeMetrics", &v.scope_metrics)?;
+    f.str("schemaUrl", &v.schema_url)?;
+});
+
+impl_json!(Resource, |v, f| {
+    f.list("attributes", &v.attributes)?;
+    f.u32("droppedAttributesCount", v.dropped_attributes_count)?;
+});
+
+impl_json!(ScopeMetrics, |v, f| {
+    f.msg("scope", v.scope.as_ref())?;
+    f.list("metrics", &v.metrics)?;
+    f.str("schemaUrl", &v.schema_url)?;
+});
+
+impl_json!(InstrumentationScope, |v, f| {
+    f.str("name", &v.name)?;
+    f.str("version", &v.version)?;
+    f.list("attributes", &v.attributes)?;
+    f.u32("droppedAttributesCount", v.dropped_attributes_count)?;
+});
+
+impl_json!(Metric, |v, f| {
+    f.str("name", &v.name)?;
+    f.str("description", &v.description)?;
+    f.str("unit", &v.unit)?;
+    match &v.data {
+        Some(metric::Data::Gauge(d)) => f.entry("gauge", &Json(d))?,
+        Some(metric::Data::Sum(d)) => f.entry("sum", &Json(d))?,
+        Some(metric::Data::Histogram(d)) => f.entry("histogram", &Json(d))?,
+        Some(metric::Data::ExponentialHistogram(d)) => f.entry("exponentialHistogram", &Json(d))?,
+        Some(metric::Data::Summary(d)) => f.entry("summary", &Json(d))?,
+        None => {}
+    }
+    f.list("metadata", &v.metadata)?;
+});
+
+impl_json!(Gauge, |v, f| {
+    f.list("dataPoints", &v.data_points)?;
+});
+
+impl_json!(Sum, |v, f| {
+    f.list("dataPoints", &v.data_points)?;
+    f.i32("aggregationTemporality", v.aggregation_temporality)?;
+    f.bool("isMonotonic", v.is_monotonic)?;
+});
+
+impl_json!(Histogram, |v, f| {
+    f.list("dataPoints", &v.data_points)?;
+    f.i32("aggregationTemporality", v.aggregation_temporality)?;
+});
+
+impl_json!(ExponentialHistogram, |v, f| {
+    f.list("dataPoints", &v.data_points)?;
+    f.i32("aggregationTemporality", v.aggregation_temporality)?;
+});
+
+impl_json!(Summary, |v, f| {
+    f.list("dataPoints", &v.data_points)?;
+});
+
+impl_json!(NumberDataPoint, |v, f| {
+    f.list("attributes", &v.attributes)?;
+    f.u64("startTimeUnixNano", v.start_time_unix_nano)?;
+    f.u64("timeUnixNano", v.time_unix_nano)?;
+    match v.value {
+        Some(number_data_point::Value::AsDouble(d)) => f.entry("asDouble", &Double(d))?,
+        Some(number_data_point::Value::AsInt(i)) => f.entry("asInt", &Int64(i))?,
+        None => {}
+    }
+    f.list("exemplars", &v.exemplars)?;
+    f.u32("flags", v.flags)?;
+});
+
+impl_json!(HistogramDataPoint, |v, f| {
+    f.list("attributes", &v.attributes)?;
+    f.u64("startTimeUnixNano", v.start_time_unix_nano)?;
+    f.u64("timeUnixNano", v.time_unix_nano)?;
+    f.u64("count", v.count)?;
+    f.f64_opt("sum", v.sum)?;
+    if !v.bucket_counts.is_empty() {
+        f.entry("bucketCounts", &Int64List(&v.bucket_counts))?;
+    }
+    if !v.explicit_bounds.is_empty() {
+        f.entry("explicitBounds", &DoubleList(&v.explicit_bounds))?;
+    }
+    f.list("exemplars", &v.exemplars)?;
+    f.u32("flags", v.flags)?;
+    f.f64_opt("min", v.min)?;
+    f.f64_opt("max", v.max)?;
+});
+
+impl_json!(ExponentialHistogramDataPoint, |v, f| {
+    f.list("attributes", &v.attributes)?;
+    f.u64("startTimeUnixNano", v.start_time_unix_nano)?;
+    f.u64("timeUnixNano", v.time_unix_nano)?;
+    f.u64("count", v.count)?;
+    f.f64_opt("sum", v.sum)?;
+    f.i32("scale", v.scale)?;
+    f.u64("zeroCount", v.zero_count)?;
+    f.msg("positive", v.positive.as_ref())?;
+    f.msg("negative", v.negative.as_ref())?;
+    f.u32("flags", v.flags)?;
+    f.list("exemplars", &v.exemplars)?;
+    f.f64_opt("min", v.min)?;
+    f.f64_opt("max", v.max)?;
+    f.f64("zeroThreshold", v.zero_threshold)?;
+});
+
+impl_json!(Buckets, |v, f| {
+    f.i32("offset", v.offset)?;
+    if !v.bucket_counts.is_empty() {
+        f.entry("bucketCounts", &Int64List(&v.bucket_counts))?;
+    }
+});
+
+impl_json!(SummaryDataPoint, |v, f| {
+    f.list("attributes", &v.attributes)?;
+    f.u64("startTimeUnixNano", v.start_time_unix_nano)?;
+    f.u64("timeUnixNano", v.time_unix_nano)?;
+    f.u64("count", v.count)?;
+    f.f64("sum", v.sum)?;
+    f.list("quantileValues", &v.quantile_values)?;
+    f.u32("flags", v.flags)?;
+});
+
+impl_json!(ValueAtQuantile, |v, f| {
+    f.f64("quantile", v.quantile)?;
+    f.f64("value", v.value)?;
+});
+
+impl_json!(Exemplar, |v, f| {
+    f.list("filteredAttributes", &v.filtered_attributes)?;
+    f.u64("timeUnixNano", v.time_unix_nano)?;
+    match v.value {
+        Some(exemplar::Value::AsDouble(d)) => f.entry("asDouble", &Double(d))?,
+        Some(exemplar::Value::AsInt(i)) => f.entry("asInt", &Int64(i))?,
+        None => {}
+    }
+    f.hex("spanId", &v.span_id)?;
+    f.hex("traceId", &v.trace_id)?;
+});
+
+impl_json!(KeyValue, |v, f| {
+    f.entry("key", &v.key)?;
+    f.msg("value", v.value.as_ref())?;
+});
+
+impl_json!(AnyValue, |v, f| {
+    match &v.value {
+        Some(any_value::Value::StringValue(s)) => f.entry("stringValue", s)?,
+        Some(any_value::Value::BoolValue(b)) => f.entry("boolValue", b)?,
+        Some(any_value::Value::IntValue(i)) => f.entry("intValue", &Int64(*i))?,
+        Some(any_value::Value::DoubleValue(d)) => f.entry("doubleValue", &Double(*d))?,
+        Some(any_value::Value::ArrayValue(a)) => f.entry("arrayValue", &Json(a))?,
+        Some(any_value::Value::KvlistValue(kv)) => f.entry("kvlistValue", &Json(kv))?,
+        Some(any_value::Value::BytesValue(b)) => f.entry("bytesValue", &BASE64.encode(b))?,
+        None => {}
+    }
+});
+
+impl_json!(ArrayValue, |v, f| {
+    f.list("values", &v.values)?;
+});
+
+impl_json!(KeyValueList, |v, f| {
+    f.list("values", &v.values)?;
+});
+
+#[cfg(test)]
+mod tests {
+    use crate::export::json::to_json;
+    use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+    use opentelemetry_proto::tonic::common::v1::any_value::Value;
+    use opentelemetry_proto::tonic::common::v1::{AnyValue, KeyValue};
+    use opentelemetry_proto::tonic::metrics::v1::{
+        exemplar, metric, number_data_point, Exemplar, Gauge, Metric, NumberDataPoint,
+        ResourceMetrics, ScopeMetrics,
+    };
+
+    #[test]
+    fn test_otlp_json_mapping() {
+        let request = ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                resource: None,
+                scope_metrics: vec![ScopeMetrics {
+                    scope: None,
+                    metrics: vec![Metric {
+                        name: "m".to_string(),
+                        data: Some(metric::Data::Gauge(Gauge {
+                            data_points: vec![NumberDataPoint {
+                                attributes: vec![KeyValue {
+                                    key: "k".to_string(),
+                                    value: Some(AnyValue {
+                                        value: Some(Value::BytesValue(vec![1, 2, 3])),
+                                    }),
+                                }],
+                                time_unix_nano: 1_700_000_000_000_000_000,
+                                value: Some(number_data_point::Value::AsInt(0)),
+                                exemplars: vec![Exemplar {
+                                    span_id: vec![0xab; 8],
+                                    trace_id: vec![0x01; 16],
+                                    value: Some(exemplar::Value::AsDouble(f64::NAN)),
+                                    ..Default::default()
+                                }],
+                                ..Default::default()
+                            }],
+                        })),
+                        ..Default::default()
+                    }],
+                    schema_url: "".to_string(),
+                }],
+                schema_url: "".to_string(),
+            }],
+        };
+
+        let expected = r#"{"resourceMetrics":[{"scopeMetrics":[{"metrics":[{"name":"m","gauge":{"dataPoints":[{"attributes":[{"key":"k","value":{"bytesValue":"AQID"}}],"timeUnixNano":"1700000000000000000","asInt":"0","exemplars":[{"asDouble":"NaN","spanId":"abababababababab","traceId":"01010101010101010101010101010101"}]}]}}]}]}]}"#;
+        assert_eq!(expected, to_json(&request).unwrap());
+    }
+}
@@ -0,0 +1,184 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Length-delimited protobuf files, i.e. a sequence of messages each prefixed by its
+//! varint encoded length, as produced by `writeDelimitedTo` in other protobuf runtimes.
+
+use crate::error;
+use prost::Message;
+use snafu::{ensure, ResultExt};
+use std::io::{ErrorKind, Read, Write};
+use std::marker::PhantomData;
+
+/// Appends a length-delimited message to writer.
+pub fn write_delimited<W: Write, M: Message>(writer: &mut W, message: &M) -> error::Result<()> {
+    writer
+        .write_all(&message.encode_length_delimited_to_vec())
+        .context(error::WriteMessageSnafu)
+}
+
+/// Default limit on the size of a single message read by [DelimitedReader].
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 << 20;
+
+/// Reads length-delimited messages of type `M` until the end of underlying reader.
+pub struct DelimitedReader<R, M> {
+    reader: R,
+    buf: Vec<u8>,
+    max_message_size: usize,
+    _marker: PhantomData<M>,
+}
+
+impl<R, M> DelimitedReader<R, M>
+where
+    R: Read,
+    M: Message + Default,
+{
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buf: vec![],
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Sets the largest message accepted, so that a corrupt length prefix fails instead of
+    /// allocating its size. Defaults to [DEFAULT_MAX_MESSAGE_SIZE].
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// Reads the next message, returns `None` on a clean end of stream.
+    pub fn read_next(&mut self) -> error::Result<Option<M>> {
+        let Some(len) = self.read_len()? else {
+            return Ok(None);
+        };
+        ensure!(
+            len <= self.max_message_size as u64,
+            error::MessageTooLargeSnafu {
+                size: len,
+                max: self.max_message_size,
+            }
+        );
+        self.buf.resize(len as usize, 0);
+        self.reader
+            .read_exact(&mut self.buf)
+            .context(error::ReadMessageSnafu)?;
+        M::decode(self.buf.as_slice())
+            .map(Some)
+            .context(error::DecodeProtobufSnafu)
+    }
+
+    fn read_len(&mut self) -> error::Result<Option<u64>> {
+        // varint encoded u64 occupies at most 10 bytes.
+        let mut len_buf = Vec::with_capacity(10);
+        loop {
+            let mut byte = [0u8; 1];
+            match self.reader.read_exact(&mut byte) {
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof && len_buf.is_empty() => {
+                    return Ok(None);
+                }
+                Err(e) => return Err(e).context(error::ReadMessageSnafu),
+            }
+            len_buf.push(byte[0]);
+            if byte[0] & 0x80 == 0 || len_buf.len() == 10 {
+                break;
+            }
+        }
+        let len = prost::encoding::decode_varint(&mut len_buf.as_slice())
+            .context(error::DecodeProtobufSnafu)?;
+        Ok(Some(len))
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R, M> Iterator for DelimitedReader<R, M>
+where
+    R: Read,
+    M: Message + Default,
+{
+    type Item = error::Result<M>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_next().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use crate::export::protobuf::{write_delimited, DelimitedReader};
+    use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+    use opentelemetry_proto::tonic::metrics::v1::{Metric, ResourceMetrics, ScopeMetrics};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_delimited_round_trip() {
+        let requests = (0..3)
+            .map(|i| ExportMetricsServiceRequest {
+                resource_metrics: vec![ResourceMetrics {
+                    scope_metrics: vec![ScopeMetrics {
+                        metrics: vec![Metric {
+                            name: format!("metric-{}", i),
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+            })
+            .collect::<Vec<_>>();
+
+        let mut buf = vec![];
+        for r in &requests {
+            write_delimited(&mut buf, r).unwrap();
+        }
+
+        let decoded = DelimitedReader::<_, ExportMetricsServiceRequest>::new(Cursor::new(buf))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(requests, decoded);
+    }
+
+    #[test]
+    fn test_max_message_size() {
+        let request = ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics::default(); 4],
+        };
+        let mut buf = vec![];
+        write_delimited(&mut buf, &request).unwrap();
+
+        let mut reader = DelimitedReader::<_, ExportMetricsServiceRequest>::new(Cursor::new(&buf))
+            .with_max_message_size(4);
+        let err = reader.read_next().unwrap_err();
+        assert!(matches!(
+            err,
+            Error::MessageTooLarge {
+                size: 8,
+                max: 4,
+                ..
+            }
+        ));
+
+        // a corrupt prefix claiming 4 GiB fails before allocating.
+        let prefix = [0xff, 0xff, 0xff, 0xff, 0x0f];
+        let mut reader =
+            DelimitedReader::<_, ExportMetricsServiceRequest>::new(Cursor::new(prefix));
+        let err = reader.read_next().unwrap_err();
+        assert!(matches!(err, Error::MessageTooLarge { .. }));
+    }
+}
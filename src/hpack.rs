@@ -0,0 +1,478 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! HPACK ([RFC 7541](https://www.rfc-editor.org/rfc/rfc7541)) coding of the per-batch
+//! headers in `BatchArrowRecords.headers`.
+//!
+//! Like in HTTP/2, the dynamic table of both sides lives as long as the OTAP stream, so each
+//! stream needs its own [Decoder] and [Encoder], and every batch of the stream must go
+//! through them in order, including batches whose headers are not used.
+
+mod huffman;
+
+use crate::error;
+use snafu::ensure;
+use std::collections::{BTreeMap, VecDeque};
+
+/// Decoded headers of one batch. Names map to their values in order of appearance.
+pub type Headers = BTreeMap<String, Vec<String>>;
+
+/// Dynamic table size used by the upstream Go implementation on both sides.
+pub const DEFAULT_TABLE_SIZE: usize = 4096;
+
+/// Static table, from RFC 7541 Appendix A. Index 1 is the first entry.
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// Headers carrying credentials, encoded as never-indexed literals so that they stay out of
+/// the dynamic tables and intermediaries don't index them either, see RFC 7541 section 7.1.3.
+const SENSITIVE_HEADERS: [&str; 2] = ["authorization", "proxy-authorization"];
+
+/// Per-entry overhead counted in the table size, see RFC 7541 section 4.1.
+const ENTRY_OVERHEAD: usize = 32;
+
+/// Dynamic table, with the most recently inserted entry first.
+#[derive(Debug)]
+struct DynamicTable {
+    entries: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl DynamicTable {
+    fn new(max_size: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            size: 0,
+            max_size,
+        }
+    }
+
+    /// Returns the entry at given index of the combined static and dynamic index space.
+    fn get(&self, index: usize) -> Option<(&str, &str)> {
+        match index {
+            0 => None,
+            1..=61 => Some(STATIC_TABLE[index - 1]),
+            _ => self
+                .entries
+                .get(index - STATIC_TABLE.len() - 1)
+                .map(|(n, v)| (n.as_str(), v.as_str())),
+        }
+    }
+
+    /// Returns the index of an entry matching name and value, or else of the first entry
+    /// matching the name, along with whether the value matched too.
+    fn find(&self, name: &str, value: &str) -> Option<(usize, bool)> {
+        let statics = STATIC_TABLE.iter().map(|(n, v)| (*n, *v));
+        let dynamics = self.entries.iter().map(|(n, v)| (n.as_str(), v.as_str()));
+        let mut name_match = None;
+        for (idx, (n, v)) in statics.chain(dynamics).enumerate() {
+            if n == name {
+                if v == value {
+                    return Some((idx + 1, true));
+                }
+                name_match.get_or_insert((idx + 1, false));
+            }
+        }
+        name_match
+    }
+
+    fn insert(&mut self, name: String, value: String) {
+        let size = name.len() + value.len() + ENTRY_OVERHEAD;
+        self.evict(self.max_size.saturating_sub(size));
+        // an entry larger than the table empties it without being inserted.
+        if size <= self.max_size {
+            self.size += size;
+            self.entries.push_front((name, value));
+        }
+    }
+
+    fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.evict(max_size);
+    }
+
+    fn evict(&mut self, target: usize) {
+        while self.size > target {
+            // safety: a non-zero size implies entries
+            let (name, value) = self.entries.pop_back().unwrap();
+            self.size -= name.len() + value.len() + ENTRY_OVERHEAD;
+        }
+    }
+}
+
+/// Stateful HPACK decoder of the headers of one OTAP stream.
+#[derive(Debug)]
+pub struct Decoder {
+    table: DynamicTable,
+    /// Upper bound of table size updates sent by the encoder.
+    max_table_size: usize,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new(DEFAULT_TABLE_SIZE)
+    }
+}
+
+impl Decoder {
+    pub fn new(max_table_size: usize) -> Self {
+        Self {
+            table: DynamicTable::new(max_table_size),
+            max_table_size,
+        }
+    }
+
+    /// Decodes the header block of the next batch of the stream.
+    pub fn decode(&mut self, mut block: &[u8]) -> error::Result<Headers> {
+        let mut headers = Headers::new();
+        while let Some(&first) = block.first() {
+            let (name, value) = if first & 0x80 != 0 {
+                // indexed header field
+                let index = decode_int(&mut block, 7)?;
+                let (name, value) = self.entry(index)?;
+                (name.to_string(), value.to_string())
+            } else if first & 0x40 != 0 {
+                // literal with incremental indexing
+                let (name, value) = self.decode_literal(&mut block, 6)?;
+                self.table.insert(name.clone(), value.clone());
+                (name, value)
+            } else if first & 0x20 != 0 {
+                let size = decode_int(&mut block, 5)?;
+                ensure!(
+                    size <= self.max_table_size,
+                    error::InvalidHpackSnafu {
+                        message: format!("table size update to {size} exceeds the maximum"),
+                    }
+                );
+                self.table.set_max_size(size);
+                continue;
+            } else {
+                // literal without indexing or never indexed
+                self.decode_literal(&mut block, 4)?
+            };
+            headers.entry(name).or_default().push(value);
+        }
+        Ok(headers)
+    }
+
+    fn entry(&self, index: usize) -> error::Result<(&str, &str)> {
+        self.table
+            .get(index)
+            .ok_or_else(|| invalid(format!("invalid table index {index}")))
+    }
+
+    fn decode_literal(&self, block: &mut &[u8], prefix: u8) -> error::Result<(String, String)> {
+        let index = decode_int(block, prefix)?;
+        let name = match index {
+            0 => decode_string(block)?,
+            _ => self.entry(index)?.0.to_string(),
+        };
+        Ok((name, decode_string(block)?))
+    }
+}
+
+/// Stateful HPACK encoder of the headers of one OTAP stream.
+#[derive(Debug)]
+pub struct Encoder {
+    table: DynamicTable,
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Self {
+            table: DynamicTable::new(DEFAULT_TABLE_SIZE),
+        }
+    }
+}
+
+impl Encoder {
+    /// Encodes the headers of the next batch of the stream. Header names should be
+    /// lowercase as in HTTP/2.
+    pub fn encode<N, V>(&mut self, headers: &[(N, V)]) -> Vec<u8>
+    where
+        N: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let mut block = vec![];
+        for (name, value) in headers {
+            let (name, value) = (name.as_ref(), value.as_ref());
+            if SENSITIVE_HEADERS.contains(&name) {
+                // literal never indexed, see RFC 7541 section 6.2.3.
+                match self.table.find(name, value) {
+                    Some((index, _)) => encode_int(&mut block, 0x10, 4, index),
+                    None => {
+                        block.push(0x10);
+                        encode_string(&mut block, name);
+                    }
+                }
+                encode_string(&mut block, value);
+                continue;
+            }
+            match self.table.find(name, value) {
+                Some((index, true)) => encode_int(&mut block, 0x80, 7, index),
+                Some((index, false)) => {
+                    encode_int(&mut block, 0x40, 6, index);
+                    encode_string(&mut block, value);
+                    self.table.insert(name.to_string(), value.to_string());
+                }
+                None => {
+                    block.push(0x40);
+                    encode_string(&mut block, name);
+                    encode_string(&mut block, value);
+                    self.table.insert(name.to_string(), value.to_string());
+                }
+            }
+        }
+        block
+    }
+}
+
+fn invalid(message: String) -> error::Error {
+    error::InvalidHpackSnafu { message }.build()
+}
+
+/// Decodes an integer with an N-bit prefix, see RFC 7541 section 5.1.
+fn decode_int(block: &mut &[u8], prefix: u8) -> error::Result<usize> {
+    let truncated = || invalid("truncated integer".to_string());
+    let (&first, rest) = block.split_first().ok_or_else(truncated)?;
+    *block = rest;
+    let mask = (1u8 << prefix) - 1;
+    let mut value = (first & mask) as usize;
+    if value < mask as usize {
+        return Ok(value);
+    }
+    let mut shift = 0;
+    loop {
+        let (&b, rest) = block.split_first().ok_or_else(truncated)?;
+        *block = rest;
+        ensure!(
+            shift < 28,
+            error::InvalidHpackSnafu {
+                message: "integer overflow",
+            }
+        );
+        value += ((b & 0x7f) as usize) << shift;
+        shift += 7;
+        if b & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn encode_int(block: &mut Vec<u8>, flags: u8, prefix: u8, mut value: usize) {
+    let mask = (1usize << prefix) - 1;
+    if value < mask {
+        block.push(flags | value as u8);
+        return;
+    }
+    block.push(flags | mask as u8);
+    value -= mask;
+    while value >= 0x80 {
+        block.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    block.push(value as u8);
+}
+
+/// Decodes a string literal, Huffman coded or not, see RFC 7541 section 5.2.
+fn decode_string(block: &mut &[u8]) -> error::Result<String> {
+    let huffman = block.first().is_some_and(|b| b & 0x80 != 0);
+    let len = decode_int(block, 7)?;
+    ensure!(
+        len <= block.len(),
+        error::InvalidHpackSnafu {
+            message: "truncated string",
+        }
+    );
+    let (raw, rest) = block.split_at(len);
+    *block = rest;
+    let bytes = match huffman {
+        true => huffman::decode(raw)?,
+        false => raw.to_vec(),
+    };
+    String::from_utf8(bytes).map_err(|_| invalid("header is not valid UTF-8".to_string()))
+}
+
+/// Encodes a string literal, Huffman coded if that is shorter.
+fn encode_string(block: &mut Vec<u8>, s: &str) {
+    let huffman_len = huffman::encoded_len(s.as_bytes());
+    if huffman_len < s.len() {
+        encode_int(block, 0x80, 7, huffman_len);
+        huffman::encode(s.as_bytes(), block);
+    } else {
+        encode_int(block, 0, 7, s.len());
+        block.extend_from_slice(s.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hpack::{Decoder, Encoder, Headers};
+
+    fn headers(fields: &[(&str, &str)]) -> Headers {
+        let mut headers = Headers::new();
+        for (name, value) in fields {
+            headers
+                .entry(name.to_string())
+                .or_default()
+                .push(value.to_string());
+        }
+        headers
+    }
+
+    fn unhex(s: &str) -> Vec<u8> {
+        let s: String = s.split_whitespace().collect();
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_decode_rfc_examples() {
+        // RFC 7541 C.4, requests with Huffman coding sharing one dynamic table.
+        let mut decoder = Decoder::default();
+        let first = decoder
+            .decode(&unhex("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff"))
+            .unwrap();
+        assert_eq!(
+            first,
+            headers(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+            ])
+        );
+        let second = decoder
+            .decode(&unhex("8286 84be 5886 a8eb 1064 9cbf"))
+            .unwrap();
+        assert_eq!(second["cache-control"], vec!["no-cache"]);
+        assert_eq!(second[":authority"], vec!["www.example.com"]);
+        let third = decoder
+            .decode(&unhex(
+                "8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf",
+            ))
+            .unwrap();
+        assert_eq!(
+            third,
+            headers(&[
+                (":method", "GET"),
+                (":scheme", "https"),
+                (":path", "/index.html"),
+                (":authority", "www.example.com"),
+                ("custom-key", "custom-value"),
+            ])
+        );
+        assert!(decoder.decode(&[0xff, 0x80]).is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut encoder = Encoder::default();
+        let mut decoder = Decoder::default();
+        let long = "v".repeat(5000);
+        let batches = [
+            vec![("authorization", "Bearer token"), ("x-tenant", "a")],
+            vec![("authorization", "Bearer token"), ("x-tenant", "b")],
+            vec![
+                ("x-tenant", "b"),
+                ("x-tenant", "c"),
+                ("x-long", long.as_str()),
+            ],
+            vec![],
+        ];
+        for fields in &batches {
+            let block = encoder.encode(fields);
+            assert_eq!(decoder.decode(&block).unwrap(), headers(fields));
+        }
+    }
+
+    #[test]
+    fn test_never_index_credentials() {
+        let mut encoder = Encoder::default();
+        let mut decoder = Decoder::default();
+        let fields = [
+            ("authorization", "Bearer token"),
+            ("proxy-authorization", "Basic secret"),
+        ];
+        for _ in 0..2 {
+            let block = encoder.encode(&fields);
+            assert_eq!(block[0] & 0xf0, 0x10);
+            assert_eq!(decoder.decode(&block).unwrap(), headers(&fields));
+        }
+        assert!(encoder.table.entries.is_empty());
+        assert!(decoder.table.entries.is_empty());
+    }
+}
@@ -0,0 +1,150 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Huffman coding of HPACK string literals (RFC 7541, section 5.2).
+
+use crate::error;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+
+/// Code of each symbol as (code, bit length), from RFC 7541 Appendix B. Symbol 256 is EOS.
+#[rustfmt::skip]
+const CODES: [(u32, u8); 257] = [
+    (0x1ff8, 13), (0x007fffd8, 23), (0x0fffffe2, 28), (0x0fffffe3, 28),
+    (0x0fffffe4, 28), (0x0fffffe5, 28), (0x0fffffe6, 28), (0x0fffffe7, 28),
+    (0x0fffffe8, 28), (0x00ffffea, 24), (0x3ffffffc, 30), (0x0fffffe9, 28),
+    (0x0fffffea, 28), (0x3ffffffd, 30), (0x0fffffeb, 28), (0x0fffffec, 28),
+    (0x0fffffed, 28), (0x0fffffee, 28), (0x0fffffef, 28), (0x0ffffff0, 28),
+    (0x0ffffff1, 28), (0x0ffffff2, 28), (0x3ffffffe, 30), (0x0ffffff3, 28),
+    (0x0ffffff4, 28), (0x0ffffff5, 28), (0x0ffffff6, 28), (0x0ffffff7, 28),
+    (0x0ffffff8, 28), (0x0ffffff9, 28), (0x0ffffffa, 28), (0x0ffffffb, 28),
+    (0x14, 6), (0x3f8, 10), (0x3f9, 10), (0xffa, 12),
+    (0x1ff9, 13), (0x15, 6), (0xf8, 8), (0x7fa, 11),
+    (0x3fa, 10), (0x3fb, 10), (0xf9, 8), (0x7fb, 11),
+    (0xfa, 8), (0x16, 6), (0x17, 6), (0x18, 6),
+    (0x0, 5), (0x1, 5), (0x2, 5), (0x19, 6),
+    (0x1a, 6), (0x1b, 6), (0x1c, 6), (0x1d, 6),
+    (0x1e, 6), (0x1f, 6), (0x5c, 7), (0xfb, 8),
+    (0x7ffc, 15), (0x20, 6), (0xffb, 12), (0x3fc, 10),
+    (0x1ffa, 13), (0x21, 6), (0x5d, 7), (0x5e, 7),
+    (0x5f, 7), (0x60, 7), (0x61, 7), (0x62, 7),
+    (0x63, 7), (0x64, 7), (0x65, 7), (0x66, 7),
+    (0x67, 7), (0x68, 7), (0x69, 7), (0x6a, 7),
+    (0x6b, 7), (0x6c, 7), (0x6d, 7), (0x6e, 7),
+    (0x6f, 7), (0x70, 7), (0x71, 7), (0x72, 7),
+    (0xfc, 8), (0x73, 7), (0xfd, 8), (0x1ffb, 13),
+    (0x7fff0, 19), (0x1ffc, 13), (0x3ffc, 14), (0x22, 6),
+    (0x7ffd, 15), (0x3, 5), (0x23, 6), (0x4, 5),
+    (0x24, 6), (0x5, 5), (0x25, 6), (0x26, 6),
+    (0x27, 6), (0x6, 5), (0x74, 7), (0x75, 7),
+    (0x28, 6), (0x29, 6), (0x2a, 6), (0x7, 5),
+    (0x2b, 6), (0x76, 7), (0x2c, 6), (0x8, 5),
+    (0x9, 5), (0x2d, 6), (0x77, 7), (0x78, 7),
+    (0x79, 7), (0x7a, 7), (0x7b, 7), (0x7ffe, 15),
+    (0x7fc, 11), (0x3ffd, 14), (0x1ffd, 13), (0x0ffffffc, 28),
+    (0xfffe6, 20), (0x003fffd2, 22), (0xfffe7, 20), (0xfffe8, 20),
+    (0x003fffd3, 22), (0x003fffd4, 22), (0x003fffd5, 22), (0x007fffd9, 23),
+    (0x003fffd6, 22), (0x007fffda, 23), (0x007fffdb, 23), (0x007fffdc, 23),
+    (0x007fffdd, 23), (0x007fffde, 23), (0x00ffffeb, 24), (0x007fffdf, 23),
+    (0x00ffffec, 24), (0x00ffffed, 24), (0x003fffd7, 22), (0x007fffe0, 23),
+    (0x00ffffee, 24), (0x007fffe1, 23), (0x007fffe2, 23), (0x007fffe3, 23),
+    (0x007fffe4, 23), (0x001fffdc, 21), (0x003fffd8, 22), (0x007fffe5, 23),
+    (0x003fffd9, 22), (0x007fffe6, 23), (0x007fffe7, 23), (0x00ffffef, 24),
+    (0x003fffda, 22), (0x001fffdd, 21), (0xfffe9, 20), (0x003fffdb, 22),
+    (0x003fffdc, 22), (0x007fffe8, 23), (0x007fffe9, 23), (0x001fffde, 21),
+    (0x007fffea, 23), (0x003fffdd, 22), (0x003fffde, 22), (0x00fffff0, 24),
+    (0x001fffdf, 21), (0x003fffdf, 22), (0x007fffeb, 23), (0x007fffec, 23),
+    (0x001fffe0, 21), (0x001fffe1, 21), (0x003fffe0, 22), (0x001fffe2, 21),
+    (0x007fffed, 23), (0x003fffe1, 22), (0x007fffee, 23), (0x007fffef, 23),
+    (0xfffea, 20), (0x003fffe2, 22), (0x003fffe3, 22), (0x003fffe4, 22),
+    (0x007ffff0, 23), (0x003fffe5, 22), (0x003fffe6, 22), (0x007ffff1, 23),
+    (0x03ffffe0, 26), (0x03ffffe1, 26), (0xfffeb, 20), (0x7fff1, 19),
+    (0x003fffe7, 22), (0x007ffff2, 23), (0x003fffe8, 22), (0x01ffffec, 25),
+    (0x03ffffe2, 26), (0x03ffffe3, 26), (0x03ffffe4, 26), (0x07ffffde, 27),
+    (0x07ffffdf, 27), (0x03ffffe5, 26), (0x00fffff1, 24), (0x01ffffed, 25),
+    (0x7fff2, 19), (0x001fffe3, 21), (0x03ffffe6, 26), (0x07ffffe0, 27),
+    (0x07ffffe1, 27), (0x03ffffe7, 26), (0x07ffffe2, 27), (0x00fffff2, 24),
+    (0x001fffe4, 21), (0x001fffe5, 21), (0x03ffffe8, 26), (0x03ffffe9, 26),
+    (0x0ffffffd, 28), (0x07ffffe3, 27), (0x07ffffe4, 27), (0x07ffffe5, 27),
+    (0xfffec, 20), (0x00fffff3, 24), (0xfffed, 20), (0x001fffe6, 21),
+    (0x003fffe9, 22), (0x001fffe7, 21), (0x001fffe8, 21), (0x007ffff3, 23),
+    (0x003fffea, 22), (0x003fffeb, 22), (0x01ffffee, 25), (0x01ffffef, 25),
+    (0x00fffff4, 24), (0x00fffff5, 24), (0x03ffffea, 26), (0x007ffff4, 23),
+    (0x03ffffeb, 26), (0x07ffffe6, 27), (0x03ffffec, 26), (0x03ffffed, 26),
+    (0x07ffffe7, 27), (0x07ffffe8, 27), (0x07ffffe9, 27), (0x07ffffea, 27),
+    (0x07ffffeb, 27), (0x0ffffffe, 28), (0x07ffffec, 27), (0x07ffffed, 27),
+    (0x07ffffee, 27), (0x07ffffef, 27), (0x07fffff0, 27), (0x03ffffee, 26),
+    (0x3fffffff, 30),
+];
+
+const EOS: u16 = 256;
+
+lazy_static! {
+    static ref SYMBOLS: HashMap<(u32, u8), u16> = CODES
+        .iter()
+        .enumerate()
+        .map(|(sym, code)| (*code, sym as u16))
+        .collect();
+}
+
+/// Returns the length of the Huffman encoding of `src` in bytes.
+pub(super) fn encoded_len(src: &[u8]) -> usize {
+    let bits: usize = src.iter().map(|b| CODES[*b as usize].1 as usize).sum();
+    bits.div_ceil(8)
+}
+
+pub(super) fn encode(src: &[u8], dst: &mut Vec<u8>) {
+    let mut acc: u64 = 0;
+    let mut bits = 0;
+    for b in src {
+        let (code, len) = CODES[*b as usize];
+        acc = (acc << len) | code as u64;
+        bits += len as u32;
+        while bits >= 8 {
+            bits -= 8;
+            dst.push((acc >> bits) as u8);
+        }
+    }
+    if bits > 0 {
+        // pads with the most significant bits of EOS, i.e. ones.
+        dst.push(((acc << (8 - bits)) as u8) | (0xff >> bits));
+    }
+}
+
+pub(super) fn decode(src: &[u8]) -> error::Result<Vec<u8>> {
+    let mut dst = Vec::with_capacity(src.len() * 8 / 5);
+    let (mut code, mut len) = (0u32, 0u8);
+    for byte in src {
+        for shift in (0..8).rev() {
+            code = (code << 1) | ((byte >> shift) & 1) as u32;
+            len += 1;
+            match SYMBOLS.get(&(code, len)) {
+                Some(&EOS) => return invalid("EOS in Huffman string"),
+                Some(&sym) => {
+                    dst.push(sym as u8);
+                    (code, len) = (0, 0);
+                }
+                None if len >= 30 => return invalid("invalid Huffman code"),
+                None => {}
+            }
+        }
+    }
+    // padding must be shorter than a byte and a prefix of EOS.
+    if len >= 8 || code != (1 << len) - 1 {
+        return invalid("invalid Huffman padding");
+    }
+    Ok(dst)
+}
+
+fn invalid<T>(message: &str) -> error::Result<T> {
+    error::InvalidHpackSnafu { message }.fail()
+}
@@ -15,11 +15,26 @@
 
 #[allow(dead_code)]
 pub(crate) mod arrays;
+pub mod batching;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod compare;
 mod decode;
+mod encode;
 mod error;
+pub mod export;
+pub mod hpack;
 mod otlp;
+pub mod processor;
+pub mod prometheus;
+pub mod replay;
 #[allow(dead_code)]
 mod schema;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "trace")]
+pub mod telemetry;
+pub mod temporality;
 #[cfg(test)]
 mod test_util;
 
@@ -27,12 +42,16 @@ mod test_util;
 pub mod opentelemetry {
     pub use proto::*;
     pub mod proto {
+        #[cfg(feature = "client")]
         pub use crate::opentelemetry::proto::arrow::arrow_metrics_service_client as metrics_client;
+        #[cfg(feature = "server")]
         pub use crate::opentelemetry::proto::arrow::arrow_metrics_service_server as metrics_server;
         pub use crate::opentelemetry::proto::arrow::{
-            ArrowPayload, ArrowPayloadType, BatchArrowRecords, BatchStatus,
+            ArrowPayload, ArrowPayloadType, BatchArrowRecords, BatchStatus, StatusCode,
         };
+        #[cfg(feature = "client")]
         pub use metrics_client::ArrowMetricsServiceClient;
+        #[cfg(feature = "server")]
         pub use metrics_server::{ArrowMetricsService, ArrowMetricsServiceServer};
 
         #[allow(clippy::all)]
@@ -41,4 +60,14 @@ pub mod opentelemetry {
     }
 }
 
-pub use decode::decoder::Consumer;
+pub use decode::decoder::{decode_metrics, decode_metrics_with, Consumer};
+pub use decode::filter::{MetricFilter, MetricMatcher};
+pub use decode::record_message::RecordMessage;
+pub use decode::signal::{SignalRequest, SignalType};
+pub use decode::stats::{BatchStats, PayloadStats, StreamDictionaries};
+pub use encode::encoder::Producer;
+pub use encode::metric::{encode_metrics, encode_metrics_with};
+pub use encode::sort::{AttributeOrder, DataPointOrder, SortStrategy};
+pub use error::{Error, Result};
+pub use otlp::metric::{regroup_metrics, MetricType, MetricsVisitor};
+pub use schema::schema_id;
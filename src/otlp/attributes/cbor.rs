@@ -0,0 +1,395 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! CBOR serialization of map and slice attribute values stored in the `ser` column.
+//!
+//! otel-arrow serializes the raw Go representation of the value, so maps are CBOR maps with
+//! text keys, slices are CBOR arrays, and empty values are `null`.
+//! See https://github.com/open-telemetry/otel-arrow/blob/985aa1500a012859cec44855e187eacf46eda7c8/pkg/otel/common/arrow/attributes.go#L318
+
+use crate::error;
+use opentelemetry_proto::tonic::common::v1::any_value::Value;
+use opentelemetry_proto::tonic::common::v1::{AnyValue, ArrayValue, KeyValue, KeyValueList};
+use snafu::{ensure, OptionExt};
+
+const MAJOR_UNSIGNED: u8 = 0;
+const MAJOR_NEGATIVE: u8 = 1;
+const MAJOR_BYTES: u8 = 2;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_ARRAY: u8 = 4;
+const MAJOR_MAP: u8 = 5;
+const MAJOR_TAG: u8 = 6;
+const MAJOR_SIMPLE: u8 = 7;
+
+const FALSE: u8 = 20;
+const TRUE: u8 = 21;
+const NULL: u8 = 22;
+const UNDEFINED: u8 = 23;
+const FLOAT16: u8 = 25;
+const FLOAT32: u8 = 26;
+const FLOAT64: u8 = 27;
+const BREAK: u8 = 31;
+
+/// Nesting limit of decoded values, protecting against stack overflow on hostile input.
+const MAX_DEPTH: usize = 64;
+
+/// Serializes an attribute value to CBOR.
+pub fn encode(value: &AnyValue) -> Vec<u8> {
+    let mut buf = Vec::new();
+    encode_any_value(value, &mut buf);
+    buf
+}
+
+/// Deserializes an attribute value written by [encode] or by otel-arrow.
+pub fn decode(bytes: &[u8]) -> error::Result<AnyValue> {
+    let mut decoder = Decoder { bytes, pos: 0 };
+    let value = decoder.any_value(0)?;
+    ensure!(
+        decoder.pos == bytes.len(),
+        error::InvalidSerializedValueSnafu {
+            message: format!("{} trailing bytes", bytes.len() - decoder.pos),
+        }
+    );
+    Ok(value)
+}
+
+fn encode_any_value(value: &AnyValue, buf: &mut Vec<u8>) {
+    let Some(value) = &value.value else {
+        buf.push(MAJOR_SIMPLE << 5 | NULL);
+        return;
+    };
+    match value {
+        Value::StringValue(s) => {
+            write_head(MAJOR_TEXT, s.len() as u64, buf);
+            buf.extend_from_slice(s.as_bytes());
+        }
+        Value::BoolValue(b) => buf.push(MAJOR_SIMPLE << 5 | if *b { TRUE } else { FALSE }),
+        Value::IntValue(i) if *i >= 0 => write_head(MAJOR_UNSIGNED, *i as u64, buf),
+        Value::IntValue(i) => write_head(MAJOR_NEGATIVE, !(*i) as u64, buf),
+        Value::DoubleValue(d) => {
+            buf.push(MAJOR_SIMPLE << 5 | FLOAT64);
+            buf.extend_from_slice(&d.to_be_bytes());
+        }
+        Value::ArrayValue(array) => {
+            write_head(MAJOR_ARRAY, array.values.len() as u64, buf);
+            for v in &array.values {
+                encode_any_value(v, buf);
+            }
+        }
+        Value::KvlistValue(kvs) => {
+            write_head(MAJOR_MAP, kvs.values.len() as u64, buf);
+            for kv in &kvs.values {
+                write_head(MAJOR_TEXT, kv.key.len() as u64, buf);
+                buf.extend_from_slice(kv.key.as_bytes());
+                match &kv.value {
+                    Some(v) => encode_any_value(v, buf),
+                    None => buf.push(MAJOR_SIMPLE << 5 | NULL),
+                }
+            }
+        }
+        Value::BytesValue(b) => {
+            write_head(MAJOR_BYTES, b.len() as u64, buf);
+            buf.extend_from_slice(b);
+        }
+    }
+}
+
+fn write_head(major: u8, arg: u64, buf: &mut Vec<u8>) {
+    let major = major << 5;
+    match arg {
+        0..=23 => buf.push(major | arg as u8),
+        24..=0xff => buf.extend_from_slice(&[major | 24, arg as u8]),
+        0x100..=0xffff => {
+            buf.push(major | 25);
+            buf.extend_from_slice(&(arg as u16).to_be_bytes());
+        }
+        0x10000..=0xffff_ffff => {
+            buf.push(major | 26);
+            buf.extend_from_slice(&(arg as u32).to_be_bytes());
+        }
+        _ => {
+            buf.push(major | 27);
+            buf.extend_from_slice(&arg.to_be_bytes());
+        }
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> error::Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .context(error::InvalidSerializedValueSnafu {
+                message: "unexpected end of input",
+            })?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn peek_break(&mut self) -> bool {
+        if self.bytes.get(self.pos) == Some(&(MAJOR_SIMPLE << 5 | BREAK)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Reads the initial byte and argument of a data item. The argument is `None` for
+    /// indefinite length items.
+    fn head(&mut self) -> error::Result<(u8, u8, Option<u64>)> {
+        let initial = self.take(1)?[0];
+        let (major, info) = (initial >> 5, initial & 0x1f);
+        let arg = match info {
+            0..=23 => Some(info as u64),
+            24 => Some(self.take(1)?[0] as u64),
+            25 => Some(u16::from_be_bytes(self.take(2)?.try_into().unwrap()) as u64),
+            26 => Some(u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as u64),
+            27 => Some(u64::from_be_bytes(self.take(8)?.try_into().unwrap())),
+            31 => None,
+            _ => {
+                return error::InvalidSerializedValueSnafu {
+                    message: format!("reserved additional info {}", info),
+                }
+                .fail()
+            }
+        };
+        Ok((major, info, arg))
+    }
+
+    fn len(&self, arg: u64) -> error::Result<usize> {
+        // every item takes at least one byte, which bounds pre-allocations.
+        let len = usize::try_from(arg).unwrap_or(usize::MAX);
+        ensure!(
+            len <= self.bytes.len() - self.pos,
+            error::InvalidSerializedValueSnafu {
+                message: format!("length {} exceeds input", arg),
+            }
+        );
+        Ok(len)
+    }
+
+    /// Reads a byte or text string, concatenating indefinite length chunks.
+    fn string(&mut self, major: u8, arg: Option<u64>) -> error::Result<Vec<u8>> {
+        match arg {
+            Some(len) => {
+                let len = self.len(len)?;
+                Ok(self.take(len)?.to_vec())
+            }
+            None => {
+                let mut buf = vec![];
+                while !self.peek_break() {
+                    let (chunk_major, _, chunk_len) = self.head()?;
+                    let chunk_len = chunk_len.filter(|_| chunk_major == major).context(
+                        error::InvalidSerializedValueSnafu {
+                            message: "invalid indefinite length string chunk",
+                        },
+                    )?;
+                    let chunk_len = self.len(chunk_len)?;
+                    buf.extend_from_slice(self.take(chunk_len)?);
+                }
+                Ok(buf)
+            }
+        }
+    }
+
+    fn text(&mut self, arg: Option<u64>) -> error::Result<String> {
+        String::from_utf8(self.string(MAJOR_TEXT, arg)?)
+            .ok()
+            .context(error::InvalidSerializedValueSnafu {
+                message: "invalid utf-8 text",
+            })
+    }
+
+    /// Calls `f` for each item of an array or map with given length argument.
+    fn items(
+        &mut self,
+        arg: Option<u64>,
+        mut f: impl FnMut(&mut Self) -> error::Result<()>,
+    ) -> error::Result<()> {
+        match arg {
+            Some(len) => {
+                for _ in 0..self.len(len)? {
+                    f(self)?;
+                }
+            }
+            None => {
+                while !self.peek_break() {
+                    f(self)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn any_value(&mut self, depth: usize) -> error::Result<AnyValue> {
+        ensure!(
+            depth < MAX_DEPTH,
+            error::InvalidSerializedValueSnafu {
+                message: "value nested too deeply",
+            }
+        );
+        let (major, info, arg) = self.head()?;
+        let value = match major {
+            MAJOR_UNSIGNED | MAJOR_NEGATIVE => {
+                let arg = arg.context(error::InvalidSerializedValueSnafu {
+                    message: "indefinite length integer",
+                })?;
+                let value =
+                    i64::try_from(arg)
+                        .ok()
+                        .context(error::InvalidSerializedValueSnafu {
+                            message: format!("integer {} out of range", arg),
+                        })?;
+                Some(Value::IntValue(if major == MAJOR_NEGATIVE {
+                    !value
+                } else {
+                    value
+                }))
+            }
+            MAJOR_BYTES => Some(Value::BytesValue(self.string(MAJOR_BYTES, arg)?)),
+            MAJOR_TEXT => Some(Value::StringValue(self.text(arg)?)),
+            MAJOR_ARRAY => {
+                let mut values = vec![];
+                self.items(arg, |d| {
+                    values.push(d.any_value(depth + 1)?);
+                    Ok(())
+                })?;
+                Some(Value::ArrayValue(ArrayValue { values }))
+            }
+            MAJOR_MAP => {
+                let mut values = vec![];
+                self.items(arg, |d| {
+                    let (key_major, _, key_arg) = d.head()?;
+                    ensure!(
+                        key_major == MAJOR_TEXT,
+                        error::InvalidSerializedValueSnafu {
+                            message: format!("unsupported map key type {}", key_major),
+                        }
+                    );
+                    let key = d.text(key_arg)?;
+                    let value = d.any_value(depth + 1)?;
+                    values.push(KeyValue {
+                        key,
+                        value: Some(value),
+                    });
+                    Ok(())
+                })?;
+                Some(Value::KvlistValue(KeyValueList { values }))
+            }
+            // Tags carry no meaning for attribute values, decode the tagged item.
+            MAJOR_TAG => return self.any_value(depth + 1),
+            _ => match (info, arg) {
+                (FALSE, _) => Some(Value::BoolValue(false)),
+                (TRUE, _) => Some(Value::BoolValue(true)),
+                (NULL | UNDEFINED, _) => None,
+                (FLOAT16, Some(bits)) => Some(Value::DoubleValue(f16_to_f64(bits as u16))),
+                (FLOAT32, Some(bits)) => {
+                    Some(Value::DoubleValue(f32::from_bits(bits as u32) as f64))
+                }
+                (FLOAT64, Some(bits)) => Some(Value::DoubleValue(f64::from_bits(bits))),
+                _ => {
+                    return error::InvalidSerializedValueSnafu {
+                        message: format!("unsupported simple value {}", info),
+                    }
+                    .fail()
+                }
+            },
+        };
+        Ok(AnyValue { value })
+    }
+}
+
+fn f16_to_f64(bits: u16) -> f64 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f64;
+    sign * match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f64.powi(exponent - 15),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::otlp::attributes::cbor::{decode, encode};
+    use opentelemetry_proto::tonic::common::v1::any_value::Value;
+    use opentelemetry_proto::tonic::common::v1::{AnyValue, ArrayValue, KeyValue, KeyValueList};
+
+    fn any(value: Value) -> AnyValue {
+        AnyValue { value: Some(value) }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let value = any(Value::KvlistValue(KeyValueList {
+            values: vec![
+                KeyValue {
+                    key: "str".to_string(),
+                    value: Some(any(Value::StringValue("a".repeat(300)))),
+                },
+                KeyValue {
+                    key: "list".to_string(),
+                    value: Some(any(Value::ArrayValue(ArrayValue {
+                        values: vec![
+                            any(Value::IntValue(i64::MIN)),
+                            any(Value::IntValue(i64::MAX)),
+                            any(Value::DoubleValue(-1.5)),
+                            any(Value::BoolValue(true)),
+                            any(Value::BytesValue(vec![0, 1, 2])),
+                            AnyValue { value: None },
+                        ],
+                    }))),
+                },
+            ],
+        }));
+        assert_eq!(value, decode(&encode(&value)).unwrap());
+    }
+
+    #[test]
+    fn test_decode() {
+        // {"a": [1, -2, 1.5 (f16)], "b": h'ff'} with indefinite length array.
+        let bytes = [
+            0xa2, 0x61, b'a', 0x9f, 0x01, 0x21, 0xf9, 0x3e, 0x00, 0xff, 0x61, b'b', 0x41, 0xff,
+        ];
+        let expected = any(Value::KvlistValue(KeyValueList {
+            values: vec![
+                KeyValue {
+                    key: "a".to_string(),
+                    value: Some(any(Value::ArrayValue(ArrayValue {
+                        values: vec![
+                            any(Value::IntValue(1)),
+                            any(Value::IntValue(-2)),
+                            any(Value::DoubleValue(1.5)),
+                        ],
+                    }))),
+                },
+                KeyValue {
+                    key: "b".to_string(),
+                    value: Some(any(Value::BytesValue(vec![0xff]))),
+                },
+            ],
+        }));
+        assert_eq!(expected, decode(&bytes).unwrap());
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decode(&[0x9b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).is_err());
+    }
+}
@@ -18,9 +18,11 @@ use arrow::array::{UInt16Array, UInt32Array};
 use arrow::datatypes::DataType;
 use num_enum::TryFromPrimitive;
 use std::hash::Hash;
-use std::ops::{Add, AddAssign};
+use std::ops::{Add, AddAssign, Sub};
 
-pub trait ParentId: Copy + Hash + Eq + Default + Add<Output = Self> + AddAssign {
+pub trait ParentId:
+    Copy + Hash + Eq + Default + Add<Output = Self> + AddAssign + Sub<Output = Self>
+{
     type Array: NullableArrayAccessor<Native = Self> + 'static;
 
     fn arrow_data_type() -> DataType;
@@ -15,6 +15,7 @@ use crate::arrays::{
     NullableArrayAccessor, StringArrayAccessor,
 };
 use crate::error;
+use crate::otlp::attributes::cbor;
 use crate::otlp::attributes::parent_id::ParentId;
 use crate::schema::consts;
 use arrow::array::{Array, RecordBatch};
@@ -72,87 +73,109 @@ where
 
     fn try_from(rb: &RecordBatch) -> Result<Self, Self::Error> {
         let mut store = Self::default();
-
-        let key_arr = rb
-            .column_by_name(consts::ATTRIBUTE_KEY)
-            .map(StringArrayAccessor::new)
-            .transpose()?;
-        let value_type_arr = get_u8_array(rb, consts::ATTRIBUTE_TYPE)?;
-
-        let value_str_arr = StringArrayAccessor::new(
-            rb.column_by_name(consts::ATTRIBUTE_STR)
-                .context(error::ColumnNotFoundSnafu {
-                    name: consts::ATTRIBUTE_STR,
-                })?,
-        )?;
-
-        let value_int_arr = get_i64_array_opt(rb, consts::ATTRIBUTE_INT)?;
-        let value_double_arr = get_f64_array_opt(rb, consts::ATTRIBUTE_DOUBLE)?;
-        let value_bool_arr = get_bool_array_opt(rb, consts::ATTRIBUTE_BOOL)?;
-        let value_bytes_arr = get_binary_array_opt(rb, consts::ATTRIBUTE_BYTES)?;
-
-        for idx in 0..rb.num_rows() {
-            let key = key_arr.value_at_or_default(idx);
-            let value_type = AttributeValueType::try_from(value_type_arr.value_at_or_default(idx))
-                .context(error::UnrecognizedAttributeValueTypeSnafu)?;
-            let value = match value_type {
-                AttributeValueType::Str => {
-                    Value::StringValue(value_str_arr.value_at(idx).unwrap_or_default())
-                }
-                AttributeValueType::Int => Value::IntValue(value_int_arr.value_at_or_default(idx)),
-                AttributeValueType::Double => {
-                    Value::DoubleValue(value_double_arr.value_at_or_default(idx))
-                }
-                AttributeValueType::Bool => {
-                    Value::BoolValue(value_bool_arr.value_at_or_default(idx))
-                }
-                AttributeValueType::Bytes => {
-                    Value::BytesValue(value_bytes_arr.value_at_or_default(idx))
-                }
-                AttributeValueType::Slice => {
-                    // todo: support deserialize [any_value::Value::ArrayValue]
-                    return error::UnsupportedAttributeValueSnafu { type_name: "slice" }.fail();
-                }
-                AttributeValueType::Map => {
-                    // todo: support deserialize [any_value::Value::KvlistValue]
-                    return error::UnsupportedAttributeValueSnafu { type_name: "map" }.fail();
-                }
-                AttributeValueType::Empty => {
-                    // should warn here.
-                    continue;
-                }
-            };
-
-            // Parse potentially delta encoded parent id field.
-            let parent_id_arr =
-                rb.column_by_name(consts::PARENT_ID)
-                    .context(error::ColumnNotFoundSnafu {
-                        name: consts::PARENT_ID,
-                    })?;
-            let parent_id_arr = parent_id_arr.as_any().downcast_ref::<T::Array>().context(
-                error::ColumnDataTypeMismatchSnafu {
-                    name: consts::PARENT_ID,
-                    expect: T::arrow_data_type(),
-                    actual: parent_id_arr.data_type().clone(),
-                },
-            )?;
-            // Curious, but looks like this is not used anywhere in otel-arrow
-            // See https://github.com/open-telemetry/otel-arrow/blob/985aa1500a012859cec44855e187eacf46eda7c8/pkg/otel/common/otlp/attributes.go#L134
-            let _delta_encoded = is_delta_encoded(rb.schema_ref());
-            let mut parent_id_decoder = T::new_decoder();
-
-            let parent_id =
-                parent_id_decoder.decode(parent_id_arr.value_at_or_default(idx), &key, &value);
+        for_each_attribute(rb, |parent_id: T, key, value| {
             let attributes = store.attribute_by_ids.entry(parent_id).or_default();
-            //todo: support assigning ArrayValue and KvListValue by deep copy as in https://github.com/open-telemetry/opentelemetry-collector/blob/fbf6d103eea79e72ff6b2cc3a2a18fc98a836281/pdata/pcommon/value.go#L323
             *attributes.find_or_append(&key) = Some(AnyValue { value: Some(value) });
-        }
-
+        })?;
         Ok(store)
     }
 }
 
-trait FindOrAppendValue<V> {
+/// Calls `f` with the decoded parent id, key and value of every row of an attributes record,
+/// skipping empty values.
+pub(crate) fn for_each_attribute<T>(
+    rb: &RecordBatch,
+    mut f: impl FnMut(T, String, Value),
+) -> error::Result<()>
+where
+    T: ParentId,
+    <T as ParentId>::Array: Array,
+{
+    for_each_attribute_row(rb, |_, parent_id, key, value| f(parent_id, key, value))
+}
+
+/// Like [for_each_attribute], also passing the index of the row.
+pub(crate) fn for_each_attribute_row<T>(
+    rb: &RecordBatch,
+    mut f: impl FnMut(usize, T, String, Value),
+) -> error::Result<()>
+where
+    T: ParentId,
+    <T as ParentId>::Array: Array,
+{
+    let key_arr = rb
+        .column_by_name(consts::ATTRIBUTE_KEY)
+        .map(StringArrayAccessor::new)
+        .transpose()?;
+    let value_type_arr = get_u8_array(rb, consts::ATTRIBUTE_TYPE)?;
+
+    let value_str_arr = rb
+        .column_by_name(consts::ATTRIBUTE_STR)
+        .map(StringArrayAccessor::new)
+        .transpose()?;
+
+    let value_int_arr = get_i64_array_opt(rb, consts::ATTRIBUTE_INT)?;
+    let value_double_arr = get_f64_array_opt(rb, consts::ATTRIBUTE_DOUBLE)?;
+    let value_bool_arr = get_bool_array_opt(rb, consts::ATTRIBUTE_BOOL)?;
+    let value_bytes_arr = get_binary_array_opt(rb, consts::ATTRIBUTE_BYTES)?;
+    let value_ser_arr = get_binary_array_opt(rb, consts::ATTRIBUTE_SER)?;
+
+    // Parse potentially delta encoded parent id field.
+    let parent_id_arr =
+        rb.column_by_name(consts::PARENT_ID)
+            .context(error::ColumnNotFoundSnafu {
+                name: consts::PARENT_ID,
+            })?;
+    let parent_id_arr = parent_id_arr.as_any().downcast_ref::<T::Array>().context(
+        error::ColumnDataTypeMismatchSnafu {
+            name: consts::PARENT_ID,
+            expect: T::arrow_data_type(),
+            actual: parent_id_arr.data_type().clone(),
+        },
+    )?;
+    // Curious, but looks like this is not used anywhere in otel-arrow
+    // See https://github.com/open-telemetry/otel-arrow/blob/985aa1500a012859cec44855e187eacf46eda7c8/pkg/otel/common/otlp/attributes.go#L134
+    let _delta_encoded = is_delta_encoded(rb.schema_ref());
+    let mut parent_id_decoder = T::new_decoder();
+
+    for idx in 0..rb.num_rows() {
+        let key = key_arr.value_at_or_default(idx);
+        let value_type = AttributeValueType::try_from(value_type_arr.value_at_or_default(idx))
+            .context(error::UnrecognizedAttributeValueTypeSnafu { row: idx })?;
+        let value = match value_type {
+            AttributeValueType::Str => Value::StringValue(value_str_arr.value_at_or_default(idx)),
+            AttributeValueType::Int => Value::IntValue(value_int_arr.value_at_or_default(idx)),
+            AttributeValueType::Double => {
+                Value::DoubleValue(value_double_arr.value_at_or_default(idx))
+            }
+            AttributeValueType::Bool => Value::BoolValue(value_bool_arr.value_at_or_default(idx)),
+            AttributeValueType::Bytes => {
+                Value::BytesValue(value_bytes_arr.value_at_or_default(idx))
+            }
+            AttributeValueType::Slice | AttributeValueType::Map => {
+                let value_ser_arr = value_ser_arr.context(error::ColumnNotFoundSnafu {
+                    name: consts::ATTRIBUTE_SER,
+                })?;
+                match cbor::decode(&value_ser_arr.value_at_or_default(idx))?.value {
+                    Some(value) => value,
+                    None => continue,
+                }
+            }
+            AttributeValueType::Empty => {
+                // should warn here.
+                continue;
+            }
+        };
+
+        let parent_id =
+            parent_id_decoder.decode(parent_id_arr.value_at_or_default(idx), &key, &value);
+        f(idx, parent_id, key, value);
+    }
+
+    Ok(())
+}
+
+pub(crate) trait FindOrAppendValue<V> {
     /// Finds a value with given key and returns the mutable reference to that value.
     /// Appends a new value if not found and return mutable reference to that newly created value.
     fn find_or_append(&mut self, key: &str) -> &mut V;
@@ -30,6 +30,12 @@ where
     pub fn get_or_default(&mut self, key: u16) -> &mut Vec<T> {
         self.data_point_by_id.entry(key).or_default()
     }
+
+    /// Number of data points still held by the store.
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.data_point_by_id.values().map(Vec::len).sum()
+    }
 }
 
 pub type NumberDataPointsStore = DataPointStore<NumberDataPoint>;
@@ -25,12 +25,14 @@ use arrow::array::{Array, Int32Array, ListArray, RecordBatch, StructArray};
 use arrow::datatypes::{DataType, Field, FieldRef, Fields, UInt64Type};
 use opentelemetry_proto::tonic::metrics::v1::exponential_histogram_data_point::Buckets;
 use snafu::OptionExt;
+use std::collections::HashSet;
 
 impl EHistogramDataPointsStore {
     pub fn from_record_batch(
         rb: &RecordBatch,
         exemplar_store: &mut ExemplarsStore,
         attr_store: &Attribute32Store,
+        excluded_metric_ids: &HashSet<u16>,
     ) -> error::Result<Self> {
         let mut store = Self::default();
 
@@ -58,6 +60,10 @@ impl EHistogramDataPointsStore {
             let delta = delta_arr.value_at_or_default(idx);
             let parent_id = prev_parent_id + delta;
             prev_parent_id = parent_id;
+            if excluded_metric_ids.contains(&parent_id) {
+                last_id += id_arr_opt.value_at_or_default(idx);
+                continue;
+            }
             let ehdps = store.get_or_default(parent_id);
             let hdp = ehdps.append_and_get();
             hdp.start_time_unix_nano = start_time_unix_nano.value_at_or_default(idx) as u64;
@@ -25,6 +25,7 @@ use arrow::datatypes::{
     ArrowNativeType, ArrowPrimitiveType, DataType, Field, FieldRef, Float64Type, UInt64Type,
 };
 use snafu::OptionExt;
+use std::collections::HashSet;
 
 impl HistogramDataPointsStore {
     // See https://github.com/open-telemetry/otel-arrow/blob/985aa1500a012859cec44855e187eacf46eda7c8/pkg/otel/metrics/otlp/histogram.go#L139
@@ -32,6 +33,7 @@ impl HistogramDataPointsStore {
         rb: &RecordBatch,
         exemplar_store: &mut ExemplarsStore,
         attrs_store: &Attribute32Store,
+        excluded_metric_ids: &HashSet<u16>,
     ) -> error::Result<HistogramDataPointsStore> {
         let mut store = HistogramDataPointsStore::default();
 
@@ -66,6 +68,10 @@ impl HistogramDataPointsStore {
             let delta = delta_id.value_at_or_default(idx);
             let parent_id = prev_parent_id + delta;
             prev_parent_id = parent_id;
+            if excluded_metric_ids.contains(&parent_id) {
+                last_id += id_array_opt.value_at_or_default(idx);
+                continue;
+            }
 
             // Creates a new HistogramDataPoint and append to the list.
             let hdps = store.get_or_default(parent_id).append_and_get();
@@ -22,6 +22,7 @@ use crate::schema::consts;
 use arrow::array::RecordBatch;
 use opentelemetry_proto::tonic::metrics::v1::number_data_point::Value;
 use opentelemetry_proto::tonic::metrics::v1::NumberDataPoint;
+use std::collections::HashSet;
 
 impl NumberDataPointsStore {
     /// Ref: https://github.com/open-telemetry/otel-arrow/blob/985aa1500a012859cec44855e187eacf46eda7c8/pkg/otel/metrics/otlp/number_data_point.go#L110
@@ -29,6 +30,7 @@ impl NumberDataPointsStore {
         rb: &RecordBatch,
         exemplar_store: &mut ExemplarsStore,
         attribute_store: &Attribute32Store,
+        excluded_metric_ids: &HashSet<u16>,
     ) -> Result<NumberDataPointsStore> {
         let mut store = NumberDataPointsStore::default();
 
@@ -52,6 +54,10 @@ impl NumberDataPointsStore {
             let delta = parent_id_array.value_at(idx).unwrap_or_default();
             let parent_id = prev_parent_id + delta;
             prev_parent_id = parent_id;
+            if excluded_metric_ids.contains(&parent_id) {
+                last_id += id.unwrap_or_default();
+                continue;
+            }
 
             let nbdps = store.get_or_default(parent_id);
             let mut nbdp = NumberDataPoint {
@@ -22,12 +22,14 @@ use crate::schema::consts;
 use arrow::array::{Array, ArrayRef, Float64Array, ListArray, RecordBatch, StructArray};
 use opentelemetry_proto::tonic::metrics::v1::summary_data_point::ValueAtQuantile;
 use snafu::OptionExt;
+use std::collections::HashSet;
 
 impl SummaryDataPointsStore {
     // see https://github.com/open-telemetry/otel-arrow/blob/985aa1500a012859cec44855e187eacf46eda7c8/pkg/otel/metrics/otlp/summary.go#L117
     pub fn from_record_batch(
         rb: &RecordBatch,
         attr_store: &mut Attribute32Store,
+        excluded_metric_ids: &HashSet<u16>,
     ) -> error::Result<SummaryDataPointsStore> {
         let mut store = SummaryDataPointsStore::default();
         let mut prev_parent_id = 0;
@@ -51,6 +53,13 @@ impl SummaryDataPointsStore {
             let delta = delta_id_arr.value_at_or_default(idx);
             let parent_id = prev_parent_id + delta;
             prev_parent_id = parent_id;
+            if excluded_metric_ids.contains(&parent_id) {
+                // attribute ids are delta encoded across all rows.
+                if let Some(id) = id_arr_opt.value_at(idx) {
+                    attr_store.attribute_by_delta_id(id);
+                }
+                continue;
+            }
             let nbdps = store.get_or_default(parent_id);
 
             let sdp = nbdps.append_and_get();
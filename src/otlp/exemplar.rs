@@ -73,23 +73,28 @@ impl ExemplarsStore {
             let time_unix_nano = time_unix_nano_arr.value_at_or_default(idx);
             current_exemplar.time_unix_nano = time_unix_nano as u64;
 
-            let span_id_bytes = span_id_arr.value_at_or_default(idx);
-            ensure!(
-                span_id_bytes.len() == 8,
-                error::InvalidSpanIdSnafu {
-                    message: format!("rb: {:?}", rb),
-                }
-            );
-            current_exemplar.span_id = span_id_bytes;
+            // ids are optional, null when the exemplar was not sampled in a trace.
+            if let Some(span_id_bytes) = span_id_arr.value_at(idx) {
+                ensure!(
+                    span_id_bytes.len() == 8,
+                    error::InvalidSpanIdSnafu {
+                        row: idx,
+                        message: format!("rb: {:?}", rb),
+                    }
+                );
+                current_exemplar.span_id = span_id_bytes;
+            }
 
-            let trace_id_bytes = trace_id_arr.value_at_or_default(idx);
-            ensure!(
-                trace_id_bytes.len() == 16,
-                error::InvalidTraceIdSnafu {
-                    message: format!("rb: {:?}", rb),
-                }
-            );
-            current_exemplar.trace_id = trace_id_bytes;
+            if let Some(trace_id_bytes) = trace_id_arr.value_at(idx) {
+                ensure!(
+                    trace_id_bytes.len() == 16,
+                    error::InvalidTraceIdSnafu {
+                        row: idx,
+                        message: format!("rb: {:?}", rb),
+                    }
+                );
+                current_exemplar.trace_id = trace_id_bytes;
+            }
 
             match (int_value, double_value) {
                 (Some(int_value), None) => {
@@ -101,6 +106,7 @@ impl ExemplarsStore {
                 }
                 _ => {
                     return error::InvalidExemplarDataSnafu {
+                        row: idx,
                         message: format!("record batch: {:?}", rb),
                     }
                     .fail()
@@ -11,23 +11,29 @@
 // limitations under the License.
 
 use crate::arrays::{
-    get_bool_array_opt, get_i32_array_opt, get_string_array_opt, get_u16_array, get_u8_array,
-    NullableArrayAccessor, StringArrayAccessor,
+    get_bool_array_opt, get_i32_array_opt, get_u16_array, get_u8_array, NullableArrayAccessor,
+    StringArrayAccessor,
 };
+use crate::decode::filter::MetricFilter;
 use crate::error;
+use crate::otlp::attributes::store::Attribute16Store;
 use crate::otlp::related_data::RelatedData;
 use crate::schema::consts;
 use arrow::array::{
-    Array, ArrayRef, BooleanArray, Int32Array, RecordBatch, StringArray, StructArray, UInt16Array,
-    UInt32Array, UInt8Array,
+    Array, ArrayRef, BooleanArray, Int32Array, RecordBatch, StructArray, UInt16Array, UInt32Array,
+    UInt8Array,
 };
 use arrow::datatypes::DataType::UInt32;
 use arrow::datatypes::{DataType, Field, Fields};
 use num_enum::TryFromPrimitive;
 use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
-use opentelemetry_proto::tonic::common::v1::InstrumentationScope;
-use opentelemetry_proto::tonic::metrics::v1::metric;
+use opentelemetry_proto::tonic::common::v1::{InstrumentationScope, KeyValue};
+use opentelemetry_proto::tonic::metrics::v1::{metric, ResourceMetrics};
+use prost::Message;
 use snafu::{OptionExt, ResultExt};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug, TryFromPrimitive)]
 #[repr(u8)]
@@ -43,7 +49,7 @@ pub enum MetricType {
 struct ResourceArrays<'a> {
     id: &'a UInt16Array,
     dropped_attributes_count: Option<&'a UInt32Array>,
-    schema_url: Option<&'a StringArray>,
+    schema_url: Option<StringArrayAccessor<'a>>,
 }
 
 impl<'a> ResourceArrays<'a> {
@@ -91,15 +97,7 @@ impl<'a> TryFrom<&'a RecordBatch> for ResourceArrays<'a> {
 
         let schema_url = struct_array
             .column_by_name(consts::SCHEMA_URL)
-            .map(|a| {
-                a.as_any().downcast_ref::<StringArray>().context(
-                    error::ColumnDataTypeMismatchSnafu {
-                        name: consts::SCHEMA_URL,
-                        expect: DataType::Utf8,
-                        actual: a.data_type().clone(),
-                    },
-                )
-            })
+            .map(StringArrayAccessor::new)
             .transpose()?;
 
         Ok(Self {
@@ -112,7 +110,7 @@ impl<'a> TryFrom<&'a RecordBatch> for ResourceArrays<'a> {
 
 struct ScopeArrays<'a> {
     name: StringArrayAccessor<'a>,
-    version: Option<&'a StringArray>,
+    version: Option<StringArrayAccessor<'a>>,
     dropped_attributes_count: Option<&'a UInt32Array>,
     id: Option<&'a UInt16Array>,
 }
@@ -175,15 +173,7 @@ impl<'a> TryFrom<&'a RecordBatch> for ScopeArrays<'a> {
 
         let version = scope_array
             .column_by_name(consts::VERSION)
-            .map(|a| {
-                a.as_any().downcast_ref::<StringArray>().context(
-                    error::ColumnDataTypeMismatchSnafu {
-                        name: consts::VERSION,
-                        expect: DataType::Utf8,
-                        actual: a.data_type().clone(),
-                    },
-                )
-            })
+            .map(StringArrayAccessor::new)
             .transpose()?;
 
         let dropped_attributes_count = scope_array
@@ -224,10 +214,10 @@ impl<'a> TryFrom<&'a RecordBatch> for ScopeArrays<'a> {
 struct MetricsArrays<'a> {
     id: &'a UInt16Array,
     metric_type: &'a UInt8Array,
-    schema_url: Option<&'a StringArray>,
+    schema_url: Option<StringArrayAccessor<'a>>,
     name: StringArrayAccessor<'a>,
     description: StringArrayAccessor<'a>,
-    unit: Option<&'a StringArray>,
+    unit: Option<StringArrayAccessor<'a>>,
     aggregation_temporality: Option<&'a Int32Array>,
     is_monotonic: Option<&'a BooleanArray>,
 }
@@ -249,18 +239,13 @@ impl<'a> TryFrom<&'a RecordBatch> for MetricsArrays<'a> {
                     name: consts::DESCRIPTION,
                 })?,
         )?;
-        let schema_url = get_string_array_opt(rb, consts::SCHEMA_URL)?;
+        let schema_url = rb
+            .column_by_name(consts::SCHEMA_URL)
+            .map(StringArrayAccessor::new)
+            .transpose()?;
         let unit = rb
             .column_by_name(consts::UNIT)
-            .map(|a| {
-                a.as_any().downcast_ref::<StringArray>().with_context(|| {
-                    error::ColumnDataTypeMismatchSnafu {
-                        name: consts::UNIT,
-                        expect: DataType::Utf8,
-                        actual: a.data_type().clone(),
-                    }
-                })
-            })
+            .map(StringArrayAccessor::new)
             .transpose()?;
         let aggregation_temporality = get_i32_array_opt(rb, consts::AGGREGATION_TEMPORALITY)?;
         let is_monotonic = get_bool_array_opt(rb, consts::IS_MONOTONIC)?;
@@ -277,12 +262,73 @@ impl<'a> TryFrom<&'a RecordBatch> for MetricsArrays<'a> {
     }
 }
 
-/// Builds [ExportMetricsServiceRequest] from given record batch.
-pub fn metrics_from(
+impl MetricsArrays<'_> {
+    fn metric_type(&self, idx: usize) -> error::Result<MetricType> {
+        let metric_type = self.metric_type.value_at_or_default(idx);
+        MetricType::try_from(metric_type).context(error::UnrecognizedMetricTypeSnafu {
+            metric_type,
+            row: idx,
+        })
+    }
+}
+
+/// Returns the ids of the metrics of given record dropped by the filter.
+pub(crate) fn excluded_metric_ids(
+    rb: &RecordBatch,
+    res_attr_store: &Attribute16Store,
+    filter: &MetricFilter,
+) -> error::Result<HashSet<u16>> {
+    let resource_arrays = ResourceArrays::try_from(rb)?;
+    let metrics_arrays = MetricsArrays::try_from(rb)?;
+
+    let mut excluded = HashSet::new();
+    let mut res_id = 0;
+    let mut metric_id = 0;
+    for idx in 0..rb.num_rows() {
+        res_id += resource_arrays.id.value_at_or_default(idx);
+        metric_id += metrics_arrays.id.value_at_or_default(idx);
+        let name = metrics_arrays.name.value_at(idx).unwrap_or_default();
+        let unit = metrics_arrays.unit.value_at_or_default(idx);
+        let resource_attributes = res_attr_store.attribute_by_id(res_id).unwrap_or_default();
+        if filter.excludes(
+            &name,
+            metrics_arrays.metric_type(idx)?,
+            &unit,
+            resource_attributes,
+        ) {
+            excluded.insert(metric_id);
+        }
+    }
+    Ok(excluded)
+}
+
+/// Receives decoded metrics as they are assembled by [visit_metrics_from].
+pub trait MetricsVisitor {
+    /// Called once per `ResourceMetrics` when all of its metrics have been assembled.
+    fn visit_resource_metrics(&mut self, resource_metrics: ResourceMetrics) -> error::Result<()>;
+}
+
+impl<F> MetricsVisitor for F
+where
+    F: FnMut(ResourceMetrics) -> error::Result<()>,
+{
+    fn visit_resource_metrics(&mut self, resource_metrics: ResourceMetrics) -> error::Result<()> {
+        self(resource_metrics)
+    }
+}
+
+/// Decodes given record batch, handing each `ResourceMetrics` to the visitor as soon as it
+/// is complete and moving data points out of `related_data` along the way. Excluded metrics
+/// are skipped, and so are the resources and scopes left without metrics.
+///
+/// `related_data` is fully materialised by [RelatedData::from_record_messages] before this is
+/// called. Only delivery is streamed: bounding peak memory is out of scope.
+pub fn visit_metrics_from(
     rb: &RecordBatch,
     related_data: &mut RelatedData,
-) -> error::Result<ExportMetricsServiceRequest> {
-    let mut metrics = ExportMetricsServiceRequest::default();
+    visitor: &mut impl MetricsVisitor,
+) -> error::Result<()> {
+    let mut current: Option<ResourceMetrics> = None;
 
     let mut prev_res_id: Option<u16> = None;
     let mut prev_scope_id: Option<u16> = None;
@@ -297,11 +343,21 @@ pub fn metrics_from(
     for idx in 0..rb.num_rows() {
         let res_delta_id = resource_arrays.id.value_at(idx).unwrap_or_default();
         res_id += res_delta_id;
+        let scope_delta_id_opt = scope_arrays.id.value_at(idx);
+        scope_id += scope_delta_id_opt.unwrap_or_default();
+        let delta_id = metrics_arrays.id.value_at_or_default(idx);
+        let metric_id = related_data.metric_id_from_delta(delta_id);
+        if related_data.excluded_metric_ids.contains(&metric_id) {
+            continue;
+        }
 
         if prev_res_id != Some(res_id) {
             // new resource id
             prev_res_id = Some(res_id);
-            let res_metrics = metrics.resource_metrics.append_and_get();
+            if let Some(done) = current.take() {
+                visitor.visit_resource_metrics(done)?;
+            }
+            let res_metrics = current.insert(ResourceMetrics::default());
             prev_scope_id = None;
 
             // Update the resource field of current resource metrics.
@@ -312,24 +368,19 @@ pub fn metrics_from(
                 resource.dropped_attributes_count = dropped_attributes_count;
             }
 
-            if let Some(res_id) = resource_arrays.id.value_at(idx)
-                && let Some(attrs) = related_data
-                    .res_attr_map_store
-                    .attribute_by_delta_id(res_id)
+            // ids are looked up as absolute since rows of excluded metrics may be skipped.
+            if resource_arrays.id.value_at(idx).is_some()
+                && let Some(attrs) = related_data.res_attr_map_store.attribute_by_id(res_id)
             {
                 resource.attributes = attrs.to_vec();
             }
             res_metrics.schema_url = resource_arrays.schema_url.value_at(idx).unwrap_or_default();
         }
 
-        let scope_delta_id_opt = scope_arrays.id.value_at(idx);
-        scope_id += scope_delta_id_opt.unwrap_or_default();
-
         if prev_scope_id != Some(scope_id) {
             prev_scope_id = Some(scope_id);
             // safety: We must have appended at least one resource metrics when reach here
-            let current_scope_metrics_slice =
-                &mut metrics.resource_metrics.last_mut().unwrap().scope_metrics;
+            let current_scope_metrics_slice = &mut current.as_mut().unwrap().scope_metrics;
             let scope_metrics = current_scope_metrics_slice.append_and_get();
 
             let mut scope = InstrumentationScope {
@@ -341,10 +392,8 @@ pub fn metrics_from(
                 attributes: vec![],
             };
 
-            if let Some(scope_id) = scope_delta_id_opt
-                && let Some(attrs) = related_data
-                    .scope_attr_map_store
-                    .attribute_by_delta_id(scope_id)
+            if scope_delta_id_opt.is_some()
+                && let Some(attrs) = related_data.scope_attr_map_store.attribute_by_id(scope_id)
             {
                 scope.attributes = attrs.to_vec();
             }
@@ -355,21 +404,10 @@ pub fn metrics_from(
 
         // Creates a metric at the end of current scope metrics slice.
         // safety: we've append at least one value at each slice when reach here.
-        let current_scope_metrics = &mut metrics
-            .resource_metrics
-            .last_mut()
-            .unwrap()
-            .scope_metrics
-            .last_mut()
-            .unwrap();
+        let current_scope_metrics =
+            &mut current.as_mut().unwrap().scope_metrics.last_mut().unwrap();
         let current_metric = current_scope_metrics.metrics.append_and_get();
-        let delta_id = metrics_arrays.id.value_at_or_default(idx);
-        let metric_id = related_data.metric_id_from_delta(delta_id);
-        let metric_type_val = metrics_arrays.metric_type.value_at_or_default(idx);
-        let metric_type =
-            MetricType::try_from(metric_type_val).context(error::UnrecognizedMetricTypeSnafu {
-                metric_type: metric_type_val,
-            })?;
+        let metric_type = metrics_arrays.metric_type(idx)?;
 
         let aggregation_temporality = metrics_arrays
             .aggregation_temporality
@@ -430,11 +468,81 @@ pub fn metrics_from(
                 };
                 current_metric.data = Some(metric::Data::Summary(summary));
             }
-            MetricType::Empty => return error::EmptyMetricTypeSnafu.fail(),
+            MetricType::Empty => return error::EmptyMetricTypeSnafu { row: idx }.fail(),
         }
     }
 
-    Ok(metrics)
+    if let Some(done) = current {
+        visitor.visit_resource_metrics(done)?;
+    }
+    Ok(())
+}
+
+/// Merges `ResourceMetrics` with equal resource and schema url, and within each resource the
+/// `ScopeMetrics` with equal scope and schema url, so that every distinct (resource, scope)
+/// pair appears once. Groups keep the position where they first appear.
+///
+/// Resource and scope ids are delta encoded and thus never decrease within a batch, so an
+/// encoder interleaving resources has to repeat them under new ids. Groups are therefore
+/// matched by their decoded content rather than by id, regardless of attribute order.
+pub fn regroup_metrics(request: &mut ExportMetricsServiceRequest) {
+    let resource_metrics = std::mem::take(&mut request.resource_metrics);
+    request.resource_metrics = merge_groups(
+        resource_metrics,
+        |rm| {
+            (
+                rm.resource
+                    .as_ref()
+                    .map(|r| canonical_bytes(r, |r| &mut r.attributes)),
+                rm.schema_url.clone(),
+            )
+        },
+        |rm, other| rm.scope_metrics.extend(other.scope_metrics),
+    );
+    for rm in &mut request.resource_metrics {
+        let scope_metrics = std::mem::take(&mut rm.scope_metrics);
+        rm.scope_metrics = merge_groups(
+            scope_metrics,
+            |sm| {
+                (
+                    sm.scope
+                        .as_ref()
+                        .map(|s| canonical_bytes(s, |s| &mut s.attributes)),
+                    sm.schema_url.clone(),
+                )
+            },
+            |sm, other| sm.metrics.extend(other.metrics),
+        );
+    }
+}
+
+/// Encodes the message with its attributes sorted by key.
+fn canonical_bytes<M: Message + Clone>(
+    message: &M,
+    attributes: impl Fn(&mut M) -> &mut Vec<KeyValue>,
+) -> Vec<u8> {
+    let mut message = message.clone();
+    attributes(&mut message).sort_by(|a, b| a.key.cmp(&b.key));
+    message.encode_to_vec()
+}
+
+fn merge_groups<T, K: Hash + Eq>(
+    groups: Vec<T>,
+    key: impl Fn(&T) -> K,
+    merge: impl Fn(&mut T, T),
+) -> Vec<T> {
+    let mut merged: Vec<T> = Vec::with_capacity(groups.len());
+    let mut positions = HashMap::new();
+    for group in groups {
+        match positions.entry(key(&group)) {
+            Entry::Occupied(e) => merge(&mut merged[*e.get()], group),
+            Entry::Vacant(e) => {
+                e.insert(merged.len());
+                merged.push(group);
+            }
+        }
+    }
+    merged
 }
 
 pub trait AppendAndGet<T> {
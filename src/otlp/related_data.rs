@@ -10,6 +10,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::decode::filter::MetricFilter;
 use crate::decode::record_message::RecordMessage;
 use crate::error;
 use crate::opentelemetry::ArrowPayloadType;
@@ -19,10 +20,15 @@ use crate::otlp::data_points::data_point_store::{
     SummaryDataPointsStore,
 };
 use crate::otlp::exemplar::ExemplarsStore;
+use crate::otlp::metric::excluded_metric_ids;
+use snafu::ResultExt;
+use std::collections::HashSet;
 
 #[derive(Default)]
 pub struct RelatedData {
     pub(crate) metric_id: u16,
+    /// Ids of the metrics dropped by the filter, whose data points are skipped.
+    pub(crate) excluded_metric_ids: HashSet<u16>,
 
     // Resource attributes.
     pub(crate) res_attr_map_store: Attribute16Store,
@@ -55,8 +61,11 @@ impl RelatedData {
         self.metric_id
     }
 
+    /// Reads all related records of the batch into stores, before any metric is assembled.
+    /// Returns the index of the main metrics record, if any.
     pub fn from_record_messages(
         rbs: &[RecordMessage],
+        filter: &MetricFilter,
     ) -> error::Result<(RelatedData, Option<usize>)> {
         let mut related_data = RelatedData::default();
 
@@ -74,10 +83,12 @@ impl RelatedData {
         for (idx, rm) in rbs.iter().enumerate() {
             match rm.payload_type {
                 ArrowPayloadType::ResourceAttrs => {
-                    related_data.res_attr_map_store = Attribute16Store::try_from(&rm.record)?;
+                    related_data.res_attr_map_store =
+                        Attribute16Store::try_from(&rm.record).context(rm.decode_context())?;
                 }
                 ArrowPayloadType::ScopeAttrs => {
-                    related_data.scope_attr_map_store = Attribute16Store::try_from(&rm.record)?;
+                    related_data.scope_attr_map_store =
+                        Attribute16Store::try_from(&rm.record).context(rm.decode_context())?;
                 }
                 ArrowPayloadType::UnivariateMetrics => {
                     // this record is the main metrics record.
@@ -96,17 +107,20 @@ impl RelatedData {
                     exp_histogram_dp_idx = Some(idx);
                 }
                 ArrowPayloadType::NumberDpAttrs => {
-                    related_data.number_d_p_attrs_store = Attribute32Store::try_from(&rm.record)?;
+                    related_data.number_d_p_attrs_store =
+                        Attribute32Store::try_from(&rm.record).context(rm.decode_context())?;
                 }
                 ArrowPayloadType::SummaryDpAttrs => {
-                    related_data.summary_attrs_store = Attribute32Store::try_from(&rm.record)?;
+                    related_data.summary_attrs_store =
+                        Attribute32Store::try_from(&rm.record).context(rm.decode_context())?;
                 }
                 ArrowPayloadType::HistogramDpAttrs => {
-                    related_data.histogram_attrs_store = Attribute32Store::try_from(&rm.record)?;
+                    related_data.histogram_attrs_store =
+                        Attribute32Store::try_from(&rm.record).context(rm.decode_context())?;
                 }
                 ArrowPayloadType::ExpHistogramDpAttrs => {
                     related_data.exp_histogram_attrs_store =
-                        Attribute32Store::try_from(&rm.record)?;
+                        Attribute32Store::try_from(&rm.record).context(rm.decode_context())?;
                 }
                 ArrowPayloadType::NumberDpExemplars => {
                     number_dp_ex_idx = Some(idx);
@@ -119,15 +133,15 @@ impl RelatedData {
                 }
                 ArrowPayloadType::NumberDpExemplarAttrs => {
                     related_data.number_d_p_exemplar_attrs_store =
-                        Attribute32Store::try_from(&rm.record)?;
+                        Attribute32Store::try_from(&rm.record).context(rm.decode_context())?;
                 }
                 ArrowPayloadType::HistogramDpExemplarAttrs => {
                     related_data.histogram_exemplar_attrs_store =
-                        Attribute32Store::try_from(&rm.record)?;
+                        Attribute32Store::try_from(&rm.record).context(rm.decode_context())?;
                 }
                 ArrowPayloadType::ExpHistogramDpExemplarAttrs => {
                     related_data.exp_histogram_exemplar_attrs_store =
-                        Attribute32Store::try_from(&rm.record)?;
+                        Attribute32Store::try_from(&rm.record).context(rm.decode_context())?;
                 }
                 _ => {
                     //todo: support logs/trace/span
@@ -139,27 +153,42 @@ impl RelatedData {
             }
         }
 
+        // Resource attributes are needed to find the excluded metrics before data points.
+        if !filter.is_empty()
+            && let Some(metrics_record_idx) = metrics_record_idx
+        {
+            let record = &rbs[metrics_record_idx];
+            related_data.excluded_metric_ids =
+                excluded_metric_ids(&record.record, &related_data.res_attr_map_store, filter)
+                    .context(record.decode_context())?;
+        }
+
         // Process exemplars.
         if let Some(number_dp_ex_rec_idx) = number_dp_ex_idx {
-            let record = &rbs[number_dp_ex_rec_idx].record;
+            let record = &rbs[number_dp_ex_rec_idx];
             related_data.number_data_point_exemplars_store = ExemplarsStore::try_from(
-                record,
+                &record.record,
                 &mut related_data.number_d_p_exemplar_attrs_store,
-            )?;
+            )
+            .context(record.decode_context())?;
         }
 
         if let Some(histogram_dp_ex_rec_idx) = histogram_dp_ex_idx {
-            let record = &rbs[histogram_dp_ex_rec_idx].record;
-            related_data.histogram_data_point_exemplars_store =
-                ExemplarsStore::try_from(record, &mut related_data.histogram_exemplar_attrs_store)?
+            let record = &rbs[histogram_dp_ex_rec_idx];
+            related_data.histogram_data_point_exemplars_store = ExemplarsStore::try_from(
+                &record.record,
+                &mut related_data.histogram_exemplar_attrs_store,
+            )
+            .context(record.decode_context())?;
         }
 
         if let Some(exp_histogram_dp_ex_rec_idx) = exp_histogram_dp_ex_idx {
-            let record = &rbs[exp_histogram_dp_ex_rec_idx].record;
+            let record = &rbs[exp_histogram_dp_ex_rec_idx];
             related_data.e_histogram_data_point_exemplars_store = ExemplarsStore::try_from(
-                record,
+                &record.record,
                 &mut related_data.exp_histogram_exemplar_attrs_store,
-            )?;
+            )
+            .context(record.decode_context())?;
         }
 
         // Process data points
@@ -169,32 +198,40 @@ impl RelatedData {
                 &number_data_point_record.record,
                 &mut related_data.number_data_point_exemplars_store,
                 &related_data.number_d_p_attrs_store,
-            )?;
+                &related_data.excluded_metric_ids,
+            )
+            .context(number_data_point_record.decode_context())?;
         }
         if let Some(summary_data_point_rec_idx) = summary_dp_idx {
-            let record = &rbs[summary_data_point_rec_idx].record;
+            let record = &rbs[summary_data_point_rec_idx];
             related_data.summary_data_points_store = SummaryDataPointsStore::from_record_batch(
-                record,
+                &record.record,
                 &mut related_data.summary_attrs_store,
-            )?;
+                &related_data.excluded_metric_ids,
+            )
+            .context(record.decode_context())?;
         }
         if let Some(histogram_dp_idx) = histogram_dp_idx {
-            let record = &rbs[histogram_dp_idx].record;
+            let record = &rbs[histogram_dp_idx];
             related_data.histogram_data_points_store = HistogramDataPointsStore::from_record_batch(
-                record,
+                &record.record,
                 &mut related_data.histogram_data_point_exemplars_store,
                 &related_data.histogram_attrs_store,
-            )?;
+                &related_data.excluded_metric_ids,
+            )
+            .context(record.decode_context())?;
         }
 
         if let Some(exp_histogram_data_point_idx) = exp_histogram_dp_idx {
-            let record = &rbs[exp_histogram_data_point_idx].record;
+            let record = &rbs[exp_histogram_data_point_idx];
             related_data.e_histogram_data_points_store =
                 EHistogramDataPointsStore::from_record_batch(
-                    record,
+                    &record.record,
                     &mut related_data.e_histogram_data_point_exemplars_store,
                     &related_data.exp_histogram_attrs_store,
-                )?;
+                    &related_data.excluded_metric_ids,
+                )
+                .context(record.decode_context())?;
         }
 
         Ok((related_data, metrics_record_idx))
@@ -0,0 +1,529 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Processing of OTAP batches on their Arrow records, without decoding them to OTLP.
+
+use crate::decode::decoder::Consumer;
+use crate::decode::record_message::RecordMessage;
+use crate::encode::adaptive::AdaptiveSchema;
+use crate::encode::encoder::Producer;
+use crate::error;
+use crate::opentelemetry::{ArrowPayloadType, BatchArrowRecords};
+use crate::otlp::attributes::cbor;
+use crate::otlp::attributes::parent_id::ParentId;
+use crate::otlp::attributes::store::{for_each_attribute_row, AttributeValueType};
+use crate::schema::consts;
+use arrow::array::{
+    Array, ArrayRef, AsArray, BooleanArray, RecordBatch, StringArray, UInt32Array, UInt8Array,
+};
+use arrow::compute::{cast, nullif, take_record_batch};
+use arrow::datatypes::{DataType, Field, Schema};
+use hmac::{Hmac, Mac};
+use opentelemetry_proto::tonic::common::v1::any_value::Value;
+use opentelemetry_proto::tonic::common::v1::AnyValue;
+use sha2::{Digest, Sha256};
+use snafu::{OptionExt, ResultExt};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Payload types holding attributes, processed by [AttributeProcessor].
+const ATTRIBUTE_PAYLOAD_TYPES: &[ArrowPayloadType] = &[
+    ArrowPayloadType::ResourceAttrs,
+    ArrowPayloadType::ScopeAttrs,
+    ArrowPayloadType::NumberDpAttrs,
+    ArrowPayloadType::SummaryDpAttrs,
+    ArrowPayloadType::HistogramDpAttrs,
+    ArrowPayloadType::ExpHistogramDpAttrs,
+    ArrowPayloadType::NumberDpExemplarAttrs,
+    ArrowPayloadType::HistogramDpExemplarAttrs,
+    ArrowPayloadType::ExpHistogramDpExemplarAttrs,
+    ArrowPayloadType::LogAttrs,
+    ArrowPayloadType::SpanAttrs,
+    ArrowPayloadType::SpanEventAttrs,
+    ArrowPayloadType::SpanLinkAttrs,
+];
+
+/// Value columns cleared when a value is replaced by a string.
+const NON_STR_VALUE_COLUMNS: &[&str] = &[
+    consts::ATTRIBUTE_INT,
+    consts::ATTRIBUTE_DOUBLE,
+    consts::ATTRIBUTE_BOOL,
+    consts::ATTRIBUTE_BYTES,
+    consts::ATTRIBUTE_SER,
+];
+
+/// What to do with the attributes of a given key.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AttributeAction {
+    Drop,
+    /// Renames the key. If the parent already has an attribute with the new key, only one of
+    /// them is kept.
+    Rename(String),
+    /// Replaces the value by the hex SHA-256 of its string, bytes or CBOR representation, or
+    /// its HMAC-SHA256 if the processor has a hash secret.
+    Hash,
+    /// Replaces the value by given string.
+    Redact(String),
+}
+
+/// Drops, renames, hashes or redacts attributes of an OTAP stream by rewriting only its
+/// attribute records; all other records are forwarded as is.
+///
+/// Attribute records are processed column by column: dropped rows are filtered out, renamed
+/// keys and replaced values are rewritten in place, and rows are then sorted by parent id and
+/// key so that parent ids can be delta encoded again.
+///
+/// Holds the IPC state of both the incoming and the outgoing stream, so one processor must be
+/// used per stream, for all of its batches in order.
+#[derive(Default)]
+pub struct AttributeProcessor {
+    actions: HashMap<String, AttributeAction>,
+    /// Attribute payload types to process, all of them if `None`.
+    payload_types: Option<HashSet<ArrowPayloadType>>,
+    hash_secret: Option<Vec<u8>>,
+    consumer: Consumer,
+    producer: Producer,
+    adaptive_schemas: HashMap<ArrowPayloadType, AdaptiveSchema>,
+}
+
+impl AttributeProcessor {
+    pub fn drop_key(self, key: impl Into<String>) -> Self {
+        self.with_action(key, AttributeAction::Drop)
+    }
+
+    pub fn rename_key(self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.with_action(from, AttributeAction::Rename(to.into()))
+    }
+
+    pub fn hash_value(self, key: impl Into<String>) -> Self {
+        self.with_action(key, AttributeAction::Hash)
+    }
+
+    pub fn redact_value(self, key: impl Into<String>, replacement: impl Into<String>) -> Self {
+        self.with_action(key, AttributeAction::Redact(replacement.into()))
+    }
+
+    /// Sets the action for a key, replacing any previous one.
+    pub fn with_action(mut self, key: impl Into<String>, action: AttributeAction) -> Self {
+        self.actions.insert(key.into(), action);
+        self
+    }
+
+    /// Hashes values with HMAC-SHA256 keyed by the secret instead of plain SHA-256, so that
+    /// values from a small set, e.g. user ids, can't be recovered by hashing candidates.
+    pub fn with_hash_secret(mut self, secret: impl Into<Vec<u8>>) -> Self {
+        self.hash_secret = Some(secret.into());
+        self
+    }
+
+    /// Only processes the attributes of given payload types, e.g. `ResourceAttrs`.
+    pub fn with_payload_types(
+        mut self,
+        payload_types: impl IntoIterator<Item = ArrowPayloadType>,
+    ) -> Self {
+        self.payload_types = Some(payload_types.into_iter().collect());
+        self
+    }
+
+    /// Processes the next batch of the incoming stream and returns the next batch of the
+    /// outgoing stream, with the same headers. Attribute records left empty are omitted.
+    pub fn process(&mut self, batch: &mut BatchArrowRecords) -> error::Result<BatchArrowRecords> {
+        let records = self.consumer.consume_records(batch)?;
+        let mut processed = Vec::with_capacity(records.len());
+        for RecordMessage {
+            payload_type,
+            record,
+            ..
+        } in records
+        {
+            if !self.applies_to(payload_type) {
+                processed.push((payload_type, record));
+                continue;
+            }
+            let record = match record
+                .column_by_name(consts::PARENT_ID)
+                .map(|c| c.data_type())
+            {
+                Some(DataType::UInt16) => self.process_attributes::<u16>(&record)?,
+                _ => self.process_attributes::<u32>(&record)?,
+            };
+            if let Some(record) = record {
+                let adaptive = self.adaptive_schemas.entry(payload_type).or_default();
+                processed.push((payload_type, adaptive.adapt(&record)?));
+            }
+        }
+        let headers: Vec<_> = self
+            .consumer
+            .last_batch_headers()
+            .iter()
+            .flat_map(|(name, values)| values.iter().map(|v| (name.as_str(), v.as_str())))
+            .collect();
+        self.producer
+            .produce_records_with_headers(processed, &headers)
+    }
+
+    fn applies_to(&self, payload_type: ArrowPayloadType) -> bool {
+        ATTRIBUTE_PAYLOAD_TYPES.contains(&payload_type)
+            && self
+                .payload_types
+                .as_ref()
+                .map_or(true, |types| types.contains(&payload_type))
+    }
+
+    fn process_attributes<T>(&self, record: &RecordBatch) -> error::Result<Option<RecordBatch>>
+    where
+        T: ParentId + Ord,
+        T::Array: From<Vec<T>> + Array,
+    {
+        // parent ids are delta encoded depending on values, so they are decoded first. Rows
+        // skipped by the decoder stay `None` and are dropped.
+        let mut decoded: Vec<Option<(T, Value)>> = vec![None; record.num_rows()];
+        for_each_attribute_row(record, |row, parent_id: T, _, value| {
+            decoded[row] = Some((parent_id, value));
+        })?;
+
+        let keys = cast(column(record, consts::ATTRIBUTE_KEY)?, &DataType::Utf8)
+            .context(error::BuildRecordBatchSnafu)?;
+        let keys = keys.as_string::<i32>();
+        let mut new_keys = Vec::with_capacity(keys.len());
+        let mut replacements = Vec::with_capacity(keys.len());
+        let mut rows = Vec::with_capacity(keys.len());
+        let mut seen = HashSet::new();
+        for (row, decoded) in decoded.iter_mut().enumerate() {
+            let key = keys
+                .is_valid(row)
+                .then(|| keys.value(row))
+                .unwrap_or_default();
+            let action = self.actions.get(key);
+            let key = match action {
+                Some(AttributeAction::Rename(to)) => to.as_str(),
+                _ => key,
+            };
+            let replacement = match (action, decoded.as_mut()) {
+                (Some(AttributeAction::Hash), Some((_, value))) => Some(self.hash(value)),
+                (Some(AttributeAction::Redact(replacement)), Some(_)) => Some(replacement.clone()),
+                _ => None,
+            };
+            if let (Some(replacement), Some((_, value))) = (&replacement, decoded.as_mut()) {
+                *value = Value::StringValue(replacement.clone());
+            }
+            if let Some((parent_id, _)) = decoded
+                && action != Some(&AttributeAction::Drop)
+                && seen.insert((*parent_id, key))
+            {
+                rows.push(row);
+            }
+            new_keys.push(key);
+            replacements.push(replacement);
+        }
+        if rows.is_empty() {
+            return Ok(None);
+        }
+        rows.sort_by_key(|&row| {
+            (
+                decoded[row].as_ref().map(|(parent_id, _)| *parent_id),
+                new_keys[row],
+            )
+        });
+
+        let replaced = BooleanArray::from_iter(replacements.iter().map(|r| Some(r.is_some())));
+        let mut fields = Vec::with_capacity(record.num_columns() + 1);
+        let mut columns = Vec::with_capacity(record.num_columns() + 1);
+        for (field, column) in record.schema().fields().iter().zip(record.columns()) {
+            let column = match field.name().as_str() {
+                consts::ATTRIBUTE_KEY => Arc::new(StringArray::from(new_keys.clone())) as ArrayRef,
+                consts::ATTRIBUTE_TYPE => {
+                    let value_types = column.as_any().downcast_ref::<UInt8Array>().context(
+                        error::ColumnDataTypeMismatchSnafu {
+                            name: consts::ATTRIBUTE_TYPE,
+                            expect: DataType::UInt8,
+                            actual: column.data_type().clone(),
+                        },
+                    )?;
+                    Arc::new(UInt8Array::from_iter(
+                        value_types
+                            .iter()
+                            .zip(&replacements)
+                            .map(|(value_type, replacement)| match replacement {
+                                Some(_) => Some(AttributeValueType::Str as u8),
+                                None => value_type,
+                            }),
+                    ))
+                }
+                consts::ATTRIBUTE_STR => replace_strings(Some(column), &replacements)?,
+                name if NON_STR_VALUE_COLUMNS.contains(&name) => {
+                    nullif(column, &replaced).context(error::BuildRecordBatchSnafu)?
+                }
+                _ => column.clone(),
+            };
+            fields.push(
+                field
+                    .as_ref()
+                    .clone()
+                    .with_data_type(column.data_type().clone())
+                    .with_nullable(field.is_nullable() || column.null_count() > 0),
+            );
+            columns.push(column);
+        }
+        if record.column_by_name(consts::ATTRIBUTE_STR).is_none() && replaced.true_count() > 0 {
+            let column = replace_strings(None, &replacements)?;
+            fields.push(Field::new(consts::ATTRIBUTE_STR, DataType::Utf8, true));
+            columns.push(column);
+        }
+        let schema = Schema::new_with_metadata(fields, record.schema().metadata().clone());
+        let rewritten = RecordBatch::try_new(Arc::new(schema), columns)
+            .context(error::BuildRecordBatchSnafu)?;
+        let indices = UInt32Array::from_iter_values(rows.iter().map(|&row| row as u32));
+        let sorted =
+            take_record_batch(&rewritten, &indices).context(error::BuildRecordBatchSnafu)?;
+
+        // mirrors the delta group encoding, in the order of parent ids so that deltas are
+        // never negative.
+        let mut parent_ids = Vec::with_capacity(rows.len());
+        let mut prev: Option<(&str, &Value)> = None;
+        let mut prev_parent_id = T::default();
+        for &row in &rows {
+            // safety: only decoded rows are kept.
+            let (parent_id, value) = decoded[row].as_ref().unwrap();
+            let key = new_keys[row];
+            if prev == Some((key, value)) {
+                parent_ids.push(*parent_id - prev_parent_id);
+            } else {
+                prev = Some((key, value));
+                parent_ids.push(*parent_id);
+            }
+            prev_parent_id = *parent_id;
+        }
+        let mut columns = sorted.columns().to_vec();
+        let parent_id_index = sorted.schema().index_of(consts::PARENT_ID).ok().context(
+            error::ColumnNotFoundSnafu {
+                name: consts::PARENT_ID,
+            },
+        )?;
+        columns[parent_id_index] = Arc::new(T::Array::from(parent_ids));
+        RecordBatch::try_new(sorted.schema(), columns)
+            .context(error::BuildRecordBatchSnafu)
+            .map(Some)
+    }
+
+    /// Returns the lowercase hex digest of a value.
+    fn hash(&self, value: &Value) -> String {
+        let input = hash_input(value);
+        let digest = match &self.hash_secret {
+            Some(secret) => {
+                // safety: HMAC accepts keys of any length.
+                let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+                mac.update(&input);
+                mac.finalize().into_bytes()
+            }
+            None => Sha256::digest(&input),
+        };
+        const DIGITS: &[u8; 16] = b"0123456789abcdef";
+        digest
+            .iter()
+            .flat_map(|b| [DIGITS[(b >> 4) as usize], DIGITS[(b & 0xf) as usize]])
+            .map(char::from)
+            .collect()
+    }
+}
+
+fn column<'a>(record: &'a RecordBatch, name: &'static str) -> error::Result<&'a ArrayRef> {
+    record
+        .column_by_name(name)
+        .context(error::ColumnNotFoundSnafu { name })
+}
+
+/// Returns the string column with the replaced values, as plain strings.
+fn replace_strings(
+    column: Option<&ArrayRef>,
+    replacements: &[Option<String>],
+) -> error::Result<ArrayRef> {
+    let strings = column
+        .map(|column| cast(column, &DataType::Utf8))
+        .transpose()
+        .context(error::BuildRecordBatchSnafu)?;
+    let strings = strings.as_ref().map(|s| s.as_string::<i32>());
+    Ok(Arc::new(StringArray::from_iter(
+        replacements.iter().enumerate().map(|(row, replacement)| {
+            replacement
+                .as_deref()
+                .or_else(|| strings.filter(|s| s.is_valid(row)).map(|s| s.value(row)))
+        }),
+    )))
+}
+
+/// Bytes of a value to hash: strings and bytes as is, scalars as displayed and maps and
+/// slices as their CBOR encoding.
+fn hash_input(value: &Value) -> Vec<u8> {
+    match value {
+        Value::StringValue(v) => v.clone().into_bytes(),
+        Value::BytesValue(v) => v.clone(),
+        Value::IntValue(v) => v.to_string().into_bytes(),
+        Value::DoubleValue(v) => v.to_string().into_bytes(),
+        Value::BoolValue(v) => v.to_string().into_bytes(),
+        Value::ArrayValue(_) | Value::KvlistValue(_) => cbor::encode(&AnyValue {
+            value: Some(value.clone()),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decode::decoder::Consumer;
+    use crate::encode::encoder::Producer;
+    use crate::opentelemetry::ArrowPayloadType;
+    use crate::processor::AttributeProcessor;
+    use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+    use opentelemetry_proto::tonic::common::v1::{any_value, AnyValue, KeyValue};
+    use opentelemetry_proto::tonic::metrics::v1::{
+        metric, Gauge, Metric, NumberDataPoint, ResourceMetrics, ScopeMetrics,
+    };
+    use opentelemetry_proto::tonic::resource::v1::Resource;
+
+    fn kv(key: &str, value: &str) -> KeyValue {
+        KeyValue {
+            key: key.to_string(),
+            value: Some(AnyValue {
+                value: Some(any_value::Value::StringValue(value.to_string())),
+            }),
+        }
+    }
+
+    fn request(user: &str) -> ExportMetricsServiceRequest {
+        let data_point = NumberDataPoint {
+            attributes: vec![
+                kv("user.id", user),
+                kv("session.id", "1234"),
+                kv("http.method", "GET"),
+            ],
+            time_unix_nano: 1,
+            ..Default::default()
+        };
+        ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                resource: Some(Resource {
+                    attributes: vec![kv("host.name", "db-1"), kv("session.id", "kept")],
+                    ..Default::default()
+                }),
+                scope_metrics: vec![ScopeMetrics {
+                    metrics: vec![Metric {
+                        name: "requests".to_string(),
+                        data: Some(metric::Data::Gauge(Gauge {
+                            data_points: vec![data_point],
+                        })),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        }
+    }
+
+    fn sorted(mut attributes: Vec<KeyValue>) -> Vec<KeyValue> {
+        attributes.sort_by(|a, b| a.key.cmp(&b.key));
+        attributes
+    }
+
+    #[test]
+    fn test_process() {
+        let mut producer = Producer::default();
+        let mut consumer = Consumer::default();
+        let mut processor = AttributeProcessor::default()
+            .drop_key("session.id")
+            .rename_key("http.method", "http.request.method")
+            .hash_value("user.id")
+            .redact_value("host.name", "redacted")
+            .with_payload_types([ArrowPayloadType::NumberDpAttrs]);
+
+        // the second batch reuses the IPC streams of the first one.
+        for user in ["abc", "alice"] {
+            let mut batch = producer
+                .produce_batches_with_headers(&request(user), &[("tenant", "a")])
+                .unwrap();
+            let mut batch = processor.process(&mut batch).unwrap();
+            let decoded = consumer.consume_batches(&mut batch).unwrap();
+            assert_eq!(
+                consumer.last_batch_headers()["tenant"],
+                vec!["a".to_string()]
+            );
+
+            // resource attributes are out of scope.
+            let rm = &decoded.resource_metrics[0];
+            assert_eq!(
+                sorted(rm.resource.as_ref().unwrap().attributes.clone()),
+                vec![kv("host.name", "db-1"), kv("session.id", "kept")]
+            );
+            let Some(metric::Data::Gauge(gauge)) = &rm.scope_metrics[0].metrics[0].data else {
+                panic!("expected a gauge");
+            };
+            let hash = match user {
+                "abc" => "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+                _ => "2bd806c97f0e00af1a1fc3328fa763a9269723c8db8fac4f93af71db186d6e90",
+            };
+            assert_eq!(
+                sorted(gauge.data_points[0].attributes.clone()),
+                vec![kv("http.request.method", "GET"), kv("user.id", hash)]
+            );
+        }
+    }
+
+    #[test]
+    fn test_process_many_parents() {
+        let data_points = (0..8)
+            .map(|i| NumberDataPoint {
+                attributes: vec![
+                    kv("user.id", &format!("user-{}", 7 - i)),
+                    kv("user.name", "what do ya want for nothing?"),
+                    kv("tenant", "a"),
+                    kv("team", if i % 2 == 0 { "x" } else { "y" }),
+                ],
+                time_unix_nano: i,
+                ..Default::default()
+            })
+            .collect();
+        let mut request = request("abc");
+        request.resource_metrics[0].scope_metrics[0].metrics[0].data =
+            Some(metric::Data::Gauge(Gauge { data_points }));
+
+        let mut processor = AttributeProcessor::default()
+            .redact_value("user.id", "redacted")
+            .hash_value("user.name")
+            .with_hash_secret("Jefe")
+            // only one of "tenant" and "team" is kept.
+            .rename_key("tenant", "team")
+            .with_payload_types([ArrowPayloadType::NumberDpAttrs]);
+        let mut batch = Producer::default().produce_batches(&request).unwrap();
+        let mut batch = processor.process(&mut batch).unwrap();
+        let decoded = Consumer::default().consume_batches(&mut batch).unwrap();
+
+        let Some(metric::Data::Gauge(gauge)) =
+            &decoded.resource_metrics[0].scope_metrics[0].metrics[0].data
+        else {
+            panic!("expected a gauge");
+        };
+        assert_eq!(gauge.data_points.len(), 8);
+        for dp in &gauge.data_points {
+            let attributes = sorted(dp.attributes.clone());
+            assert_eq!(attributes.len(), 3);
+            assert_eq!(attributes[1], kv("user.id", "redacted"));
+            // HMAC-SHA256 test case 2 of RFC 4231.
+            assert_eq!(
+                attributes[2],
+                kv(
+                    "user.name",
+                    "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+                )
+            );
+        }
+    }
+}
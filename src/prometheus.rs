@@ -0,0 +1,752 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conversion of decoded metrics to Prometheus remote-write requests, following the
+//! [OTLP to Prometheus](https://opentelemetry.io/docs/specs/otel/compatibility/prometheus_and_openmetrics/)
+//! compatibility specification.
+//!
+//! Prometheus only supports cumulative temporality, so delta sums and histograms are left
+//! out; convert them first with [TemporalityConverter](crate::temporality::TemporalityConverter).
+
+mod naming;
+pub mod prompb;
+
+use crate::prometheus::naming::{label_name, metric_name, unit_name};
+use crate::prometheus::prompb::{
+    histogram, BucketSpan, Exemplar, Histogram, Label, MetricMetadata, MetricType, Sample,
+    TimeSeries, WriteRequest,
+};
+use crate::temporality::{downscale, MAX_SCALE};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use opentelemetry_proto::tonic::common::v1::{any_value, AnyValue, KeyValue};
+use opentelemetry_proto::tonic::metrics::v1::exponential_histogram_data_point::Buckets;
+use opentelemetry_proto::tonic::metrics::v1::{
+    exemplar, metric, number_data_point, AggregationTemporality, DataPointFlags,
+    ExponentialHistogramDataPoint, Metric, NumberDataPoint,
+};
+use std::collections::{BTreeMap, HashMap};
+
+/// Value of Prometheus staleness markers, sent for points without a recorded value.
+const STALE_NAN: u64 = 0x7ff0000000000002;
+
+/// Range of native histogram schemas supported by Prometheus.
+const MIN_SCHEMA: i32 = -4;
+const MAX_SCHEMA: i32 = 8;
+
+/// Resource attributes mapped to the `job` and `instance` labels rather than `target_info`.
+const SERVICE_NAME: &str = "service.name";
+const SERVICE_NAMESPACE: &str = "service.namespace";
+const SERVICE_INSTANCE_ID: &str = "service.instance.id";
+
+/// Converts decoded metrics to remote-write requests.
+///
+/// Gauges and cumulative sums become samples, monotonic sums as counters. Histograms become
+/// `_bucket`, `_sum` and `_count` series, exponential histograms become native histograms,
+/// and summaries become quantile, `_sum` and `_count` series. Exemplars are kept, with their
+/// trace and span ids as labels.
+pub struct RemoteWriteConverter {
+    add_suffixes: bool,
+    target_info: bool,
+    max_series_per_request: usize,
+}
+
+impl Default for RemoteWriteConverter {
+    fn default() -> Self {
+        Self {
+            add_suffixes: true,
+            target_info: true,
+            max_series_per_request: usize::MAX,
+        }
+    }
+}
+
+impl RemoteWriteConverter {
+    /// Whether to append unit, `_total` and `_ratio` suffixes to metric names.
+    pub fn with_suffixes(mut self, add_suffixes: bool) -> Self {
+        self.add_suffixes = add_suffixes;
+        self
+    }
+
+    /// Whether to send the attributes of every resource as a `target_info` series.
+    pub fn with_target_info(mut self, target_info: bool) -> Self {
+        self.target_info = target_info;
+        self
+    }
+
+    /// Splits the output into requests of at most given number of series.
+    pub fn with_max_series_per_request(mut self, max_series_per_request: usize) -> Self {
+        self.max_series_per_request = max_series_per_request.max(1);
+        self
+    }
+
+    /// Converts a request. Samples of identical series are merged into one time series, and
+    /// metadata is sent once per metric family, in the first request.
+    pub fn convert(&self, request: &ExportMetricsServiceRequest) -> Vec<WriteRequest> {
+        let mut series = SeriesSet::default();
+        for rm in &request.resource_metrics {
+            let resource_attributes = rm
+                .resource
+                .as_ref()
+                .map_or(&[][..], |r| r.attributes.as_slice());
+            let resource_labels = resource_labels(resource_attributes);
+            let mut latest = None;
+            for sm in &rm.scope_metrics {
+                let mut context = resource_labels.clone();
+                if let Some(scope) = &sm.scope {
+                    if !scope.name.is_empty() {
+                        context.insert("otel_scope_name".to_string(), scope.name.clone());
+                    }
+                    if !scope.version.is_empty() {
+                        context.insert("otel_scope_version".to_string(), scope.version.clone());
+                    }
+                }
+                for metric in &sm.metrics {
+                    if let Some(time) = self.add_metric(&mut series, metric, &context) {
+                        latest = latest.max(Some(time));
+                    }
+                }
+            }
+            if self.target_info
+                && let Some(time) = latest
+            {
+                let attributes: Vec<_> = resource_attributes
+                    .iter()
+                    .filter(|kv| {
+                        ![SERVICE_NAME, SERVICE_NAMESPACE, SERVICE_INSTANCE_ID]
+                            .contains(&kv.key.as_str())
+                    })
+                    .cloned()
+                    .collect();
+                if !attributes.is_empty() {
+                    let labels = attribute_labels(&attributes, &resource_labels);
+                    series.metadata("target_info", MetricType::Gauge, "", "");
+                    series.sample(labels_with_name(&labels, "target_info", None), 1.0, time);
+                }
+            }
+        }
+        series.into_requests(self.max_series_per_request)
+    }
+
+    /// Adds the points of a metric, returning the latest point time.
+    fn add_metric(
+        &self,
+        series: &mut SeriesSet,
+        metric: &Metric,
+        context: &BTreeMap<String, String>,
+    ) -> Option<u64> {
+        let name = metric_name(metric, self.add_suffixes);
+        let unit = unit_name(&metric.unit);
+        let cumulative = AggregationTemporality::Cumulative as i32;
+        let mut latest = None;
+        match metric.data.as_ref()? {
+            metric::Data::Gauge(gauge) => {
+                series.metadata(&name, MetricType::Gauge, &metric.description, &unit);
+                for dp in &gauge.data_points {
+                    latest = latest.max(series.number(&name, dp, context));
+                }
+            }
+            metric::Data::Sum(sum) if sum.aggregation_temporality == cumulative => {
+                let metric_type = if sum.is_monotonic {
+                    MetricType::Counter
+                } else {
+                    MetricType::Gauge
+                };
+                series.metadata(&name, metric_type, &metric.description, &unit);
+                for dp in &sum.data_points {
+                    latest = latest.max(series.number(&name, dp, context));
+                }
+            }
+            metric::Data::Histogram(h) if h.aggregation_temporality == cumulative => {
+                series.metadata(&name, MetricType::Histogram, &metric.description, &unit);
+                for dp in &h.data_points {
+                    let labels = attribute_labels(&dp.attributes, context);
+                    let time = dp.time_unix_nano;
+                    let stale = no_recorded_value(dp.flags);
+                    let value = |v: f64| if stale { f64::from_bits(STALE_NAN) } else { v };
+                    let bucket = format!("{name}_bucket");
+                    let mut cumulative_count = 0;
+                    for (i, count) in dp.bucket_counts.iter().enumerate() {
+                        cumulative_count += count;
+                        let le = dp
+                            .explicit_bounds
+                            .get(i)
+                            .map_or_else(|| "+Inf".to_string(), |bound| bound.to_string());
+                        let labels = labels_with_name(&labels, &bucket, Some(("le", le)));
+                        series.sample(labels, value(cumulative_count as f64), time);
+                    }
+                    if dp.bucket_counts.len() <= dp.explicit_bounds.len() {
+                        let labels =
+                            labels_with_name(&labels, &bucket, Some(("le", "+Inf".to_string())));
+                        series.sample(labels, value(dp.count as f64), time);
+                    }
+                    for e in &dp.exemplars {
+                        let exemplar = convert_exemplar(e);
+                        let le = dp
+                            .explicit_bounds
+                            .iter()
+                            .find(|bound| exemplar.value <= **bound)
+                            .map_or_else(|| "+Inf".to_string(), |bound| bound.to_string());
+                        let labels = labels_with_name(&labels, &bucket, Some(("le", le)));
+                        series.series(labels).exemplars.push(exemplar);
+                    }
+                    if let Some(sum) = dp.sum {
+                        let labels = labels_with_name(&labels, &format!("{name}_sum"), None);
+                        series.sample(labels, value(sum), time);
+                    }
+                    let labels = labels_with_name(&labels, &format!("{name}_count"), None);
+                    series.sample(labels, value(dp.count as f64), time);
+                    latest = latest.max(Some(time));
+                }
+            }
+            metric::Data::ExponentialHistogram(h) if h.aggregation_temporality == cumulative => {
+                series.metadata(&name, MetricType::Histogram, &metric.description, &unit);
+                for dp in &h.data_points {
+                    let Some(histogram) = native_histogram(dp) else {
+                        continue;
+                    };
+                    let labels = attribute_labels(&dp.attributes, context);
+                    let timeseries = series.series(labels_with_name(&labels, &name, None));
+                    timeseries.histograms.push(histogram);
+                    timeseries
+                        .exemplars
+                        .extend(dp.exemplars.iter().map(convert_exemplar));
+                    latest = latest.max(Some(dp.time_unix_nano));
+                }
+            }
+            metric::Data::Summary(summary) => {
+                series.metadata(&name, MetricType::Summary, &metric.description, &unit);
+                for dp in &summary.data_points {
+                    let labels = attribute_labels(&dp.attributes, context);
+                    let time = dp.time_unix_nano;
+                    let stale = no_recorded_value(dp.flags);
+                    let value = |v: f64| if stale { f64::from_bits(STALE_NAN) } else { v };
+                    for q in &dp.quantile_values {
+                        let quantile = Some(("quantile", q.quantile.to_string()));
+                        let labels = labels_with_name(&labels, &name, quantile);
+                        series.sample(labels, value(q.value), time);
+                    }
+                    let labels_sum = labels_with_name(&labels, &format!("{name}_sum"), None);
+                    series.sample(labels_sum, value(dp.sum), time);
+                    let labels = labels_with_name(&labels, &format!("{name}_count"), None);
+                    series.sample(labels, value(dp.count as f64), time);
+                    latest = latest.max(Some(time));
+                }
+            }
+            // delta temporality is not supported by Prometheus.
+            _ => {}
+        }
+        latest
+    }
+}
+
+/// Series of a conversion, in order of first appearance.
+#[derive(Default)]
+struct SeriesSet {
+    index: HashMap<Vec<Label>, usize>,
+    timeseries: Vec<TimeSeries>,
+    metadata: BTreeMap<String, MetricMetadata>,
+}
+
+impl SeriesSet {
+    fn series(&mut self, labels: Vec<Label>) -> &mut TimeSeries {
+        let index = *self.index.entry(labels.clone()).or_insert_with(|| {
+            self.timeseries.push(TimeSeries {
+                labels,
+                ..Default::default()
+            });
+            self.timeseries.len() - 1
+        });
+        &mut self.timeseries[index]
+    }
+
+    fn sample(&mut self, labels: Vec<Label>, value: f64, time_unix_nano: u64) {
+        self.series(labels).samples.push(Sample {
+            value,
+            timestamp: millis(time_unix_nano),
+        });
+    }
+
+    /// Adds a point of a gauge or sum, returning its time.
+    fn number(
+        &mut self,
+        name: &str,
+        dp: &NumberDataPoint,
+        context: &BTreeMap<String, String>,
+    ) -> Option<u64> {
+        let value = if no_recorded_value(dp.flags) {
+            f64::from_bits(STALE_NAN)
+        } else {
+            match dp.value? {
+                number_data_point::Value::AsDouble(v) => v,
+                number_data_point::Value::AsInt(v) => v as f64,
+            }
+        };
+        let labels = attribute_labels(&dp.attributes, context);
+        let labels = labels_with_name(&labels, name, None);
+        self.sample(labels.clone(), value, dp.time_unix_nano);
+        self.series(labels)
+            .exemplars
+            .extend(dp.exemplars.iter().map(convert_exemplar));
+        Some(dp.time_unix_nano)
+    }
+
+    fn metadata(&mut self, name: &str, metric_type: MetricType, help: &str, unit: &str) {
+        self.metadata
+            .entry(name.to_string())
+            .or_insert_with(|| MetricMetadata {
+                r#type: metric_type as i32,
+                metric_family_name: name.to_string(),
+                help: help.to_string(),
+                unit: unit.to_string(),
+            });
+    }
+
+    fn into_requests(self, max_series_per_request: usize) -> Vec<WriteRequest> {
+        let mut metadata: Vec<_> = self.metadata.into_values().collect();
+        let mut requests: Vec<_> = self
+            .timeseries
+            .chunks(max_series_per_request)
+            .map(|timeseries| WriteRequest {
+                timeseries: timeseries.to_vec(),
+                metadata: std::mem::take(&mut metadata),
+            })
+            .collect();
+        if requests.is_empty() && !metadata.is_empty() {
+            requests.push(WriteRequest {
+                timeseries: vec![],
+                metadata,
+            });
+        }
+        requests
+    }
+}
+
+/// Returns the `job` and `instance` labels of a resource.
+fn resource_labels(attributes: &[KeyValue]) -> BTreeMap<String, String> {
+    let attribute = |key: &str| {
+        attributes
+            .iter()
+            .find(|kv| kv.key == key)
+            .map(|kv| attribute_value(kv.value.as_ref()))
+            .filter(|v| !v.is_empty())
+    };
+    let mut labels = BTreeMap::new();
+    let job = match (attribute(SERVICE_NAMESPACE), attribute(SERVICE_NAME)) {
+        (Some(namespace), Some(name)) => Some(format!("{namespace}/{name}")),
+        (_, name) => name,
+    };
+    if let Some(job) = job {
+        labels.insert("job".to_string(), job);
+    }
+    if let Some(instance) = attribute(SERVICE_INSTANCE_ID) {
+        labels.insert("instance".to_string(), instance);
+    }
+    labels
+}
+
+/// Returns the labels of attributes, falling back to the context labels. Values of
+/// attributes mapped to the same label are joined with `;`, ordered by key.
+fn attribute_labels(
+    attributes: &[KeyValue],
+    context: &BTreeMap<String, String>,
+) -> BTreeMap<String, String> {
+    let mut grouped: BTreeMap<String, Vec<(&str, String)>> = BTreeMap::new();
+    for kv in attributes {
+        if let Some(name) = label_name(&kv.key) {
+            let value = attribute_value(kv.value.as_ref());
+            grouped.entry(name).or_default().push((&kv.key, value));
+        }
+    }
+    let mut labels: BTreeMap<_, _> = grouped
+        .into_iter()
+        .map(|(name, mut values)| {
+            values.sort();
+            let values: Vec<_> = values.into_iter().map(|(_, value)| value).collect();
+            (name, values.join(";"))
+        })
+        .collect();
+    for (name, value) in context {
+        labels.entry(name.clone()).or_insert_with(|| value.clone());
+    }
+    labels
+}
+
+/// Returns the sorted labels of a series.
+fn labels_with_name(
+    labels: &BTreeMap<String, String>,
+    name: &str,
+    extra: Option<(&str, String)>,
+) -> Vec<Label> {
+    let mut labels = labels.clone();
+    labels.insert("__name__".to_string(), name.to_string());
+    if let Some((name, value)) = extra {
+        labels.insert(name.to_string(), value);
+    }
+    labels
+        .into_iter()
+        .map(|(name, value)| Label { name, value })
+        .collect()
+}
+
+fn attribute_value(value: Option<&AnyValue>) -> String {
+    match value.and_then(|v| v.value.as_ref()) {
+        Some(any_value::Value::StringValue(s)) => s.clone(),
+        Some(any_value::Value::BytesValue(b)) => BASE64.encode(b),
+        Some(_) => json_value(value).to_string(),
+        None => String::new(),
+    }
+}
+
+/// Returns the JSON form of a value, used for array and map attributes.
+fn json_value(value: Option<&AnyValue>) -> serde_json::Value {
+    use serde_json::Value;
+    match value.and_then(|v| v.value.as_ref()) {
+        Some(any_value::Value::StringValue(s)) => Value::from(s.as_str()),
+        Some(any_value::Value::BoolValue(b)) => Value::from(*b),
+        Some(any_value::Value::IntValue(i)) => Value::from(*i),
+        Some(any_value::Value::DoubleValue(d)) => Value::from(*d),
+        Some(any_value::Value::BytesValue(b)) => Value::from(BASE64.encode(b)),
+        Some(any_value::Value::ArrayValue(a)) => {
+            Value::Array(a.values.iter().map(|v| json_value(Some(v))).collect())
+        }
+        Some(any_value::Value::KvlistValue(kv)) => Value::Object(
+            kv.values
+                .iter()
+                .map(|kv| (kv.key.clone(), json_value(kv.value.as_ref())))
+                .collect(),
+        ),
+        None => Value::Null,
+    }
+}
+
+fn convert_exemplar(e: &opentelemetry_proto::tonic::metrics::v1::Exemplar) -> Exemplar {
+    let mut labels: Vec<_> = attribute_labels(&e.filtered_attributes, &BTreeMap::new())
+        .into_iter()
+        .map(|(name, value)| Label { name, value })
+        .collect();
+    if !e.trace_id.is_empty() {
+        labels.push(Label {
+            name: "trace_id".to_string(),
+            value: hex(&e.trace_id),
+        });
+    }
+    if !e.span_id.is_empty() {
+        labels.push(Label {
+            name: "span_id".to_string(),
+            value: hex(&e.span_id),
+        });
+    }
+    labels.sort();
+    let value = match e.value {
+        Some(exemplar::Value::AsDouble(v)) => v,
+        Some(exemplar::Value::AsInt(v)) => v as f64,
+        None => 0.0,
+    };
+    Exemplar {
+        labels,
+        value,
+        timestamp: millis(e.time_unix_nano),
+    }
+}
+
+/// Converts an exponential histogram point, lowering its scale to the highest supported
+/// schema. Returns `None` for scales below the lowest one, or above the highest one allowed
+/// by OTLP.
+fn native_histogram(dp: &ExponentialHistogramDataPoint) -> Option<Histogram> {
+    if !(MIN_SCHEMA..=MAX_SCALE).contains(&dp.scale) {
+        return None;
+    }
+    let by = (dp.scale - MAX_SCHEMA).max(0) as u32;
+    let (positive_spans, positive_deltas) = spans(&downscale(&dp.positive, by)?);
+    let (negative_spans, negative_deltas) = spans(&downscale(&dp.negative, by)?);
+    let sum = if no_recorded_value(dp.flags) {
+        f64::from_bits(STALE_NAN)
+    } else {
+        dp.sum.unwrap_or_default()
+    };
+    Some(Histogram {
+        count: Some(histogram::Count::CountInt(dp.count)),
+        sum,
+        schema: dp.scale.min(MAX_SCHEMA),
+        zero_threshold: dp.zero_threshold,
+        zero_count: Some(histogram::ZeroCount::ZeroCountInt(dp.zero_count)),
+        negative_spans,
+        negative_deltas,
+        positive_spans,
+        positive_deltas,
+        reset_hint: histogram::ResetHint::Unknown as i32,
+        timestamp: millis(dp.time_unix_nano),
+        ..Default::default()
+    })
+}
+
+/// Encodes buckets as spans and delta-encoded counts. Runs of up to two empty buckets are
+/// kept inside spans, longer ones start a new span.
+fn spans(buckets: &Buckets) -> (Vec<BucketSpan>, Vec<i64>) {
+    let mut spans: Vec<BucketSpan> = vec![];
+    let mut deltas = vec![];
+    let mut previous = 0;
+    let mut next_index = None;
+    for (i, &count) in buckets.bucket_counts.iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+        // bucket `i` covers (base^i, base^(i+1)] in OTLP and (base^(i-1), base^i] in
+        // Prometheus.
+        let index = buckets.offset + i as i32 + 1;
+        match next_index {
+            Some(next) if index - next <= 2 => {
+                for _ in next..index {
+                    deltas.push(-previous);
+                    previous = 0;
+                }
+                if let Some(span) = spans.last_mut() {
+                    span.length += (index - next) as u32 + 1;
+                }
+            }
+            Some(next) => spans.push(BucketSpan {
+                offset: index - next,
+                length: 1,
+            }),
+            None => spans.push(BucketSpan {
+                offset: index,
+                length: 1,
+            }),
+        }
+        deltas.push(count as i64 - previous);
+        previous = count as i64;
+        next_index = Some(index + 1);
+    }
+    (spans, deltas)
+}
+
+fn no_recorded_value(flags: u32) -> bool {
+    flags & DataPointFlags::NoRecordedValueMask as u32 != 0
+}
+
+fn millis(time_unix_nano: u64) -> i64 {
+    (time_unix_nano / 1_000_000) as i64
+}
+
+fn hex(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    bytes
+        .iter()
+        .flat_map(|b| [DIGITS[(b >> 4) as usize], DIGITS[(b & 0xf) as usize]])
+        .map(char::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prometheus::prompb::{BucketSpan, Label, TimeSeries};
+    use crate::prometheus::RemoteWriteConverter;
+    use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+    use opentelemetry_proto::tonic::common::v1::{any_value, AnyValue, KeyValue};
+    use opentelemetry_proto::tonic::metrics::v1::exponential_histogram_data_point::Buckets;
+    use opentelemetry_proto::tonic::metrics::v1::{
+        exemplar, metric, number_data_point, AggregationTemporality, Exemplar,
+        ExponentialHistogram, ExponentialHistogramDataPoint, Histogram, HistogramDataPoint, Metric,
+        NumberDataPoint, ResourceMetrics, ScopeMetrics, Sum,
+    };
+    use opentelemetry_proto::tonic::resource::v1::Resource;
+
+    fn kv(key: &str, value: &str) -> KeyValue {
+        KeyValue {
+            key: key.to_string(),
+            value: Some(AnyValue {
+                value: Some(any_value::Value::StringValue(value.to_string())),
+            }),
+        }
+    }
+
+    fn labels(series: &TimeSeries) -> Vec<(&str, &str)> {
+        series
+            .labels
+            .iter()
+            .map(|Label { name, value }| (name.as_str(), value.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_convert() {
+        let cumulative = AggregationTemporality::Cumulative as i32;
+        let time = 2_000_000_000;
+        let metrics = vec![
+            Metric {
+                name: "http.requests".to_string(),
+                unit: "{request}".to_string(),
+                data: Some(metric::Data::Sum(Sum {
+                    data_points: vec![NumberDataPoint {
+                        attributes: vec![kv("http.method", "GET"), kv("http_method", "get")],
+                        time_unix_nano: time,
+                        value: Some(number_data_point::Value::AsInt(7)),
+                        exemplars: vec![Exemplar {
+                            time_unix_nano: time,
+                            value: Some(exemplar::Value::AsInt(1)),
+                            trace_id: vec![0xab; 16],
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    }],
+                    aggregation_temporality: cumulative,
+                    is_monotonic: true,
+                })),
+                ..Default::default()
+            },
+            Metric {
+                name: "latency".to_string(),
+                unit: "s".to_string(),
+                data: Some(metric::Data::Histogram(Histogram {
+                    data_points: vec![HistogramDataPoint {
+                        time_unix_nano: time,
+                        count: 6,
+                        sum: Some(3.5),
+                        bucket_counts: vec![1, 2, 3],
+                        explicit_bounds: vec![0.1, 1.0],
+                        ..Default::default()
+                    }],
+                    aggregation_temporality: cumulative,
+                })),
+                ..Default::default()
+            },
+            Metric {
+                name: "size".to_string(),
+                unit: "By".to_string(),
+                data: Some(metric::Data::ExponentialHistogram(ExponentialHistogram {
+                    data_points: vec![
+                        ExponentialHistogramDataPoint {
+                            time_unix_nano: time,
+                            count: 4,
+                            scale: 9,
+                            positive: Some(Buckets {
+                                offset: 0,
+                                bucket_counts: vec![1, 1, 0, 0, 0, 0, 0, 0, 2],
+                            }),
+                            ..Default::default()
+                        },
+                        // out of spec, dropped.
+                        ExponentialHistogramDataPoint {
+                            time_unix_nano: time + 1,
+                            count: 1,
+                            scale: 40,
+                            positive: Some(Buckets {
+                                offset: 0,
+                                bucket_counts: vec![1],
+                            }),
+                            ..Default::default()
+                        },
+                    ],
+                    aggregation_temporality: cumulative,
+                })),
+                ..Default::default()
+            },
+            Metric {
+                name: "dropped".to_string(),
+                data: Some(metric::Data::Sum(Sum {
+                    data_points: vec![NumberDataPoint::default()],
+                    aggregation_temporality: AggregationTemporality::Delta as i32,
+                    is_monotonic: true,
+                })),
+                ..Default::default()
+            },
+        ];
+        let request = ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                resource: Some(Resource {
+                    attributes: vec![
+                        kv("service.name", "api"),
+                        kv("service.namespace", "shop"),
+                        kv("host.name", "h1"),
+                    ],
+                    dropped_attributes_count: 0,
+                }),
+                scope_metrics: vec![ScopeMetrics {
+                    metrics,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+
+        let requests = RemoteWriteConverter::default()
+            .with_max_series_per_request(3)
+            .convert(&request);
+        assert_eq!(requests.len(), 3);
+        let families: Vec<_> = requests[0]
+            .metadata
+            .iter()
+            .map(|m| m.metric_family_name.as_str())
+            .collect();
+        assert_eq!(
+            families,
+            [
+                "http_requests_total",
+                "latency_seconds",
+                "size_bytes",
+                "target_info"
+            ]
+        );
+        let series: Vec<_> = requests.iter().flat_map(|r| &r.timeseries).collect();
+        assert_eq!(series.len(), 8);
+
+        assert_eq!(
+            labels(series[0]),
+            [
+                ("__name__", "http_requests_total"),
+                ("http_method", "GET;get"),
+                ("job", "shop/api"),
+            ]
+        );
+        assert_eq!(series[0].samples[0].value, 7.0);
+        assert_eq!(series[0].samples[0].timestamp, 2000);
+        assert_eq!(series[0].exemplars[0].labels[0].value, "ab".repeat(16));
+
+        let buckets: Vec<_> = series[1..4]
+            .iter()
+            .map(|s| (labels(s)[2].1, s.samples[0].value))
+            .collect();
+        assert_eq!(buckets, [("0.1", 1.0), ("1", 3.0), ("+Inf", 6.0)]);
+        assert_eq!(labels(series[4])[0].1, "latency_seconds_sum");
+        assert_eq!(labels(series[5])[0].1, "latency_seconds_count");
+
+        // scale 9 buckets 0..9 are scale 8 buckets 0..5, then shifted by one.
+        assert_eq!(series[6].histograms.len(), 1);
+        let histogram = &series[6].histograms[0];
+        assert_eq!(histogram.schema, 8);
+        assert_eq!(
+            histogram.positive_spans,
+            [
+                BucketSpan {
+                    offset: 1,
+                    length: 1
+                },
+                BucketSpan {
+                    offset: 3,
+                    length: 1
+                }
+            ]
+        );
+        assert_eq!(histogram.positive_deltas, [2, 0]);
+
+        assert_eq!(
+            labels(series[7]),
+            [
+                ("__name__", "target_info"),
+                ("host_name", "h1"),
+                ("job", "shop/api"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,217 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use opentelemetry_proto::tonic::metrics::v1::{metric, Metric};
+
+/// UCUM units and their Prometheus names.
+const UNITS: &[(&str, &str)] = &[
+    ("d", "days"),
+    ("h", "hours"),
+    ("min", "minutes"),
+    ("s", "seconds"),
+    ("ms", "milliseconds"),
+    ("us", "microseconds"),
+    ("ns", "nanoseconds"),
+    ("By", "bytes"),
+    ("KiBy", "kibibytes"),
+    ("MiBy", "mebibytes"),
+    ("GiBy", "gibibytes"),
+    ("TiBy", "tibibytes"),
+    ("KBy", "kilobytes"),
+    ("MBy", "megabytes"),
+    ("GBy", "gigabytes"),
+    ("TBy", "terabytes"),
+    ("m", "meters"),
+    ("V", "volts"),
+    ("A", "amperes"),
+    ("J", "joules"),
+    ("W", "watts"),
+    ("g", "grams"),
+    ("Cel", "celsius"),
+    ("Hz", "hertz"),
+    ("%", "percent"),
+];
+
+/// Units of the denominator of a rate, e.g. `m/s`.
+const PER_UNITS: &[(&str, &str)] = &[
+    ("s", "second"),
+    ("m", "minute"),
+    ("h", "hour"),
+    ("d", "day"),
+    ("w", "week"),
+    ("mo", "month"),
+    ("y", "year"),
+];
+
+/// Returns the Prometheus name of a metric family. With suffixes, the unit is appended unless
+/// the name already contains it, monotonic sums end with `_total` and gauges of unit `1` with
+/// `_ratio`.
+pub(crate) fn metric_name(metric: &Metric, add_suffixes: bool) -> String {
+    let mut name = sanitize(&metric.name, |c| {
+        c.is_ascii_alphanumeric() || c == '_' || c == ':'
+    });
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    if !add_suffixes {
+        return name;
+    }
+    let (unit, per_unit) = unit_suffixes(&metric.unit);
+    if let Some(unit) = unit
+        && !name.contains(&unit)
+    {
+        name = format!("{name}_{unit}");
+    }
+    if let Some(per_unit) = per_unit
+        && !name.contains(&format!("per_{per_unit}"))
+    {
+        name = format!("{name}_per_{per_unit}");
+    }
+    match &metric.data {
+        Some(metric::Data::Sum(sum)) if sum.is_monotonic => {
+            if let Some(stripped) = name.strip_suffix("_total") {
+                name.truncate(stripped.len());
+            }
+            name.push_str("_total");
+        }
+        Some(metric::Data::Gauge(_)) if metric.unit == "1" && !name.contains("ratio") => {
+            name.push_str("_ratio");
+        }
+        _ => {}
+    }
+    name
+}
+
+/// Returns the Prometheus unit of a metric, as sent in metadata.
+pub(crate) fn unit_name(unit: &str) -> String {
+    match unit_suffixes(unit) {
+        (Some(unit), Some(per_unit)) => format!("{unit}_per_{per_unit}"),
+        (None, Some(per_unit)) => format!("per_{per_unit}"),
+        (Some(unit), None) => unit,
+        (None, None) => String::new(),
+    }
+}
+
+/// Returns the label name of an attribute key, or `None` for empty keys.
+pub(crate) fn label_name(key: &str) -> Option<String> {
+    if key.is_empty() {
+        return None;
+    }
+    let name = sanitize(key, |c| c.is_ascii_alphanumeric() || c == '_');
+    Some(if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("key_{name}")
+    } else if name.starts_with('_') && !name.starts_with("__") {
+        format!("key{name}")
+    } else {
+        name
+    })
+}
+
+/// Replaces invalid characters with `_`, collapsing runs of them.
+fn sanitize(value: &str, valid: impl Fn(char) -> bool) -> String {
+    let mut sanitized = String::with_capacity(value.len());
+    for c in value.chars() {
+        if valid(c) {
+            sanitized.push(c);
+        } else if !sanitized.ends_with('_') {
+            sanitized.push('_');
+        }
+    }
+    sanitized
+}
+
+/// Splits a unit into its main and per-unit suffixes, ignoring `{annotations}`.
+fn unit_suffixes(unit: &str) -> (Option<String>, Option<String>) {
+    let mut stripped = String::with_capacity(unit.len());
+    let mut depth = 0;
+    for c in unit.chars() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            c if depth == 0 => stripped.push(c),
+            _ => {}
+        }
+    }
+    let (unit, per_unit) = match stripped.split_once('/') {
+        Some((unit, per_unit)) => (unit, Some(per_unit)),
+        None => (stripped.as_str(), None),
+    };
+    let lookup = |unit: &str, table: &[(&str, &str)]| {
+        let unit = unit.trim();
+        let name = table.iter().find(|(ucum, _)| *ucum == unit).map_or_else(
+            || sanitize(unit, |c| c.is_ascii_alphanumeric()),
+            |(_, name)| name.to_string(),
+        );
+        let name = name.trim_matches('_').to_string();
+        (!name.is_empty() && unit != "1").then_some(name)
+    };
+    (
+        lookup(unit, UNITS),
+        per_unit.and_then(|per_unit| lookup(per_unit, PER_UNITS)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prometheus::naming::{label_name, metric_name};
+    use opentelemetry_proto::tonic::metrics::v1::{metric, Gauge, Metric, Sum};
+
+    #[test]
+    fn test_names() {
+        let metric = |name: &str, unit: &str, data| Metric {
+            name: name.to_string(),
+            unit: unit.to_string(),
+            data: Some(data),
+            ..Default::default()
+        };
+        let counter = || {
+            metric::Data::Sum(Sum {
+                is_monotonic: true,
+                ..Default::default()
+            })
+        };
+        let gauge = || metric::Data::Gauge(Gauge::default());
+        let cases = [
+            (
+                metric("system.cpu.time", "s", counter()),
+                "system_cpu_time_seconds_total",
+            ),
+            (
+                metric("http.requests_total", "{request}", counter()),
+                "http_requests_total",
+            ),
+            (
+                metric("memory.utilization", "1", gauge()),
+                "memory_utilization_ratio",
+            ),
+            (
+                metric("net.speed", "By/s", gauge()),
+                "net_speed_bytes_per_second",
+            ),
+            (metric("latency_seconds", "s", gauge()), "latency_seconds"),
+            (metric("2xx", "", gauge()), "_2xx"),
+        ];
+        for (metric, expected) in cases {
+            assert_eq!(metric_name(&metric, true), expected);
+        }
+        assert_eq!(
+            metric_name(&metric("system.cpu.time", "s", counter()), false),
+            "system_cpu_time"
+        );
+
+        assert_eq!(label_name("http.method").unwrap(), "http_method");
+        assert_eq!(label_name("0day").unwrap(), "key_0day");
+        assert_eq!(label_name("_tag").unwrap(), "key_tag");
+        assert_eq!(label_name("__reserved").unwrap(), "__reserved");
+        assert_eq!(label_name(""), None);
+    }
+}
@@ -0,0 +1,267 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Capture and replay of OTAP streams.
+//!
+//! A capture file starts with [CAPTURE_MAGIC], followed by length-delimited [RecordedBatch]
+//! messages in the order they were received on one gRPC stream. Since [Consumer] keeps IPC
+//! reader state per `schema_id`, a capture must start at the beginning of the stream and
+//! batches must be recorded before they are consumed.
+
+use crate::decode::decoder::Consumer;
+use crate::error;
+use crate::export::protobuf::{write_delimited, DelimitedReader};
+use crate::opentelemetry::BatchArrowRecords;
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use snafu::{ensure, OptionExt, ResultExt};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Magic bytes and format version at the start of every capture file.
+pub const CAPTURE_MAGIC: &[u8; 8] = b"OTAPCAP1";
+
+/// A batch as received on the wire.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RecordedBatch {
+    /// Time the batch was recorded, in nanoseconds since unix epoch.
+    #[prost(uint64, tag = "1")]
+    pub recorded_time_unix_nano: u64,
+    #[prost(message, optional, tag = "2")]
+    pub batch: Option<BatchArrowRecords>,
+}
+
+/// Writes a capture of one OTAP stream.
+pub struct Recorder<W: Write> {
+    writer: W,
+}
+
+impl Recorder<BufWriter<File>> {
+    /// Creates a capture file at given path, truncating the existing one.
+    pub fn create(path: impl AsRef<Path>) -> error::Result<Self> {
+        let file = File::create(path).context(error::WriteMessageSnafu)?;
+        Self::try_new(BufWriter::new(file))
+    }
+}
+
+impl<W: Write> Recorder<W> {
+    pub fn try_new(mut writer: W) -> error::Result<Self> {
+        writer
+            .write_all(CAPTURE_MAGIC)
+            .context(error::WriteMessageSnafu)?;
+        Ok(Self { writer })
+    }
+
+    /// Appends a batch to the capture. Must be called before the batch is handed to
+    /// [Consumer::consume_batches], which drains its payloads.
+    pub fn record(&mut self, batch: &BatchArrowRecords) -> error::Result<()> {
+        let recorded_time_unix_nano = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        write_delimited(
+            &mut self.writer,
+            &RecordedBatch {
+                recorded_time_unix_nano,
+                batch: Some(batch.clone()),
+            },
+        )
+    }
+
+    pub fn flush(&mut self) -> error::Result<()> {
+        self.writer.flush().context(error::WriteMessageSnafu)
+    }
+
+    pub fn into_inner(mut self) -> error::Result<W> {
+        self.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Reads [RecordedBatch]es from a capture in the order they were recorded.
+pub struct Replayer<R> {
+    reader: DelimitedReader<R, RecordedBatch>,
+}
+
+impl Replayer<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> error::Result<Self> {
+        let file = File::open(path).context(error::ReadMessageSnafu)?;
+        Self::try_new(BufReader::new(file))
+    }
+}
+
+impl<R: Read> Replayer<R> {
+    pub fn try_new(mut reader: R) -> error::Result<Self> {
+        let mut magic = [0u8; CAPTURE_MAGIC.len()];
+        reader
+            .read_exact(&mut magic)
+            .context(error::ReadMessageSnafu)?;
+        ensure!(
+            &magic == CAPTURE_MAGIC,
+            error::InvalidCaptureFileSnafu {
+                message: format!("unexpected magic bytes: {:?}", magic),
+            }
+        );
+        Ok(Self {
+            reader: DelimitedReader::new(reader),
+        })
+    }
+
+    /// Returns an iterator over recorded batches without their recording timestamps.
+    pub fn batches(self) -> impl Iterator<Item = error::Result<BatchArrowRecords>> {
+        self.map(|r| {
+            r.and_then(|r| {
+                r.batch.context(error::InvalidCaptureFileSnafu {
+                    message: "recorded batch is empty",
+                })
+            })
+        })
+    }
+
+    /// Replays the capture through given consumer, yielding one decoded request per batch.
+    /// The consumer should be fresh, or at least have consumed nothing of another stream.
+    pub fn decode_with<'a>(
+        self,
+        consumer: &'a mut Consumer,
+    ) -> impl Iterator<Item = error::Result<ExportMetricsServiceRequest>> + 'a
+    where
+        R: 'a,
+    {
+        self.batches()
+            .map(|batch| consumer.consume_batches(&mut batch?))
+    }
+}
+
+impl<R: Read> Iterator for Replayer<R> {
+    type Item = error::Result<RecordedBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.reader.next()
+    }
+}
+
+/// Sends the recorded batches on a new stream to an OTAP server, returning the received
+/// statuses once the server closes the stream.
+#[cfg(feature = "client")]
+pub async fn replay_to_server(
+    client: &mut crate::opentelemetry::ArrowMetricsServiceClient<tonic::transport::Channel>,
+    batches: Vec<BatchArrowRecords>,
+) -> Result<Vec<crate::opentelemetry::BatchStatus>, tonic::Status> {
+    let mut response = client
+        .arrow_metrics(tonic::codegen::tokio_stream::iter(batches))
+        .await?
+        .into_inner();
+    let mut statuses = vec![];
+    while let Some(status) = response.message().await? {
+        statuses.push(status);
+    }
+    Ok(statuses)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decode::decoder::Consumer;
+    use crate::encode::encoder::Producer;
+    use crate::replay::{Recorder, Replayer};
+    use crate::test_util::create_gauge_batch;
+    use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+    use opentelemetry_proto::tonic::metrics::v1::metric::Data;
+    use opentelemetry_proto::tonic::metrics::v1::number_data_point::Value;
+    use opentelemetry_proto::tonic::metrics::v1::{
+        Gauge, Metric, NumberDataPoint, ResourceMetrics, ScopeMetrics,
+    };
+    use std::io::Cursor;
+
+    #[test]
+    fn test_record_and_replay() {
+        let mut producer = Producer::default();
+        let mut recorder = Recorder::try_new(vec![]).unwrap();
+        for i in 0..3 {
+            let batch = create_gauge_batch(&mut producer, &["a", "b"], &[i, i + 1]);
+            recorder.record(&batch).unwrap();
+        }
+        let capture = recorder.into_inner().unwrap();
+
+        let mut consumer = Consumer::default();
+        let requests = Replayer::try_new(Cursor::new(capture))
+            .unwrap()
+            .decode_with(&mut consumer)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(3, requests.len());
+        for (i, request) in requests.iter().enumerate() {
+            let metrics = &request.resource_metrics[0].scope_metrics[0].metrics;
+            assert_eq!("b", metrics[1].name);
+            let Some(Data::Gauge(gauge)) = &metrics[1].data else {
+                panic!("expect gauge, actual: {:?}", metrics[1].data);
+            };
+            assert_eq!(Some(Value::AsInt(i as i64 + 1)), gauge.data_points[0].value);
+        }
+    }
+
+    #[test]
+    fn test_replay_schema_change() {
+        let request = |value: Value| ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                resource: Some(Default::default()),
+                scope_metrics: vec![ScopeMetrics {
+                    scope: Some(Default::default()),
+                    metrics: vec![Metric {
+                        name: "a".to_string(),
+                        data: Some(Data::Gauge(Gauge {
+                            data_points: vec![NumberDataPoint {
+                                value: Some(value),
+                                ..Default::default()
+                            }],
+                        })),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+        // data points switch from int to double values and back, restarting their IPC stream.
+        let requests = [
+            request(Value::AsInt(1)),
+            request(Value::AsDouble(2.0)),
+            request(Value::AsInt(3)),
+        ];
+
+        let mut producer = Producer::default();
+        let mut recorder = Recorder::try_new(vec![]).unwrap();
+        let mut schema_resets = vec![];
+        for request in &requests {
+            let batch = producer.produce_batches(request).unwrap();
+            schema_resets.push(producer.last_batch_stats().unwrap().schema_resets());
+            recorder.record(&batch).unwrap();
+        }
+        assert_eq!(vec![0, 1, 1], schema_resets);
+        let capture = recorder.into_inner().unwrap();
+
+        let mut consumer = Consumer::default();
+        let mut decoded = vec![];
+        for request in Replayer::try_new(Cursor::new(capture))
+            .unwrap()
+            .decode_with(&mut consumer)
+        {
+            decoded.push(request.unwrap());
+        }
+        assert_eq!(requests.to_vec(), decoded);
+    }
+
+    #[test]
+    fn test_invalid_capture() {
+        assert!(Replayer::try_new(Cursor::new(b"not a capture".to_vec())).is_err());
+    }
+}
@@ -13,3 +13,6 @@
 //todo: support schema transformation if we need to implement the encoding part.
 
 pub mod consts;
+mod id;
+
+pub use id::schema_id;
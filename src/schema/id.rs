@@ -0,0 +1,162 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::error;
+use arrow::datatypes::{DataType, Field, Fields, Schema, TimeUnit, UnionMode};
+
+/// Returns a canonical description of a schema, to log and compare the schemas of payloads,
+/// e.g. across encoders. It borrows the notation of `SchemaToID` of the Go implementation but
+/// is not guaranteed to produce the same ids, and the encoder does not send it.
+///
+/// The id lists the fields sorted by name at every nesting level, e.g.
+/// `id:U16,name:Dic<U8,Str>,resource:{id:U16,schema_url:Str}`. Field order, nullability and
+/// metadata are not part of the id, so it does not detect every schema change of an IPC
+/// stream. The `schema_id` sent in `ArrowPayload` is a counter.
+pub fn schema_id(schema: &Schema) -> error::Result<String> {
+    sorted_fields_id(schema.fields())
+}
+
+fn sorted_fields_id(fields: &Fields) -> error::Result<String> {
+    let mut fields: Vec<_> = fields.iter().collect();
+    fields.sort_by(|a, b| a.name().cmp(b.name()));
+    let ids = fields
+        .into_iter()
+        .map(|f| field_id(f))
+        .collect::<error::Result<Vec<_>>>()?;
+    Ok(ids.join(","))
+}
+
+fn field_id(field: &Field) -> error::Result<String> {
+    Ok(format!(
+        "{}:{}",
+        field.name(),
+        data_type_id(field.data_type())?
+    ))
+}
+
+fn data_type_id(data_type: &DataType) -> error::Result<String> {
+    let id = match data_type {
+        DataType::Boolean => "Bol".to_string(),
+        DataType::Int8 => "I8".to_string(),
+        DataType::Int16 => "I16".to_string(),
+        DataType::Int32 => "I32".to_string(),
+        DataType::Int64 => "I64".to_string(),
+        DataType::UInt8 => "U8".to_string(),
+        DataType::UInt16 => "U16".to_string(),
+        DataType::UInt32 => "U32".to_string(),
+        DataType::UInt64 => "U64".to_string(),
+        DataType::Float32 => "F32".to_string(),
+        DataType::Float64 => "F64".to_string(),
+        DataType::Utf8 => "Str".to_string(),
+        DataType::Binary => "Bin".to_string(),
+        DataType::Timestamp(unit, None) => format!("T{}", time_unit_id(unit)),
+        DataType::Timestamp(unit, Some(tz)) => format!("T{}<{tz}>", time_unit_id(unit)),
+        DataType::Duration(unit) => format!("D{}", time_unit_id(unit)),
+        DataType::FixedSizeBinary(width) => format!("FSB<{width}>"),
+        DataType::Struct(fields) => format!("{{{}}}", sorted_fields_id(fields)?),
+        DataType::List(item) => format!("[{}]", data_type_id(item.data_type())?),
+        DataType::Dictionary(key, value) => {
+            format!("Dic<{},{}>", data_type_id(key)?, data_type_id(value)?)
+        }
+        DataType::Map(entries, _) => match entries.data_type() {
+            DataType::Struct(kv) if kv.len() == 2 => format!(
+                "Map<{},{}>",
+                data_type_id(kv[0].data_type())?,
+                data_type_id(kv[1].data_type())?
+            ),
+            _ => {
+                return error::UnsupportedSchemaDataTypeSnafu {
+                    data_type: data_type.clone(),
+                }
+                .fail()
+            }
+        },
+        DataType::Union(fields, mode) => {
+            // union fields keep their declaration order.
+            let ids = fields
+                .iter()
+                .map(|(_, f)| field_id(f))
+                .collect::<error::Result<Vec<_>>>()?;
+            let prefix = match mode {
+                UnionMode::Dense => "DU",
+                UnionMode::Sparse => "SU",
+            };
+            format!("{prefix}{{{}}}", ids.join(","))
+        }
+        _ => {
+            return error::UnsupportedSchemaDataTypeSnafu {
+                data_type: data_type.clone(),
+            }
+            .fail()
+        }
+    };
+    Ok(id)
+}
+
+fn time_unit_id(unit: &TimeUnit) -> &'static str {
+    match unit {
+        TimeUnit::Second => "s",
+        TimeUnit::Millisecond => "ms",
+        TimeUnit::Microsecond => "us",
+        TimeUnit::Nanosecond => "ns",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schema::schema_id;
+    use arrow::datatypes::{DataType, Field, Fields, Schema, TimeUnit};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_schema_id() {
+        let resource = Fields::from(vec![
+            Field::new("schema_url", DataType::Utf8, true),
+            Field::new("id", DataType::UInt16, true),
+        ]);
+        let schema = Schema::new(vec![
+            Field::new(
+                "time",
+                DataType::Timestamp(TimeUnit::Nanosecond, None),
+                false,
+            ),
+            Field::new(
+                "name",
+                DataType::Dictionary(Box::new(DataType::UInt8), Box::new(DataType::Utf8)),
+                false,
+            ),
+            Field::new("resource", DataType::Struct(resource), true),
+            Field::new(
+                "bounds",
+                DataType::List(Arc::new(Field::new("item", DataType::Float64, true))),
+                true,
+            ),
+            Field::new("id", DataType::UInt16, false),
+        ]);
+        assert_eq!(
+            schema_id(&schema).unwrap(),
+            "bounds:[F64],id:U16,name:Dic<U8,Str>,resource:{id:U16,schema_url:Str},time:Tns"
+        );
+
+        // time units and zones are part of the id.
+        let ids = [
+            DataType::Timestamp(TimeUnit::Microsecond, None),
+            DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into())),
+            DataType::Duration(TimeUnit::Second),
+        ]
+        .map(|data_type| schema_id(&Schema::new(vec![Field::new("t", data_type, false)])).unwrap());
+        assert_eq!(ids, ["t:Tus", "t:Tns<UTC>", "t:Ds"]);
+
+        let unsupported = Schema::new(vec![Field::new("s", DataType::LargeUtf8, false)]);
+        assert!(schema_id(&unsupported).is_err());
+    }
+}
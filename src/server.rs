@@ -0,0 +1,411 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! OTAP metrics server, decoding received batches and handing them to a [MetricsSink].
+
+pub mod admission;
+pub mod auth;
+
+use crate::decode::decoder::Consumer;
+use crate::hpack::Headers;
+use crate::opentelemetry::{ArrowMetricsService, BatchArrowRecords, BatchStatus, StatusCode};
+use crate::server::admission::{Admission, AdmissionLimits};
+use crate::server::auth::{Authenticator, Tenant};
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Semaphore};
+use tokio::time::Instant;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status, Streaming};
+
+/// Number of batch statuses buffered per stream before the server stops reading batches.
+const STATUS_CHANNEL_SIZE: usize = 16;
+
+/// Decoded batch handed to a [MetricsSink].
+#[derive(Clone, Debug)]
+pub struct ReceivedMetrics {
+    pub batch_id: i64,
+    pub request: ExportMetricsServiceRequest,
+    /// Decoded headers of the batch.
+    pub headers: Headers,
+    /// Tenant resolved by the [Authenticator], if any.
+    pub tenant: Option<Tenant>,
+}
+
+/// Destination of the metrics received by a [MetricsServer].
+#[tonic::async_trait]
+pub trait MetricsSink: Send + Sync + 'static {
+    /// Exports one decoded batch. An error is reported to the client in the `BatchStatus`
+    /// of the batch.
+    async fn export(&self, metrics: ReceivedMetrics) -> Result<(), Status>;
+}
+
+/// Bounds on the duration of a stream. Once reached, the server stops reading batches and
+/// ends the stream after acknowledging the batches already read, so that clients move to a
+/// new stream. Clients should rotate streams before these limits to not lose batches in
+/// flight.
+#[derive(Clone, Debug, Default)]
+pub struct StreamLimits {
+    /// Maximum time since the stream was opened.
+    pub max_lifetime: Option<Duration>,
+    /// Maximum time without receiving a batch.
+    pub idle_timeout: Option<Duration>,
+}
+
+/// Implementation of [ArrowMetricsService], one [Consumer] per stream.
+///
+/// Batches of a stream are decoded in order, then exported concurrently within the
+/// [AdmissionLimits] of the server.
+pub struct MetricsServer<A, S> {
+    authenticator: Arc<A>,
+    sink: Arc<S>,
+    admission: Arc<Admission>,
+    stream_limits: StreamLimits,
+}
+
+impl<A: Authenticator, S: MetricsSink> MetricsServer<A, S> {
+    pub fn new(authenticator: A, sink: S) -> Self {
+        Self {
+            authenticator: Arc::new(authenticator),
+            sink: Arc::new(sink),
+            admission: Arc::new(Admission::new(AdmissionLimits::default())),
+            stream_limits: StreamLimits::default(),
+        }
+    }
+
+    pub fn with_limits(mut self, limits: AdmissionLimits) -> Self {
+        self.admission = Arc::new(Admission::new(limits));
+        self
+    }
+
+    pub fn with_stream_limits(mut self, limits: StreamLimits) -> Self {
+        self.stream_limits = limits;
+        self
+    }
+}
+
+#[tonic::async_trait]
+impl<A: Authenticator, S: MetricsSink> ArrowMetricsService for MetricsServer<A, S> {
+    type ArrowMetricsStream = ReceiverStream<Result<BatchStatus, Status>>;
+
+    async fn arrow_metrics(
+        &self,
+        request: Request<Streaming<BatchArrowRecords>>,
+    ) -> Result<Response<Self::ArrowMetricsStream>, Status> {
+        let tenant = self
+            .authenticator
+            .authenticate_stream(request.metadata())
+            .await?;
+        let mut batches = request.into_inner();
+        let mut handler = StreamHandler {
+            consumer: Consumer::default(),
+            tenant,
+            authenticator: self.authenticator.clone(),
+        };
+        let sink = self.sink.clone();
+        let admission = self.admission.clone();
+        let stream_slots = Arc::new(Semaphore::new(admission.limits().max_in_flight_per_stream));
+        let StreamLimits {
+            max_lifetime,
+            idle_timeout,
+        } = self.stream_limits;
+        let deadline = max_lifetime.map(|d| Instant::now() + d);
+        let (tx, rx) = mpsc::channel(STATUS_CHANNEL_SIZE);
+        tokio::spawn(async move {
+            loop {
+                let idle_deadline = idle_timeout.map(|d| Instant::now() + d);
+                let next = match deadline.into_iter().chain(idle_deadline).min() {
+                    Some(wake) => match tokio::time::timeout_at(wake, batches.message()).await {
+                        Ok(next) => next,
+                        // in-flight exports still hold senders, so their statuses are sent
+                        // before the stream ends.
+                        Err(_) => break,
+                    },
+                    None => batches.message().await,
+                };
+                let mut batch = match next {
+                    Ok(Some(batch)) => batch,
+                    Ok(None) => break,
+                    Err(status) => {
+                        let _ = tx.send(Err(status)).await;
+                        break;
+                    }
+                };
+                // safety: the semaphore is never closed
+                let stream_permit = stream_slots.clone().acquire_owned().await.unwrap();
+                let status = match admission.try_admit(&batch) {
+                    Err(message) => {
+                        // rejected batches are still read to keep the stream state in sync.
+                        handler.skip(&mut batch);
+                        batch_status(batch.batch_id, StatusCode::ResourceExhausted, &message)
+                    }
+                    Ok(permit) => match handler.prepare(&mut batch).await {
+                        Err(status) => status,
+                        Ok(metrics) => {
+                            let (sink, tx) = (sink.clone(), tx.clone());
+                            tokio::spawn(async move {
+                                let status = export(sink.as_ref(), metrics).await;
+                                drop((permit, stream_permit));
+                                let _ = tx.send(Ok(status)).await;
+                            });
+                            continue;
+                        }
+                    },
+                };
+                drop(stream_permit);
+                if tx.send(Ok(status)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+/// Decoding state of one OTAP stream on the server.
+pub(crate) struct StreamHandler<A> {
+    consumer: Consumer,
+    tenant: Option<Tenant>,
+    authenticator: Arc<A>,
+}
+
+impl<A: Authenticator> StreamHandler<A> {
+    /// Decodes and authenticates one batch, or returns the status rejecting it. Headers and
+    /// records are read before authenticating since IPC and HPACK state must follow every
+    /// batch of the stream, but OTLP is only built for authenticated batches.
+    pub(crate) async fn prepare(
+        &mut self,
+        batch: &mut BatchArrowRecords,
+    ) -> Result<ReceivedMetrics, BatchStatus> {
+        let batch_id = batch.batch_id;
+        let records = self.consumer.consume_records(batch);
+        let headers = self.consumer.last_batch_headers().clone();
+        let tenant = self
+            .authenticator
+            .authenticate_batch(self.tenant.as_ref(), &headers)
+            .await
+            .map_err(|e| batch_status(batch_id, e.status_code(), e.message()))?;
+        let request = records
+            .and_then(|records| self.consumer.decode_records(&records))
+            .map_err(|e| batch_status(batch_id, StatusCode::InvalidArgument, &e.to_string()))?;
+        Ok(ReceivedMetrics {
+            batch_id,
+            request,
+            headers,
+            tenant,
+        })
+    }
+
+    /// Reads the headers and records of a batch rejected before decoding, only to advance the
+    /// IPC and HPACK state of the stream.
+    pub(crate) fn skip(&mut self, batch: &mut BatchArrowRecords) {
+        let _ = self.consumer.consume_records(batch);
+    }
+}
+
+/// Exports a prepared batch, returning its status.
+pub(crate) async fn export<S: MetricsSink>(sink: &S, metrics: ReceivedMetrics) -> BatchStatus {
+    let batch_id = metrics.batch_id;
+    match sink.export(metrics).await {
+        Ok(()) => batch_status(batch_id, StatusCode::Ok, ""),
+        Err(status) => batch_status(batch_id, status_code(status.code()), status.message()),
+    }
+}
+
+fn batch_status(batch_id: i64, status_code: StatusCode, status_message: &str) -> BatchStatus {
+    #[cfg(feature = "trace")]
+    match status_code {
+        StatusCode::Ok => tracing::debug!(batch_id, "batch accepted"),
+        _ => tracing::warn!(
+            batch_id,
+            status_code = status_code.as_str_name(),
+            status_message,
+            "batch rejected"
+        ),
+    }
+    BatchStatus {
+        batch_id,
+        status_code: status_code as i32,
+        status_message: status_message.to_string(),
+    }
+}
+
+/// Maps a gRPC code to the closest OTAP status code.
+fn status_code(code: tonic::Code) -> StatusCode {
+    match code {
+        tonic::Code::Ok => StatusCode::Ok,
+        tonic::Code::Cancelled => StatusCode::Canceled,
+        tonic::Code::InvalidArgument => StatusCode::InvalidArgument,
+        tonic::Code::DeadlineExceeded => StatusCode::DeadlineExceeded,
+        tonic::Code::PermissionDenied => StatusCode::PermissionDenied,
+        tonic::Code::ResourceExhausted => StatusCode::ResourceExhausted,
+        tonic::Code::Aborted => StatusCode::Aborted,
+        tonic::Code::Unavailable => StatusCode::Unavailable,
+        tonic::Code::Unauthenticated => StatusCode::Unauthenticated,
+        _ => StatusCode::Internal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decode::decoder::Consumer;
+    use crate::encode::encoder::Producer;
+    use crate::hpack::Headers;
+    use crate::opentelemetry::StatusCode;
+    use crate::server::auth::{AuthError, Authenticator, Tenant};
+    use crate::server::{export, MetricsSink, ReceivedMetrics, StreamHandler};
+    use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+    use std::sync::{Arc, Mutex};
+    use tonic::metadata::MetadataMap;
+    use tonic::Status;
+
+    /// Maps the `x-tenant` header to a tenant, only allowing tenant `a`.
+    struct TenantHeader;
+
+    #[tonic::async_trait]
+    impl Authenticator for TenantHeader {
+        async fn authenticate_stream(&self, _: &MetadataMap) -> Result<Option<Tenant>, AuthError> {
+            Ok(None)
+        }
+
+        async fn authenticate_batch(
+            &self,
+            _: Option<&Tenant>,
+            headers: &Headers,
+        ) -> Result<Option<Tenant>, AuthError> {
+            match headers.get("x-tenant").map(|v| v[0].as_str()) {
+                None => Err(AuthError::Unauthenticated("missing tenant".to_string())),
+                Some("a") => Ok(Some(Tenant::new("a"))),
+                Some(t) => Err(AuthError::PermissionDenied(format!("tenant {t} denied"))),
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct Collect(Mutex<Vec<ReceivedMetrics>>);
+
+    #[tonic::async_trait]
+    impl MetricsSink for Arc<Collect> {
+        async fn export(&self, metrics: ReceivedMetrics) -> Result<(), Status> {
+            self.0.lock().unwrap().push(metrics);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_batches() {
+        let sink = Arc::new(Collect::default());
+        let mut handler = StreamHandler {
+            consumer: Consumer::default(),
+            tenant: None,
+            authenticator: Arc::new(TenantHeader),
+        };
+        let mut producer = Producer::default();
+        let request = ExportMetricsServiceRequest::default();
+        let cases: [(&[(&str, &str)], StatusCode); 4] = [
+            (&[("x-tenant", "a")], StatusCode::Ok),
+            (&[], StatusCode::Unauthenticated),
+            (&[("x-tenant", "b")], StatusCode::PermissionDenied),
+            (&[("x-tenant", "a")], StatusCode::Ok),
+        ];
+        for (batch_id, (headers, code)) in cases.into_iter().enumerate() {
+            let mut batch = producer
+                .produce_batches_with_headers(&request, headers)
+                .unwrap();
+            let status = match handler.prepare(&mut batch).await {
+                Ok(metrics) => export(&sink, metrics).await,
+                Err(status) => status,
+            };
+            assert_eq!(status.batch_id, batch_id as i64);
+            assert_eq!(status.status_code, code as i32);
+        }
+
+        let received = sink.0.lock().unwrap();
+        let batch_ids: Vec<_> = received.iter().map(|m| m.batch_id).collect();
+        assert_eq!(batch_ids, vec![0, 3]);
+        assert!(received.iter().all(|m| m.tenant == Some(Tenant::new("a"))));
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_after_empty_batch() {
+        let sink = Arc::new(Collect::default());
+        let mut handler = StreamHandler {
+            consumer: Consumer::default(),
+            tenant: None,
+            authenticator: Arc::new(TenantHeader),
+        };
+        let mut producer = Producer::default();
+        let request = ExportMetricsServiceRequest::default();
+        let mut batches = vec![
+            producer
+                .produce_batches_with_headers(&request, &[("x-tenant", "a")])
+                .unwrap(),
+            producer
+                .produce_records_with_headers(vec![], &[("x-tenant", "b")])
+                .unwrap(),
+            producer
+                .produce_batches_with_headers(&request, &[("x-tenant", "b")])
+                .unwrap(),
+        ];
+        // the last batch only refers to the entry added by the empty one.
+        assert_eq!(batches[2].headers, vec![190]);
+
+        let mut codes = vec![];
+        for batch in &mut batches {
+            let status = match handler.prepare(batch).await {
+                Ok(metrics) => export(&sink, metrics).await,
+                Err(status) => status,
+            };
+            codes.push(status.status_code);
+        }
+        assert_eq!(
+            codes,
+            [
+                StatusCode::Ok,
+                StatusCode::PermissionDenied,
+                StatusCode::PermissionDenied
+            ]
+            .map(|c| c as i32)
+        );
+        assert_eq!(sink.0.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_skip_batches() {
+        let mut handler = StreamHandler {
+            consumer: Consumer::default(),
+            tenant: None,
+            authenticator: Arc::new(TenantHeader),
+        };
+        let mut producer = Producer::default();
+        let request = ExportMetricsServiceRequest::default();
+        for skip in [true, false] {
+            let mut batch = producer
+                .produce_batches_with_headers(&request, &[("x-tenant", "a")])
+                .unwrap();
+            if skip {
+                handler.skip(&mut batch);
+                assert!(handler
+                    .consumer
+                    .last_batch_stats()
+                    .unwrap()
+                    .otlp_bytes
+                    .is_none());
+            } else {
+                // IPC and HPACK state follow the skipped batch.
+                let metrics = handler.prepare(&mut batch).await.unwrap();
+                assert_eq!(metrics.request, request);
+            }
+        }
+    }
+}
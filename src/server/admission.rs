@@ -0,0 +1,152 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::opentelemetry::BatchArrowRecords;
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Bounds on the batches a server holds at once, from receipt until their export completes.
+#[derive(Clone, Debug)]
+pub struct AdmissionLimits {
+    /// Batches of one stream in flight. A stream at this limit stops reading batches until
+    /// one completes, pushing back on the client through gRPC flow control. At least 1.
+    pub max_in_flight_per_stream: usize,
+    /// Batches in flight over all streams. Batches beyond it are rejected with
+    /// `ResourceExhausted`. At least 1.
+    pub max_in_flight: usize,
+    /// Bytes of `ArrowPayload.record` in flight over all streams. Batches beyond it are
+    /// rejected with `ResourceExhausted`.
+    pub max_in_flight_bytes: usize,
+}
+
+impl Default for AdmissionLimits {
+    fn default() -> Self {
+        Self {
+            max_in_flight_per_stream: 8,
+            max_in_flight: 1024,
+            max_in_flight_bytes: 256 << 20,
+        }
+    }
+}
+
+/// Global admission state shared by all streams of a server.
+#[derive(Debug)]
+pub(crate) struct Admission {
+    limits: AdmissionLimits,
+    batches: Arc<Semaphore>,
+    bytes: Arc<Semaphore>,
+}
+
+/// Resources held by an admitted batch, released on drop.
+#[derive(Debug)]
+pub(crate) struct AdmissionPermit {
+    _batch: OwnedSemaphorePermit,
+    _bytes: Option<OwnedSemaphorePermit>,
+}
+
+impl Admission {
+    /// Raises limits on batch counts below 1, which would stall or reject every batch.
+    pub(crate) fn new(mut limits: AdmissionLimits) -> Self {
+        limits.max_in_flight_per_stream = limits.max_in_flight_per_stream.max(1);
+        limits.max_in_flight = limits.max_in_flight.max(1);
+        let max_bytes = limits.max_in_flight_bytes.min(Semaphore::MAX_PERMITS);
+        Self {
+            batches: Arc::new(Semaphore::new(limits.max_in_flight)),
+            bytes: Arc::new(Semaphore::new(max_bytes)),
+            limits,
+        }
+    }
+
+    pub(crate) fn limits(&self) -> &AdmissionLimits {
+        &self.limits
+    }
+
+    /// Admits the batch if it fits in the global limits, or else returns why it doesn't.
+    pub(crate) fn try_admit(&self, batch: &BatchArrowRecords) -> Result<AdmissionPermit, String> {
+        let size: usize = batch.arrow_payloads.iter().map(|p| p.record.len()).sum();
+        if size > self.limits.max_in_flight_bytes {
+            return Err(format!(
+                "batch of {size} bytes exceeds the limit of {} bytes",
+                self.limits.max_in_flight_bytes
+            ));
+        }
+        let batch_permit = self
+            .batches
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| "too many batches in flight".to_string())?;
+        let bytes_permit = match u32::try_from(size) {
+            Ok(0) => None,
+            Ok(size) => Some(self.bytes.clone().try_acquire_many_owned(size)),
+            Err(_) => return Err(format!("batch of {size} bytes is too large")),
+        };
+        let bytes_permit = bytes_permit
+            .transpose()
+            .map_err(|_| format!("no memory budget left for a batch of {size} bytes"))?;
+        Ok(AdmissionPermit {
+            _batch: batch_permit,
+            _bytes: bytes_permit,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::opentelemetry::{ArrowPayload, BatchArrowRecords};
+    use crate::server::admission::{Admission, AdmissionLimits};
+
+    fn batch(size: usize) -> BatchArrowRecords {
+        BatchArrowRecords {
+            batch_id: 0,
+            arrow_payloads: vec![ArrowPayload {
+                record: vec![0; size],
+                ..Default::default()
+            }],
+            headers: vec![],
+        }
+    }
+
+    #[test]
+    fn test_try_admit() {
+        let admission = Admission::new(AdmissionLimits {
+            max_in_flight_per_stream: 1,
+            max_in_flight: 2,
+            max_in_flight_bytes: 100,
+        });
+        assert!(admission.try_admit(&batch(101)).is_err());
+
+        let first = admission.try_admit(&batch(60)).unwrap();
+        // over the memory budget
+        assert!(admission.try_admit(&batch(60)).is_err());
+        let second = admission.try_admit(&batch(40)).unwrap();
+        // over the batch count
+        assert!(admission.try_admit(&batch(0)).is_err());
+
+        drop(first);
+        drop(second);
+        assert!(admission.try_admit(&batch(100)).is_ok());
+    }
+
+    #[test]
+    fn test_zero_limits() {
+        let admission = Admission::new(AdmissionLimits {
+            max_in_flight_per_stream: 0,
+            max_in_flight: 0,
+            max_in_flight_bytes: 100,
+        });
+        assert_eq!(admission.limits().max_in_flight_per_stream, 1);
+        let first = admission.try_admit(&batch(10)).unwrap();
+        assert!(admission.try_admit(&batch(10)).is_err());
+        drop(first);
+        assert!(admission.try_admit(&batch(10)).is_ok());
+    }
+}
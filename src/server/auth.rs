@@ -0,0 +1,93 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::hpack::Headers;
+use crate::opentelemetry::StatusCode;
+use tonic::metadata::MetadataMap;
+
+/// Identity of the tenant data was received from, attached to decoded requests.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Tenant {
+    pub id: String,
+}
+
+impl Tenant {
+    pub fn new(id: impl Into<String>) -> Self {
+        Self { id: id.into() }
+    }
+}
+
+/// Reason a stream or batch was rejected by an [Authenticator].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AuthError {
+    /// Credentials are missing or invalid.
+    Unauthenticated(String),
+    /// Credentials are valid but not allowed to send this data.
+    PermissionDenied(String),
+}
+
+impl AuthError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            AuthError::Unauthenticated(_) => StatusCode::Unauthenticated,
+            AuthError::PermissionDenied(_) => StatusCode::PermissionDenied,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            AuthError::Unauthenticated(message) | AuthError::PermissionDenied(message) => message,
+        }
+    }
+}
+
+impl From<AuthError> for tonic::Status {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::Unauthenticated(message) => tonic::Status::unauthenticated(message),
+            AuthError::PermissionDenied(message) => tonic::Status::permission_denied(message),
+        }
+    }
+}
+
+/// Authenticates OTAP streams and their batches, and resolves the tenant they belong to.
+#[tonic::async_trait]
+pub trait Authenticator: Send + Sync + 'static {
+    /// Called once when a stream opens, with the gRPC metadata of the call. An error rejects
+    /// the whole stream with the matching gRPC status.
+    async fn authenticate_stream(
+        &self,
+        metadata: &MetadataMap,
+    ) -> Result<Option<Tenant>, AuthError>;
+
+    /// Called for every batch with its decoded headers and the tenant of the stream. An error
+    /// rejects the batch with the matching [StatusCode] in its `BatchStatus`, while the
+    /// stream stays open. By default, batches belong to the tenant of their stream.
+    async fn authenticate_batch(
+        &self,
+        stream_tenant: Option<&Tenant>,
+        _headers: &Headers,
+    ) -> Result<Option<Tenant>, AuthError> {
+        Ok(stream_tenant.cloned())
+    }
+}
+
+/// Accepts every stream and batch without tenant.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AllowAll;
+
+#[tonic::async_trait]
+impl Authenticator for AllowAll {
+    async fn authenticate_stream(&self, _: &MetadataMap) -> Result<Option<Tenant>, AuthError> {
+        Ok(None)
+    }
+}
@@ -0,0 +1,149 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Self-telemetry, enabled by the `trace` feature.
+//!
+//! Spans and events are emitted with [tracing], counters and histograms through the
+//! [metrics] facade. Both are no-ops until the application installs a subscriber/recorder.
+
+use crate::decode::stats::BatchStats;
+use crate::error;
+use std::time::Duration;
+
+/// Counter of consumed batches, labeled by `outcome` (`ok` or `error`).
+pub const BATCHES: &str = "otap_decoder_batches_total";
+/// Counter of consumed payloads, labeled by `payload_type`.
+pub const PAYLOADS: &str = "otap_decoder_payloads_total";
+/// Counter of rows read from payloads, labeled by `payload_type`.
+pub const ROWS: &str = "otap_decoder_rows_total";
+/// Counter of Arrow IPC bytes read from payloads, labeled by `payload_type`.
+pub const IPC_BYTES: &str = "otap_decoder_ipc_bytes_total";
+/// Counter of IPC stream restarts caused by a schema change, labeled by `payload_type`.
+pub const SCHEMA_RESETS: &str = "otap_decoder_schema_resets_total";
+/// Counter of decode errors, labeled by `kind`, see [error::Error::kind].
+pub const ERRORS: &str = "otap_decoder_errors_total";
+/// Histogram of batch decode latency in seconds.
+pub const DECODE_DURATION: &str = "otap_decoder_decode_duration_seconds";
+
+/// Records the outcome of [crate::Consumer::consume_batches].
+pub(crate) fn record_consume<T>(
+    elapsed: Duration,
+    stats: Option<&BatchStats>,
+    result: &error::Result<T>,
+) {
+    if let Some(stats) = stats {
+        record_payloads(stats);
+    }
+    record_outcome(elapsed, result);
+}
+
+/// Records the payloads read from a batch, also called with the payloads read before a
+/// batch fails partway.
+pub(crate) fn record_payloads(stats: &BatchStats) {
+    for (payload_type, payload) in &stats.payloads {
+        let payload_type = payload_type.as_str_name();
+        metrics::counter!(PAYLOADS, "payload_type" => payload_type)
+            .increment(payload.payloads as u64);
+        metrics::counter!(ROWS, "payload_type" => payload_type).increment(payload.rows as u64);
+        metrics::counter!(IPC_BYTES, "payload_type" => payload_type)
+            .increment(payload.ipc_bytes as u64);
+        if payload.schema_resets > 0 {
+            metrics::counter!(SCHEMA_RESETS, "payload_type" => payload_type)
+                .increment(payload.schema_resets as u64);
+        }
+    }
+}
+
+/// Records the latency and outcome of consuming a batch.
+pub(crate) fn record_outcome<T>(elapsed: Duration, result: &error::Result<T>) {
+    metrics::histogram!(DECODE_DURATION).record(elapsed.as_secs_f64());
+    match result {
+        Ok(_) => {
+            metrics::counter!(BATCHES, "outcome" => "ok").increment(1);
+            tracing::debug!(elapsed_us = elapsed.as_micros() as u64, "batch decoded");
+        }
+        Err(e) => {
+            metrics::counter!(BATCHES, "outcome" => "error").increment(1);
+            metrics::counter!(ERRORS, "kind" => e.kind()).increment(1);
+            tracing::warn!(error = %e, kind = e.kind(), "failed to decode batch");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decode::decoder::Consumer;
+    use crate::encode::encoder::Producer;
+    use crate::telemetry::{BATCHES, ERRORS, PAYLOADS};
+    use crate::test_util::create_gauge_batch;
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder, Snapshotter};
+
+    fn counter(snapshotter: &Snapshotter, name: &str, label: (&str, &str)) -> u64 {
+        snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .filter(|(key, ..)| {
+                let key = key.key();
+                key.name() == name
+                    && key
+                        .labels()
+                        .any(|l| l.key() == label.0 && l.value() == label.1)
+            })
+            .map(|(.., value)| match value {
+                DebugValue::Counter(v) => v,
+                v => panic!("expect counter, actual: {:?}", v),
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_record_failed_batches() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        metrics::with_local_recorder(&recorder, || {
+            let mut producer = Producer::default();
+            let mut consumer = Consumer::default();
+            // the data points payload is cut short, after the metrics payload was read.
+            let mut batch = create_gauge_batch(&mut producer, &["a"], &[1]);
+            batch.arrow_payloads[1].record.truncate(8);
+            consumer.consume_batches(&mut batch).unwrap_err();
+            // a broken header block rejects the batch before any payload is read.
+            let mut batch = create_gauge_batch(&mut producer, &["a"], &[1]);
+            batch.headers = vec![0xbf];
+            consumer.consume_batches(&mut batch).unwrap_err();
+            let mut batch = create_gauge_batch(&mut producer, &["a"], &[1]);
+            batch.headers = vec![0xbf];
+            assert!(consumer.consume_records(&mut batch).is_err());
+        });
+
+        assert_eq!(3, counter(&snapshotter, BATCHES, ("outcome", "error")));
+        assert_eq!(0, counter(&snapshotter, BATCHES, ("outcome", "ok")));
+        assert_eq!(2, counter(&snapshotter, ERRORS, ("kind", "invalid_hpack")));
+        assert_eq!(
+            1,
+            counter(
+                &snapshotter,
+                PAYLOADS,
+                ("payload_type", "UNIVARIATE_METRICS")
+            )
+        );
+        assert_eq!(
+            0,
+            counter(
+                &snapshotter,
+                PAYLOADS,
+                ("payload_type", "NUMBER_DATA_POINTS")
+            )
+        );
+    }
+}
@@ -0,0 +1,821 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conversion of the aggregation temporality of decoded metrics.
+//!
+//! Sums, histograms and exponential histograms are converted point by point, keeping the
+//! last point of every stream. A stream is identified by its resource, scope, metric name,
+//! unit and type, and data point attributes, regardless of the order of attributes.
+
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use opentelemetry_proto::tonic::common::v1::KeyValue;
+use opentelemetry_proto::tonic::metrics::v1::exponential_histogram_data_point::Buckets;
+use opentelemetry_proto::tonic::metrics::v1::{
+    metric, number_data_point, AggregationTemporality, DataPointFlags, Exemplar,
+    ExponentialHistogramDataPoint, HistogramDataPoint, NumberDataPoint,
+};
+use prost::encoding::encode_varint;
+use prost::Message;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Streams without a point for this long, relative to the latest point seen, are forgotten.
+pub const DEFAULT_MAX_STALE: Duration = Duration::from_secs(300);
+
+/// Scales of exponential histograms allowed by the OTLP specification.
+pub(crate) const MIN_SCALE: i32 = -10;
+pub(crate) const MAX_SCALE: i32 = 20;
+
+/// Most buckets of a merged bucket range, beyond which the stream restarts instead.
+const MAX_BUCKETS: i64 = 1 << 16;
+
+/// Converts sums, histograms and exponential histograms to a target temporality.
+///
+/// To cumulative, delta points are added to the running total of their stream, which keeps
+/// the start time of its first point. Points overlapping the last one are dropped, and a
+/// change of histogram bounds or value type restarts the stream.
+///
+/// To delta, each point is subtracted from the previous point of its stream, starting at the
+/// previous point's time. The first point of a stream, and the first point after a reset
+/// (a new start time, or a monotonic value going down), is sent as is when its start time is
+/// known, and dropped otherwise.
+///
+/// Streams also restart when a total or a difference overflows. Exponential histogram points
+/// with a scale outside of [-10, 20] are dropped.
+///
+/// Gauges and summaries, and metrics already in the target temporality, are left as is.
+pub struct TemporalityConverter {
+    target: AggregationTemporality,
+    max_stale: Duration,
+    /// Latest point time seen, used as the clock for staleness.
+    max_time: u64,
+    numbers: HashMap<Vec<u8>, NumberDataPoint>,
+    histograms: HashMap<Vec<u8>, HistogramDataPoint>,
+    exponential_histograms: HashMap<Vec<u8>, ExponentialHistogramDataPoint>,
+}
+
+impl TemporalityConverter {
+    /// Creates a converter to given temporality, either `Cumulative` or `Delta`.
+    pub fn new(target: AggregationTemporality) -> Self {
+        Self {
+            target,
+            max_stale: DEFAULT_MAX_STALE,
+            max_time: 0,
+            numbers: HashMap::new(),
+            histograms: HashMap::new(),
+            exponential_histograms: HashMap::new(),
+        }
+    }
+
+    pub fn with_max_stale(mut self, max_stale: Duration) -> Self {
+        self.max_stale = max_stale;
+        self
+    }
+
+    /// Number of streams tracked.
+    pub fn len(&self) -> usize {
+        self.numbers.len() + self.histograms.len() + self.exponential_histograms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Converts the request in place. Metrics left without data points are removed, as are
+    /// scopes and resources left without metrics.
+    pub fn convert(&mut self, request: &mut ExportMetricsServiceRequest) {
+        let target = self.target as i32;
+        for rm in &mut request.resource_metrics {
+            let mut resource_key = Vec::new();
+            if let Some(resource) = &rm.resource {
+                push_attributes(&mut resource_key, &resource.attributes);
+            }
+            push_str(&mut resource_key, &rm.schema_url);
+            for sm in &mut rm.scope_metrics {
+                let mut scope_key = resource_key.clone();
+                if let Some(scope) = &sm.scope {
+                    push_str(&mut scope_key, &scope.name);
+                    push_str(&mut scope_key, &scope.version);
+                    push_attributes(&mut scope_key, &scope.attributes);
+                }
+                push_str(&mut scope_key, &sm.schema_url);
+                for m in &mut sm.metrics {
+                    let mut key = scope_key.clone();
+                    push_str(&mut key, &m.name);
+                    push_str(&mut key, &m.unit);
+                    match &mut m.data {
+                        Some(metric::Data::Sum(sum))
+                            if convertible(sum.aggregation_temporality, target) =>
+                        {
+                            key.extend([0, sum.is_monotonic as u8]);
+                            self.max_time = self.max_time.max(convert_points(
+                                &mut self.numbers,
+                                &key,
+                                &mut sum.data_points,
+                                self.target,
+                                sum.is_monotonic,
+                            ));
+                            sum.aggregation_temporality = target;
+                        }
+                        Some(metric::Data::Histogram(histogram))
+                            if convertible(histogram.aggregation_temporality, target) =>
+                        {
+                            key.push(1);
+                            self.max_time = self.max_time.max(convert_points(
+                                &mut self.histograms,
+                                &key,
+                                &mut histogram.data_points,
+                                self.target,
+                                true,
+                            ));
+                            histogram.aggregation_temporality = target;
+                        }
+                        Some(metric::Data::ExponentialHistogram(histogram))
+                            if convertible(histogram.aggregation_temporality, target) =>
+                        {
+                            key.push(2);
+                            self.max_time = self.max_time.max(convert_points(
+                                &mut self.exponential_histograms,
+                                &key,
+                                &mut histogram.data_points,
+                                self.target,
+                                true,
+                            ));
+                            histogram.aggregation_temporality = target;
+                        }
+                        _ => {}
+                    }
+                }
+                sm.metrics.retain(|m| match &m.data {
+                    Some(metric::Data::Sum(sum)) => !sum.data_points.is_empty(),
+                    Some(metric::Data::Histogram(h)) => !h.data_points.is_empty(),
+                    Some(metric::Data::ExponentialHistogram(h)) => !h.data_points.is_empty(),
+                    _ => true,
+                });
+            }
+            rm.scope_metrics.retain(|sm| !sm.metrics.is_empty());
+        }
+        request
+            .resource_metrics
+            .retain(|rm| !rm.scope_metrics.is_empty());
+        self.evict_stale();
+    }
+
+    fn evict_stale(&mut self) {
+        let horizon = self
+            .max_time
+            .saturating_sub(self.max_stale.as_nanos() as u64);
+        self.numbers.retain(|_, p| p.time_unix_nano >= horizon);
+        self.histograms.retain(|_, p| p.time_unix_nano >= horizon);
+        self.exponential_histograms
+            .retain(|_, p| p.time_unix_nano >= horizon);
+    }
+}
+
+fn convertible(temporality: i32, target: i32) -> bool {
+    temporality != target && temporality != AggregationTemporality::Unspecified as i32
+}
+
+/// Converts the points of one metric and returns the latest point time.
+fn convert_points<P: Point>(
+    streams: &mut HashMap<Vec<u8>, P>,
+    metric_key: &[u8],
+    points: &mut Vec<P>,
+    target: AggregationTemporality,
+    monotonic: bool,
+) -> u64 {
+    let mut max_time = 0;
+    points.retain_mut(|point| {
+        max_time = max_time.max(point.time());
+        if point.flags() & DataPointFlags::NoRecordedValueMask as u32 != 0 {
+            return true;
+        }
+        if !point.is_valid() {
+            return false;
+        }
+        let mut key = metric_key.to_vec();
+        push_attributes(&mut key, point.attributes());
+        if target == AggregationTemporality::Cumulative {
+            to_cumulative(streams, key, point)
+        } else {
+            to_delta(streams, key, point, monotonic)
+        }
+    });
+    max_time
+}
+
+/// Replaces a delta point with the running total of its stream. Returns false if the point
+/// must be dropped.
+fn to_cumulative<P: Point>(streams: &mut HashMap<Vec<u8>, P>, key: Vec<u8>, point: &mut P) -> bool {
+    let accumulated = match streams.get_mut(&key) {
+        Some(total) if point.start() < total.time() => return false,
+        Some(total) => {
+            let accumulated = total.accumulate(point);
+            total.set_time(point.time());
+            accumulated
+        }
+        None => false,
+    };
+    if !accumulated {
+        let mut total = point.clone();
+        total.exemplars().clear();
+        streams.insert(key.clone(), total);
+    }
+    let total = &streams[&key];
+    let exemplars = std::mem::take(point.exemplars());
+    *point = total.clone();
+    *point.exemplars() = exemplars;
+    true
+}
+
+/// Replaces a cumulative point with its difference from the previous point of its stream.
+/// Returns false if the point must be dropped.
+fn to_delta<P: Point>(
+    streams: &mut HashMap<Vec<u8>, P>,
+    key: Vec<u8>,
+    point: &mut P,
+    monotonic: bool,
+) -> bool {
+    let mut last = point.clone();
+    last.exemplars().clear();
+    let keep = match streams.get(&key) {
+        Some(previous) if point.time() <= previous.time() => return false,
+        Some(previous) if point.start() == previous.start() => {
+            let mut delta = point.clone();
+            if delta.subtract(previous, monotonic) {
+                delta.set_start(previous.time());
+                *point = delta;
+                true
+            } else {
+                point.start() != 0
+            }
+        }
+        _ => point.start() != 0,
+    };
+    streams.insert(key, last);
+    keep
+}
+
+/// Data point of a metric with a temporality.
+trait Point: Clone {
+    fn attributes(&self) -> &[KeyValue];
+    fn flags(&self) -> u32;
+    fn start(&self) -> u64;
+    fn set_start(&mut self, start: u64);
+    fn time(&self) -> u64;
+    fn set_time(&mut self, time: u64);
+    fn exemplars(&mut self) -> &mut Vec<Exemplar>;
+    /// Whether the point can be converted at all.
+    fn is_valid(&self) -> bool {
+        true
+    }
+    /// Adds a delta point to this total. Returns false, leaving the total as is, if the points
+    /// are incompatible or the total overflows.
+    fn accumulate(&mut self, delta: &Self) -> bool;
+    /// Subtracts the previous cumulative point. Returns false on reset or overflow.
+    fn subtract(&mut self, previous: &Self, monotonic: bool) -> bool;
+}
+
+macro_rules! impl_point_fields {
+    () => {
+        fn attributes(&self) -> &[KeyValue] {
+            &self.attributes
+        }
+
+        fn flags(&self) -> u32 {
+            self.flags
+        }
+
+        fn start(&self) -> u64 {
+            self.start_time_unix_nano
+        }
+
+        fn set_start(&mut self, start: u64) {
+            self.start_time_unix_nano = start;
+        }
+
+        fn time(&self) -> u64 {
+            self.time_unix_nano
+        }
+
+        fn set_time(&mut self, time: u64) {
+            self.time_unix_nano = time;
+        }
+
+        fn exemplars(&mut self) -> &mut Vec<Exemplar> {
+            &mut self.exemplars
+        }
+    };
+}
+
+impl Point for NumberDataPoint {
+    impl_point_fields!();
+
+    fn accumulate(&mut self, delta: &Self) -> bool {
+        use number_data_point::Value;
+        match (&mut self.value, &delta.value) {
+            (Some(Value::AsInt(total)), Some(Value::AsInt(delta))) => {
+                match total.checked_add(*delta) {
+                    Some(sum) => *total = sum,
+                    None => return false,
+                }
+            }
+            (Some(Value::AsDouble(total)), Some(Value::AsDouble(delta))) => *total += delta,
+            _ => return false,
+        }
+        true
+    }
+
+    fn subtract(&mut self, previous: &Self, monotonic: bool) -> bool {
+        use number_data_point::Value;
+        match (&mut self.value, &previous.value) {
+            (Some(Value::AsInt(value)), Some(Value::AsInt(previous))) => {
+                if monotonic && *value < *previous {
+                    return false;
+                }
+                match value.checked_sub(*previous) {
+                    Some(delta) => *value = delta,
+                    None => return false,
+                }
+            }
+            (Some(Value::AsDouble(value)), Some(Value::AsDouble(previous))) => {
+                if monotonic && *value < *previous {
+                    return false;
+                }
+                *value -= previous;
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+impl Point for HistogramDataPoint {
+    impl_point_fields!();
+
+    fn accumulate(&mut self, delta: &Self) -> bool {
+        if self.explicit_bounds != delta.explicit_bounds
+            || self.bucket_counts.len() != delta.bucket_counts.len()
+        {
+            return false;
+        }
+        let Some(count) = self.count.checked_add(delta.count) else {
+            return false;
+        };
+        let Some(bucket_counts) = self
+            .bucket_counts
+            .iter()
+            .zip(&delta.bucket_counts)
+            .map(|(total, delta)| total.checked_add(*delta))
+            .collect()
+        else {
+            return false;
+        };
+        self.bucket_counts = bucket_counts;
+        self.count = count;
+        self.sum = self.sum.zip(delta.sum).map(|(a, b)| a + b);
+        self.min = self.min.zip(delta.min).map(|(a, b)| a.min(b));
+        self.max = self.max.zip(delta.max).map(|(a, b)| a.max(b));
+        true
+    }
+
+    fn subtract(&mut self, previous: &Self, _monotonic: bool) -> bool {
+        if self.explicit_bounds != previous.explicit_bounds
+            || self.bucket_counts.len() != previous.bucket_counts.len()
+            || self.count < previous.count
+        {
+            return false;
+        }
+        let Some(bucket_counts) = self
+            .bucket_counts
+            .iter()
+            .zip(&previous.bucket_counts)
+            .map(|(value, previous)| value.checked_sub(*previous))
+            .collect()
+        else {
+            return false;
+        };
+        self.bucket_counts = bucket_counts;
+        self.count -= previous.count;
+        self.sum = self.sum.zip(previous.sum).map(|(a, b)| a - b);
+        // extremes of an interval can't be derived from cumulative extremes.
+        self.min = None;
+        self.max = None;
+        true
+    }
+}
+
+impl Point for ExponentialHistogramDataPoint {
+    impl_point_fields!();
+
+    fn is_valid(&self) -> bool {
+        (MIN_SCALE..=MAX_SCALE).contains(&self.scale)
+    }
+
+    fn accumulate(&mut self, delta: &Self) -> bool {
+        if self.zero_threshold != delta.zero_threshold {
+            return false;
+        }
+        let scale = self.scale.min(delta.scale);
+        let (Some(count), Some(zero_count)) = (
+            self.count.checked_add(delta.count),
+            self.zero_count.checked_add(delta.zero_count),
+        ) else {
+            return false;
+        };
+        let add = u64::checked_add;
+        let (Some(positive), Some(negative)) = (
+            merge(
+                &self.positive,
+                self.scale,
+                &delta.positive,
+                delta.scale,
+                add,
+            ),
+            merge(
+                &self.negative,
+                self.scale,
+                &delta.negative,
+                delta.scale,
+                add,
+            ),
+        ) else {
+            return false;
+        };
+        self.positive = Some(positive);
+        self.negative = Some(negative);
+        self.scale = scale;
+        self.count = count;
+        self.zero_count = zero_count;
+        self.sum = self.sum.zip(delta.sum).map(|(a, b)| a + b);
+        self.min = self.min.zip(delta.min).map(|(a, b)| a.min(b));
+        self.max = self.max.zip(delta.max).map(|(a, b)| a.max(b));
+        true
+    }
+
+    fn subtract(&mut self, previous: &Self, _monotonic: bool) -> bool {
+        if self.zero_threshold != previous.zero_threshold
+            || self.count < previous.count
+            || self.zero_count < previous.zero_count
+        {
+            return false;
+        }
+        let scale = self.scale.min(previous.scale);
+        let (Some(positive), Some(negative)) = (
+            merge(
+                &self.positive,
+                self.scale,
+                &previous.positive,
+                previous.scale,
+                u64::checked_sub,
+            ),
+            merge(
+                &self.negative,
+                self.scale,
+                &previous.negative,
+                previous.scale,
+                u64::checked_sub,
+            ),
+        ) else {
+            return false;
+        };
+        self.positive = Some(positive);
+        self.negative = Some(negative);
+        self.scale = scale;
+        self.count -= previous.count;
+        self.zero_count -= previous.zero_count;
+        self.sum = self.sum.zip(previous.sum).map(|(a, b)| a - b);
+        self.min = None;
+        self.max = None;
+        true
+    }
+}
+
+/// Combines the counts of two bucket ranges bucket by bucket, after bringing them to the
+/// lowest of both scales. Returns `None` if `f` does, or if the range is too wide.
+fn merge(
+    a: &Option<Buckets>,
+    a_scale: i32,
+    b: &Option<Buckets>,
+    b_scale: i32,
+    f: impl Fn(u64, u64) -> Option<u64>,
+) -> Option<Buckets> {
+    let scale = a_scale.min(b_scale);
+    let a = downscale(a, a_scale.abs_diff(scale))?;
+    let b = downscale(b, b_scale.abs_diff(scale))?;
+    let end = |b: &Buckets| b.offset as i64 + b.bucket_counts.len() as i64;
+    let non_empty = [&a, &b].into_iter().filter(|b| !b.bucket_counts.is_empty());
+    let (Some(offset), Some(end)) = (
+        non_empty.clone().map(|b| b.offset).min(),
+        non_empty.map(end).max(),
+    ) else {
+        return Some(Buckets::default());
+    };
+    if end - offset as i64 > MAX_BUCKETS {
+        return None;
+    }
+    let count = |b: &Buckets, index: i64| {
+        usize::try_from(index - b.offset as i64)
+            .ok()
+            .and_then(|i| b.bucket_counts.get(i).copied())
+            .unwrap_or_default()
+    };
+    let bucket_counts = (offset as i64..end)
+        .map(|index| f(count(&a, index), count(&b, index)))
+        .collect::<Option<_>>()?;
+    Some(Buckets {
+        offset,
+        bucket_counts,
+    })
+}
+
+/// Lowers the scale of buckets by `by`, merging every `2^by` adjacent buckets. Returns `None`
+/// if a merged count overflows.
+pub(crate) fn downscale(buckets: &Option<Buckets>, by: u32) -> Option<Buckets> {
+    let Some(buckets) = buckets else {
+        return Some(Buckets::default());
+    };
+    if by == 0 || buckets.bucket_counts.is_empty() {
+        return Some(buckets.clone());
+    }
+    // arithmetic shifts round towards negative infinity, as bucket indices do. Indices are
+    // shifted as i64 since they may go past i32::MAX, and by at most 63 bits.
+    let by = by.min(63);
+    let offset = buckets.offset as i64 >> by;
+    let mut bucket_counts: Vec<u64> = Vec::new();
+    for (i, count) in buckets.bucket_counts.iter().enumerate() {
+        let index = ((buckets.offset as i64 + i as i64) >> by) - offset;
+        if bucket_counts.len() <= index as usize {
+            bucket_counts.resize(index as usize + 1, 0);
+        }
+        bucket_counts[index as usize] = bucket_counts[index as usize].checked_add(*count)?;
+    }
+    Some(Buckets {
+        // safety: shifting right brings the offset closer to zero.
+        offset: offset as i32,
+        bucket_counts,
+    })
+}
+
+fn push_str(key: &mut Vec<u8>, value: &str) {
+    encode_varint(value.len() as u64, key);
+    key.extend_from_slice(value.as_bytes());
+}
+
+/// Appends attributes sorted by key, so that their order doesn't change the identity.
+fn push_attributes(key: &mut Vec<u8>, attributes: &[KeyValue]) {
+    let mut sorted: Vec<_> = attributes.iter().collect();
+    sorted.sort_by(|a, b| a.key.cmp(&b.key));
+    encode_varint(sorted.len() as u64, key);
+    for kv in sorted {
+        key.extend(kv.encode_length_delimited_to_vec());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::temporality::TemporalityConverter;
+    use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+    use opentelemetry_proto::tonic::common::v1::{any_value, AnyValue, KeyValue};
+    use opentelemetry_proto::tonic::metrics::v1::exponential_histogram_data_point::Buckets;
+    use opentelemetry_proto::tonic::metrics::v1::{
+        metric, number_data_point, AggregationTemporality, ExponentialHistogram,
+        ExponentialHistogramDataPoint, Histogram, HistogramDataPoint, Metric, NumberDataPoint,
+        ResourceMetrics, ScopeMetrics, Sum,
+    };
+    use std::time::Duration;
+
+    fn request(data: metric::Data) -> ExportMetricsServiceRequest {
+        ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                resource: Some(Default::default()),
+                scope_metrics: vec![ScopeMetrics {
+                    scope: Some(Default::default()),
+                    metrics: vec![Metric {
+                        name: "m".to_string(),
+                        data: Some(data),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        }
+    }
+
+    fn sum(temporality: AggregationTemporality, points: &[(&str, u64, u64, i64)]) -> metric::Data {
+        let data_points = points
+            .iter()
+            .map(|(host, start, time, value)| NumberDataPoint {
+                attributes: vec![KeyValue {
+                    key: "host".to_string(),
+                    value: Some(AnyValue {
+                        value: Some(any_value::Value::StringValue(host.to_string())),
+                    }),
+                }],
+                start_time_unix_nano: *start,
+                time_unix_nano: *time,
+                value: Some(number_data_point::Value::AsInt(*value)),
+                ..Default::default()
+            })
+            .collect();
+        metric::Data::Sum(Sum {
+            data_points,
+            aggregation_temporality: temporality as i32,
+            is_monotonic: true,
+        })
+    }
+
+    fn convert(converter: &mut TemporalityConverter, data: metric::Data) -> Option<metric::Data> {
+        let mut request = request(data);
+        converter.convert(&mut request);
+        request
+            .resource_metrics
+            .pop()
+            .map(|mut rm| rm.scope_metrics.remove(0).metrics.remove(0).data.unwrap())
+    }
+
+    #[test]
+    fn test_sums() {
+        use AggregationTemporality::{Cumulative, Delta};
+        let mut converter =
+            TemporalityConverter::new(Cumulative).with_max_stale(Duration::from_nanos(100));
+        let converted = convert(
+            &mut converter,
+            sum(Delta, &[("a", 1, 2, 3), ("b", 1, 2, 1)]),
+        );
+        assert_eq!(
+            converted,
+            Some(sum(Cumulative, &[("a", 1, 2, 3), ("b", 1, 2, 1)]))
+        );
+        // overlapping points are dropped, gaps are accumulated.
+        let converted = convert(
+            &mut converter,
+            sum(Delta, &[("a", 1, 3, 5), ("b", 4, 5, 2)]),
+        );
+        assert_eq!(converted, Some(sum(Cumulative, &[("b", 1, 5, 3)])));
+        assert_eq!(converter.len(), 2);
+        // "a" is stale once points reach 102 + 1.
+        let converted = convert(&mut converter, sum(Delta, &[("b", 5, 103, 1)]));
+        assert_eq!(converted, Some(sum(Cumulative, &[("b", 1, 103, 4)])));
+        assert_eq!(converter.len(), 1);
+
+        let mut converter = TemporalityConverter::new(Delta);
+        let converted = convert(
+            &mut converter,
+            sum(Cumulative, &[("a", 1, 2, 3), ("b", 0, 2, 1)]),
+        );
+        // unknown start times can't be sent as deltas.
+        assert_eq!(converted, Some(sum(Delta, &[("a", 1, 2, 3)])));
+        let converted = convert(
+            &mut converter,
+            sum(Cumulative, &[("a", 1, 3, 7), ("b", 0, 3, 4)]),
+        );
+        assert_eq!(
+            converted,
+            Some(sum(Delta, &[("a", 2, 3, 4), ("b", 2, 3, 3)]))
+        );
+        // a monotonic sum going down is a reset.
+        let converted = convert(&mut converter, sum(Cumulative, &[("a", 1, 4, 2)]));
+        assert_eq!(converted, Some(sum(Delta, &[("a", 1, 4, 2)])));
+        let converted = convert(&mut converter, sum(Cumulative, &[("a", 1, 4, 9)]));
+        assert_eq!(converted, None);
+    }
+
+    #[test]
+    fn test_histograms() {
+        use AggregationTemporality::{Cumulative, Delta};
+        let point = |start, time, bounds: &[f64], bucket_counts: &[u64], sum, (min, max)| {
+            HistogramDataPoint {
+                start_time_unix_nano: start,
+                time_unix_nano: time,
+                count: bucket_counts.iter().sum(),
+                sum: Some(sum),
+                bucket_counts: bucket_counts.to_vec(),
+                explicit_bounds: bounds.to_vec(),
+                min: Some(min),
+                max: Some(max),
+                ..Default::default()
+            }
+        };
+        let histogram = |temporality: AggregationTemporality, point| {
+            metric::Data::Histogram(Histogram {
+                data_points: vec![point],
+                aggregation_temporality: temporality as i32,
+            })
+        };
+        let deltas = [
+            point(1, 2, &[1.0], &[1, 2], 6.0, (0.5, 3.0)),
+            point(2, 3, &[1.0], &[2, 0], 1.0, (0.2, 0.8)),
+            // new bounds restart the stream.
+            point(3, 4, &[2.0], &[1, 1], 3.0, (1.0, 2.0)),
+        ];
+        let cumulatives = [
+            point(1, 2, &[1.0], &[1, 2], 6.0, (0.5, 3.0)),
+            point(1, 3, &[1.0], &[3, 2], 7.0, (0.2, 3.0)),
+            point(3, 4, &[2.0], &[1, 1], 3.0, (1.0, 2.0)),
+        ];
+
+        let mut converter = TemporalityConverter::new(Cumulative);
+        for (delta, cumulative) in deltas.iter().zip(&cumulatives) {
+            let converted = convert(&mut converter, histogram(Delta, delta.clone()));
+            assert_eq!(converted, Some(histogram(Cumulative, cumulative.clone())));
+        }
+
+        // back to deltas, extremes of intervals are unknown.
+        let mut converter = TemporalityConverter::new(Delta);
+        for (idx, (delta, cumulative)) in deltas.iter().zip(&cumulatives).enumerate() {
+            let converted = convert(&mut converter, histogram(Cumulative, cumulative.clone()));
+            let mut expected = delta.clone();
+            if idx == 1 {
+                (expected.min, expected.max) = (None, None);
+            }
+            assert_eq!(converted, Some(histogram(Delta, expected)));
+        }
+    }
+
+    #[test]
+    fn test_exponential_histograms() {
+        let point = |time, scale, offset, bucket_counts: &[u64]| ExponentialHistogramDataPoint {
+            start_time_unix_nano: 1,
+            time_unix_nano: time,
+            count: bucket_counts.iter().sum(),
+            scale,
+            positive: Some(Buckets {
+                offset,
+                bucket_counts: bucket_counts.to_vec(),
+            }),
+            negative: Some(Default::default()),
+            ..Default::default()
+        };
+        let histogram = |temporality: AggregationTemporality, point| {
+            metric::Data::ExponentialHistogram(ExponentialHistogram {
+                data_points: vec![point],
+                aggregation_temporality: temporality as i32,
+            })
+        };
+        let mut converter = TemporalityConverter::new(AggregationTemporality::Delta);
+        convert(
+            &mut converter,
+            histogram(
+                AggregationTemporality::Cumulative,
+                point(2, 1, -1, &[1, 2, 3]),
+            ),
+        );
+        // buckets -1..2 at scale 1 are buckets -1..1 at scale 0.
+        let converted = convert(
+            &mut converter,
+            histogram(AggregationTemporality::Cumulative, point(3, 0, -1, &[2, 6])),
+        );
+        let mut expected = point(3, 0, -1, &[1, 1]);
+        expected.start_time_unix_nano = 2;
+        assert_eq!(
+            converted,
+            Some(histogram(AggregationTemporality::Delta, expected))
+        );
+
+        // out of spec scales are dropped.
+        let converted = convert(
+            &mut converter,
+            histogram(AggregationTemporality::Cumulative, point(4, 21, 0, &[1])),
+        );
+        assert_eq!(converted, None);
+
+        // scales 20 and -10 are 30 apart, and indices near i32::MAX overflow when merged.
+        let mut converter = TemporalityConverter::new(AggregationTemporality::Cumulative);
+        for (time, scale, offset) in [(2, 20, i32::MAX - 1), (3, -10, i32::MIN), (4, 0, 0)] {
+            let mut delta = point(time, scale, offset, &[1, 1]);
+            delta.start_time_unix_nano = time - 1;
+            let converted = convert(
+                &mut converter,
+                histogram(AggregationTemporality::Delta, delta),
+            );
+            assert!(converted.is_some());
+        }
+    }
+
+    #[test]
+    fn test_overflows() {
+        use AggregationTemporality::{Cumulative, Delta};
+        // totals overflowing restart the stream.
+        let mut converter = TemporalityConverter::new(Cumulative);
+        convert(&mut converter, sum(Delta, &[("a", 1, 2, i64::MAX)]));
+        let converted = convert(&mut converter, sum(Delta, &[("a", 2, 3, 1)]));
+        assert_eq!(converted, Some(sum(Cumulative, &[("a", 2, 3, 1)])));
+
+        // so do differences overflowing.
+        let mut converter = TemporalityConverter::new(Delta);
+        convert(&mut converter, sum(Cumulative, &[("a", 1, 2, -1)]));
+        let converted = convert(&mut converter, sum(Cumulative, &[("a", 1, 3, i64::MAX)]));
+        assert_eq!(converted, Some(sum(Delta, &[("a", 1, 3, i64::MAX)])));
+    }
+}
@@ -10,11 +10,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::encode::encoder::Producer;
+use crate::opentelemetry::{ArrowPayloadType, BatchArrowRecords};
+use crate::otlp::metric::MetricType;
+use crate::schema::consts;
 use arrow::array::{
-    ArrayRef, BinaryArray, BooleanArray, Float32Array, Float64Array,
-    Int16Array, Int32Array, Int64Array, Int8Array, RecordBatch, StringArray,
-    TimestampMicrosecondArray, TimestampMillisecondArray, TimestampNanosecondArray,
-    TimestampSecondArray, UInt16Array, UInt32Array, UInt64Array, UInt8Array,
+    ArrayRef, BinaryArray, BooleanArray, Float32Array, Float64Array, Int16Array, Int32Array,
+    Int64Array, Int8Array, RecordBatch, StringArray, StructArray, TimestampMicrosecondArray,
+    TimestampMillisecondArray, TimestampNanosecondArray, TimestampSecondArray, UInt16Array,
+    UInt32Array, UInt64Array, UInt8Array,
 };
 use arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
 use rand::distributions::{Alphanumeric, DistString};
@@ -102,3 +106,417 @@ pub(crate) fn create_array(dt: &DataType, num_rows: usize) -> ArrayRef {
         }
     }
 }
+
+/// Creates a `UnivariateMetrics` record of gauges sharing one resource and scope.
+pub(crate) fn create_gauge_metrics_record(names: &[&str]) -> RecordBatch {
+    let num_rows = names.len();
+    let resource = StructArray::from(vec![(
+        Arc::new(Field::new(consts::ID, DataType::UInt16, true)),
+        Arc::new(UInt16Array::from(vec![0; num_rows])) as ArrayRef,
+    )]);
+    let scope = StructArray::from(vec![
+        (
+            Arc::new(Field::new(consts::NAME, DataType::Utf8, true)),
+            Arc::new(StringArray::from(vec!["scope"; num_rows])) as ArrayRef,
+        ),
+        (
+            Arc::new(Field::new(consts::ID, DataType::UInt16, true)),
+            Arc::new(UInt16Array::from(vec![0; num_rows])) as ArrayRef,
+        ),
+    ]);
+    let delta_ids = (0..num_rows).map(|i| (i > 0) as u16).collect::<Vec<_>>();
+    RecordBatch::try_from_iter(vec![
+        (consts::RESOURCE, Arc::new(resource) as ArrayRef),
+        (consts::SCOPE, Arc::new(scope) as ArrayRef),
+        (
+            consts::ID,
+            Arc::new(UInt16Array::from(delta_ids)) as ArrayRef,
+        ),
+        (
+            consts::METRIC_TYPE,
+            Arc::new(UInt8Array::from(vec![MetricType::Gauge as u8; num_rows])) as ArrayRef,
+        ),
+        (
+            consts::NAME,
+            Arc::new(StringArray::from(names.to_vec())) as ArrayRef,
+        ),
+        (
+            consts::DESCRIPTION,
+            Arc::new(StringArray::from(vec![""; num_rows])) as ArrayRef,
+        ),
+    ])
+    .unwrap()
+}
+
+/// Creates a `NumberDataPoints` record with one int data point per metric.
+pub(crate) fn create_number_data_points_record(values: &[i64]) -> RecordBatch {
+    let num_rows = values.len();
+    let delta_ids = (0..num_rows).map(|i| (i > 0) as u32).collect::<Vec<_>>();
+    let delta_parent_ids = (0..num_rows).map(|i| (i > 0) as u16).collect::<Vec<_>>();
+    RecordBatch::try_from_iter(vec![
+        (
+            consts::ID,
+            Arc::new(UInt32Array::from(delta_ids)) as ArrayRef,
+        ),
+        (
+            consts::PARENT_ID,
+            Arc::new(UInt16Array::from(delta_parent_ids)) as ArrayRef,
+        ),
+        (
+            consts::START_TIME_UNIX_NANO,
+            Arc::new(TimestampNanosecondArray::from(vec![1; num_rows])) as ArrayRef,
+        ),
+        (
+            consts::TIME_UNIX_NANO,
+            Arc::new(TimestampNanosecondArray::from(vec![2; num_rows])) as ArrayRef,
+        ),
+        (
+            consts::INT_VALUE,
+            Arc::new(Int64Array::from(values.to_vec())) as ArrayRef,
+        ),
+    ])
+    .unwrap()
+}
+
+/// Creates a metrics [BatchArrowRecords] of gauges named `names` with given int values.
+pub(crate) fn create_gauge_batch(
+    producer: &mut Producer,
+    names: &[&str],
+    values: &[i64],
+) -> BatchArrowRecords {
+    producer
+        .produce_records(vec![
+            (
+                ArrowPayloadType::UnivariateMetrics,
+                create_gauge_metrics_record(names),
+            ),
+            (
+                ArrowPayloadType::NumberDataPoints,
+                create_number_data_points_record(values),
+            ),
+        ])
+        .unwrap()
+}
+
+/// Strategies for arbitrary [ExportMetricsServiceRequest]s covering everything the OTAP
+/// metrics encoding can carry. Values OTAP can't represent are left out: NaN doubles, metric
+/// metadata, exponential histogram zero thresholds, exemplars without value or ids, and
+/// attributes with empty values or duplicate keys.
+pub(crate) mod arb {
+    use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+    use opentelemetry_proto::tonic::common::v1::any_value::Value;
+    use opentelemetry_proto::tonic::common::v1::{
+        AnyValue, ArrayValue, InstrumentationScope, KeyValue, KeyValueList,
+    };
+    use opentelemetry_proto::tonic::metrics::v1::exponential_histogram_data_point::Buckets;
+    use opentelemetry_proto::tonic::metrics::v1::summary_data_point::ValueAtQuantile;
+    use opentelemetry_proto::tonic::metrics::v1::{
+        exemplar, metric, number_data_point, Exemplar, ExponentialHistogram,
+        ExponentialHistogramDataPoint, Gauge, Histogram, HistogramDataPoint, Metric,
+        NumberDataPoint, ResourceMetrics, ScopeMetrics, Sum, Summary, SummaryDataPoint,
+    };
+    use opentelemetry_proto::tonic::resource::v1::Resource;
+    use proptest::collection::{btree_map, vec};
+    use proptest::prelude::*;
+
+    fn name() -> impl Strategy<Value = String> {
+        "[a-z]{0,6}"
+    }
+
+    fn double() -> impl Strategy<Value = f64> {
+        -1e9f64..1e9
+    }
+
+    fn any_value() -> impl Strategy<Value = AnyValue> {
+        let leaf = prop_oneof![
+            name().prop_map(Value::StringValue),
+            any::<i64>().prop_map(Value::IntValue),
+            double().prop_map(Value::DoubleValue),
+            any::<bool>().prop_map(Value::BoolValue),
+            vec(any::<u8>(), 0..8).prop_map(Value::BytesValue),
+        ];
+        leaf.prop_recursive(3, 16, 4, |inner| {
+            prop_oneof![
+                vec(inner.clone(), 0..4).prop_map(|values| Value::ArrayValue(ArrayValue {
+                    values: values
+                        .into_iter()
+                        .map(|value| AnyValue { value: Some(value) })
+                        .collect(),
+                })),
+                btree_map(name(), inner, 0..4).prop_map(|map| Value::KvlistValue(KeyValueList {
+                    values: key_values(map),
+                })),
+            ]
+        })
+        .prop_map(|value| AnyValue { value: Some(value) })
+    }
+
+    fn key_values(map: impl IntoIterator<Item = (String, Value)>) -> Vec<KeyValue> {
+        map.into_iter()
+            .map(|(key, value)| KeyValue {
+                key,
+                value: Some(AnyValue { value: Some(value) }),
+            })
+            .collect()
+    }
+
+    fn attributes() -> impl Strategy<Value = Vec<KeyValue>> {
+        btree_map(name(), any_value(), 0..4).prop_map(|map| {
+            map.into_iter()
+                .map(|(key, value)| KeyValue {
+                    key,
+                    value: Some(value),
+                })
+                .collect()
+        })
+    }
+
+    fn exemplars() -> impl Strategy<Value = Vec<Exemplar>> {
+        let exemplar = (
+            attributes(),
+            any::<u64>(),
+            prop_oneof![
+                any::<i64>().prop_map(exemplar::Value::AsInt),
+                double().prop_map(exemplar::Value::AsDouble),
+            ],
+            // exemplars sampled outside of a trace have no ids.
+            prop_oneof![Just(vec![]), any::<[u8; 8]>().prop_map(|id| id.to_vec())],
+            prop_oneof![Just(vec![]), any::<[u8; 16]>().prop_map(|id| id.to_vec())],
+        )
+            .prop_map(
+                |(filtered_attributes, time_unix_nano, value, span_id, trace_id)| Exemplar {
+                    filtered_attributes,
+                    time_unix_nano,
+                    value: Some(value),
+                    span_id,
+                    trace_id,
+                },
+            );
+        vec(exemplar, 0..3)
+    }
+
+    fn number_data_points() -> impl Strategy<Value = Vec<NumberDataPoint>> {
+        let data_point = (
+            attributes(),
+            any::<(u64, u64, u32)>(),
+            prop::option::of(prop_oneof![
+                any::<i64>().prop_map(number_data_point::Value::AsInt),
+                double().prop_map(number_data_point::Value::AsDouble),
+            ]),
+            exemplars(),
+        )
+            .prop_map(
+                |(attributes, (start_time_unix_nano, time_unix_nano, flags), value, exemplars)| {
+                    NumberDataPoint {
+                        attributes,
+                        start_time_unix_nano,
+                        time_unix_nano,
+                        exemplars,
+                        flags,
+                        value,
+                    }
+                },
+            );
+        vec(data_point, 0..4)
+    }
+
+    fn histogram_data_points() -> impl Strategy<Value = Vec<HistogramDataPoint>> {
+        let data_point = (
+            attributes(),
+            any::<(u64, u64, u32, u64)>(),
+            prop::option::of(double()),
+            vec(any::<u64>(), 0..4),
+            vec(double(), 0..4),
+            (prop::option::of(double()), prop::option::of(double())),
+            exemplars(),
+        )
+            .prop_map(
+                |(
+                    attributes,
+                    (start_time_unix_nano, time_unix_nano, flags, count),
+                    sum,
+                    bucket_counts,
+                    explicit_bounds,
+                    (min, max),
+                    exemplars,
+                )| HistogramDataPoint {
+                    attributes,
+                    start_time_unix_nano,
+                    time_unix_nano,
+                    count,
+                    sum,
+                    bucket_counts,
+                    explicit_bounds,
+                    exemplars,
+                    flags,
+                    min,
+                    max,
+                },
+            );
+        vec(data_point, 0..4)
+    }
+
+    fn buckets() -> impl Strategy<Value = Buckets> {
+        (any::<i32>(), vec(any::<u64>(), 0..4)).prop_map(|(offset, bucket_counts)| Buckets {
+            offset,
+            bucket_counts,
+        })
+    }
+
+    fn exp_histogram_data_points() -> impl Strategy<Value = Vec<ExponentialHistogramDataPoint>> {
+        let data_point = (
+            attributes(),
+            any::<(u64, u64, u32, u64, i32, u64)>(),
+            prop::option::of(double()),
+            (buckets(), buckets()),
+            (prop::option::of(double()), prop::option::of(double())),
+            exemplars(),
+        )
+            .prop_map(
+                |(
+                    attributes,
+                    (start_time_unix_nano, time_unix_nano, flags, count, scale, zero_count),
+                    sum,
+                    (positive, negative),
+                    (min, max),
+                    exemplars,
+                )| ExponentialHistogramDataPoint {
+                    attributes,
+                    start_time_unix_nano,
+                    time_unix_nano,
+                    count,
+                    sum,
+                    scale,
+                    zero_count,
+                    positive: Some(positive),
+                    negative: Some(negative),
+                    flags,
+                    exemplars,
+                    min,
+                    max,
+                    zero_threshold: 0.0,
+                },
+            );
+        vec(data_point, 0..4)
+    }
+
+    fn summary_data_points() -> impl Strategy<Value = Vec<SummaryDataPoint>> {
+        let data_point = (
+            attributes(),
+            any::<(u64, u64, u32, u64)>(),
+            double(),
+            vec((double(), double()), 0..4),
+        )
+            .prop_map(
+                |(
+                    attributes,
+                    (start_time_unix_nano, time_unix_nano, flags, count),
+                    sum,
+                    quantiles,
+                )| SummaryDataPoint {
+                    attributes,
+                    start_time_unix_nano,
+                    time_unix_nano,
+                    count,
+                    sum,
+                    quantile_values: quantiles
+                        .into_iter()
+                        .map(|(quantile, value)| ValueAtQuantile { quantile, value })
+                        .collect(),
+                    flags,
+                },
+            );
+        vec(data_point, 0..4)
+    }
+
+    fn metric_data() -> impl Strategy<Value = metric::Data> {
+        prop_oneof![
+            number_data_points().prop_map(|data_points| metric::Data::Gauge(Gauge { data_points })),
+            (number_data_points(), any::<i32>(), any::<bool>()).prop_map(
+                |(data_points, aggregation_temporality, is_monotonic)| {
+                    metric::Data::Sum(Sum {
+                        data_points,
+                        aggregation_temporality,
+                        is_monotonic,
+                    })
+                }
+            ),
+            (histogram_data_points(), any::<i32>()).prop_map(
+                |(data_points, aggregation_temporality)| {
+                    metric::Data::Histogram(Histogram {
+                        data_points,
+                        aggregation_temporality,
+                    })
+                }
+            ),
+            (exp_histogram_data_points(), any::<i32>()).prop_map(
+                |(data_points, aggregation_temporality)| {
+                    metric::Data::ExponentialHistogram(ExponentialHistogram {
+                        data_points,
+                        aggregation_temporality,
+                    })
+                }
+            ),
+            summary_data_points()
+                .prop_map(|data_points| metric::Data::Summary(Summary { data_points })),
+        ]
+    }
+
+    fn metric() -> impl Strategy<Value = Metric> {
+        (name(), name(), name(), metric_data()).prop_map(|(name, description, unit, data)| Metric {
+            name,
+            description,
+            unit,
+            metadata: vec![],
+            data: Some(data),
+        })
+    }
+
+    fn scope_metrics() -> impl Strategy<Value = ScopeMetrics> {
+        let scope = (name(), name(), attributes(), any::<u32>()).prop_map(
+            |(name, version, attributes, dropped_attributes_count)| InstrumentationScope {
+                name,
+                version,
+                attributes,
+                dropped_attributes_count,
+            },
+        );
+        let scope = prop_oneof![
+            Just(None),
+            Just(Some(InstrumentationScope::default())),
+            scope.prop_map(Some),
+        ];
+        (scope, vec(metric(), 0..4), name()).prop_map(|(scope, metrics, schema_url)| ScopeMetrics {
+            scope,
+            metrics,
+            schema_url,
+        })
+    }
+
+    fn resource_metrics() -> impl Strategy<Value = ResourceMetrics> {
+        let resource =
+            (attributes(), any::<u32>()).prop_map(|(attributes, dropped_attributes_count)| {
+                Resource {
+                    attributes,
+                    dropped_attributes_count,
+                }
+            });
+        let resource = prop_oneof![
+            Just(None),
+            Just(Some(Resource::default())),
+            resource.prop_map(Some),
+        ];
+        (resource, vec(scope_metrics(), 0..3), name()).prop_map(
+            |(resource, scope_metrics, schema_url)| ResourceMetrics {
+                resource,
+                scope_metrics,
+                schema_url,
+            },
+        )
+    }
+
+    pub(crate) fn metrics_request() -> impl Strategy<Value = ExportMetricsServiceRequest> {
+        vec(resource_metrics(), 0..3)
+            .prop_map(|resource_metrics| ExportMetricsServiceRequest { resource_metrics })
+    }
+}
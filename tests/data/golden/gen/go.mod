@@ -0,0 +1,5 @@
+module github.com/open-telemetry/otel-arrow-rust/tests/data/golden/gen
+
+go 1.22
+
+replace github.com/open-telemetry/otel-arrow => ../../../../contrib/otel-arrow
@@ -0,0 +1,222 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Command gen writes the golden OTAP corpus replayed by tests/golden.rs. Each case runs a
+// sequence of OTLP requests through one arrow_record.Producer, i.e. one stream, and writes
+// the produced batches as <case>.otapcap and the input requests as <case>.jsonl.
+package main
+
+import (
+	"bytes"
+	"flag"
+	"log"
+	"os"
+	"path/filepath"
+	"time"
+
+	arrowpb "github.com/open-telemetry/otel-arrow/api/experimental/arrow/v1"
+	"github.com/open-telemetry/otel-arrow/pkg/otel/arrow_record"
+	"go.opentelemetry.io/collector/pdata/pcommon"
+	"go.opentelemetry.io/collector/pdata/plog"
+	"go.opentelemetry.io/collector/pdata/pmetric"
+	"go.opentelemetry.io/collector/pdata/ptrace"
+	"google.golang.org/protobuf/encoding/protowire"
+	"google.golang.org/protobuf/proto"
+)
+
+// captureMagic matches CAPTURE_MAGIC in src/replay.rs.
+const captureMagic = "OTAPCAP1"
+
+var start = pcommon.Timestamp(1_700_000_000_000_000_000)
+
+func main() {
+	out := flag.String("out", "..", "directory to write the corpus to")
+	flag.Parse()
+
+	writeMetrics(*out, "metrics-schema-change", metricsSchemaChange())
+	writeLogs(*out, "logs-basic", logsBasic())
+	writeTraces(*out, "traces-basic", tracesBasic())
+}
+
+// metricsSchemaChange starts with int gauges and string attributes, then introduces new
+// attribute types and metric types so that payload schemas change mid-stream.
+func metricsSchemaChange() []pmetric.Metrics {
+	first := pmetric.NewMetrics()
+	rm := first.ResourceMetrics().AppendEmpty()
+	rm.Resource().Attributes().PutStr("service.name", "golden")
+	sm := rm.ScopeMetrics().AppendEmpty()
+	sm.Scope().SetName("gen")
+	gauge := sm.Metrics().AppendEmpty()
+	gauge.SetName("queue.size")
+	dp := gauge.SetEmptyGauge().DataPoints().AppendEmpty()
+	dp.SetTimestamp(start)
+	dp.SetIntValue(3)
+	dp.Attributes().PutStr("queue", "a")
+
+	second := pmetric.NewMetrics()
+	first.CopyTo(second)
+	sm = second.ResourceMetrics().At(0).ScopeMetrics().At(0)
+	sum := sm.Metrics().AppendEmpty()
+	sum.SetName("requests")
+	sum.SetUnit("1")
+	s := sum.SetEmptySum()
+	s.SetIsMonotonic(true)
+	s.SetAggregationTemporality(pmetric.AggregationTemporalityCumulative)
+	sdp := s.DataPoints().AppendEmpty()
+	sdp.SetStartTimestamp(start)
+	sdp.SetTimestamp(start + 1_000_000_000)
+	sdp.SetDoubleValue(1.5)
+	sdp.Attributes().PutInt("status", 200)
+	sdp.Attributes().PutBool("retry", false)
+	histogram := sm.Metrics().AppendEmpty()
+	histogram.SetName("latency")
+	h := histogram.SetEmptyHistogram()
+	h.SetAggregationTemporality(pmetric.AggregationTemporalityDelta)
+	hdp := h.DataPoints().AppendEmpty()
+	hdp.SetStartTimestamp(start)
+	hdp.SetTimestamp(start + 1_000_000_000)
+	hdp.SetCount(3)
+	hdp.SetSum(0.6)
+	hdp.ExplicitBounds().FromRaw([]float64{0.1, 1})
+	hdp.BucketCounts().FromRaw([]uint64{1, 2, 0})
+
+	third := pmetric.NewMetrics()
+	second.CopyTo(third)
+	sm = third.ResourceMetrics().At(0).ScopeMetrics().At(0)
+	eh := sm.Metrics().AppendEmpty()
+	eh.SetName("size")
+	e := eh.SetEmptyExponentialHistogram()
+	e.SetAggregationTemporality(pmetric.AggregationTemporalityDelta)
+	edp := e.DataPoints().AppendEmpty()
+	edp.SetTimestamp(start + 2_000_000_000)
+	edp.SetScale(2)
+	edp.SetCount(4)
+	edp.SetZeroCount(1)
+	edp.Positive().SetOffset(-1)
+	edp.Positive().BucketCounts().FromRaw([]uint64{1, 2})
+	edp.Attributes().PutDouble("ratio", 0.5)
+	summary := sm.Metrics().AppendEmpty()
+	summary.SetName("gc")
+	sdp2 := summary.SetEmptySummary().DataPoints().AppendEmpty()
+	sdp2.SetTimestamp(start + 2_000_000_000)
+	sdp2.SetCount(2)
+	sdp2.SetSum(10)
+	q := sdp2.QuantileValues().AppendEmpty()
+	q.SetQuantile(0.5)
+	q.SetValue(4)
+
+	return []pmetric.Metrics{first, second, third}
+}
+
+func logsBasic() []plog.Logs {
+	first := plog.NewLogs()
+	rl := first.ResourceLogs().AppendEmpty()
+	rl.Resource().Attributes().PutStr("service.name", "golden")
+	lr := rl.ScopeLogs().AppendEmpty().LogRecords().AppendEmpty()
+	lr.SetTimestamp(start)
+	lr.SetSeverityNumber(plog.SeverityNumberInfo)
+	lr.Body().SetStr("started")
+
+	second := plog.NewLogs()
+	first.CopyTo(second)
+	lr = second.ResourceLogs().At(0).ScopeLogs().At(0).LogRecords().AppendEmpty()
+	lr.SetTimestamp(start + 1_000_000_000)
+	lr.Body().SetStr("stopped")
+	lr.Attributes().PutInt("code", 1)
+	return []plog.Logs{first, second}
+}
+
+func tracesBasic() []ptrace.Traces {
+	traces := ptrace.NewTraces()
+	rs := traces.ResourceSpans().AppendEmpty()
+	rs.Resource().Attributes().PutStr("service.name", "golden")
+	span := rs.ScopeSpans().AppendEmpty().Spans().AppendEmpty()
+	span.SetName("export")
+	span.SetTraceID(pcommon.TraceID{1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16})
+	span.SetSpanID(pcommon.SpanID{1, 2, 3, 4, 5, 6, 7, 8})
+	span.SetStartTimestamp(start)
+	span.SetEndTimestamp(start + 1_000_000)
+	return []ptrace.Traces{traces}
+}
+
+func writeMetrics(dir, name string, requests []pmetric.Metrics) {
+	producer := arrow_record.NewProducer()
+	defer producer.Close()
+	var batches []*arrowpb.BatchArrowRecords
+	var lines [][]byte
+	for _, request := range requests {
+		batch, err := producer.BatchArrowRecordsFromMetrics(request)
+		check(err)
+		batches = append(batches, batch)
+		line, err := (&pmetric.JSONMarshaler{}).MarshalMetrics(request)
+		check(err)
+		lines = append(lines, line)
+	}
+	writeCase(dir, name, batches, lines)
+}
+
+func writeLogs(dir, name string, requests []plog.Logs) {
+	producer := arrow_record.NewProducer()
+	defer producer.Close()
+	var batches []*arrowpb.BatchArrowRecords
+	var lines [][]byte
+	for _, request := range requests {
+		batch, err := producer.BatchArrowRecordsFromLogs(request)
+		check(err)
+		batches = append(batches, batch)
+		line, err := (&plog.JSONMarshaler{}).MarshalLogs(request)
+		check(err)
+		lines = append(lines, line)
+	}
+	writeCase(dir, name, batches, lines)
+}
+
+func writeTraces(dir, name string, requests []ptrace.Traces) {
+	producer := arrow_record.NewProducer()
+	defer producer.Close()
+	var batches []*arrowpb.BatchArrowRecords
+	var lines [][]byte
+	for _, request := range requests {
+		batch, err := producer.BatchArrowRecordsFromTraces(request)
+		check(err)
+		batches = append(batches, batch)
+		line, err := (&ptrace.JSONMarshaler{}).MarshalTraces(request)
+		check(err)
+		lines = append(lines, line)
+	}
+	writeCase(dir, name, batches, lines)
+}
+
+// writeCase writes the batches as length-delimited RecordedBatch messages after the capture
+// magic, see src/replay.rs, and the requests as one JSON line each.
+func writeCase(dir, name string, batches []*arrowpb.BatchArrowRecords, lines [][]byte) {
+	capture := []byte(captureMagic)
+	for _, batch := range batches {
+		encoded, err := proto.Marshal(batch)
+		check(err)
+		var recorded []byte
+		recorded = protowire.AppendTag(recorded, 1, protowire.VarintType)
+		recorded = protowire.AppendVarint(recorded, uint64(time.Now().UnixNano()))
+		recorded = protowire.AppendTag(recorded, 2, protowire.BytesType)
+		recorded = protowire.AppendBytes(recorded, encoded)
+		capture = protowire.AppendBytes(capture, recorded)
+	}
+	check(os.WriteFile(filepath.Join(dir, name+".otapcap"), capture, 0o644))
+	jsonl := append(bytes.Join(lines, []byte("\n")), '\n')
+	check(os.WriteFile(filepath.Join(dir, name+".jsonl"), jsonl, 0o644))
+}
+
+func check(err error) {
+	if err != nil {
+		log.Fatal(err)
+	}
+}
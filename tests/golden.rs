@@ -0,0 +1,126 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Replays the captures under `tests/data/golden` through [Consumer] and compares each
+//! decoded metrics batch with the expected OTLP/JSON next to the capture. Logs and traces
+//! captures are only checked to be rejected cleanly. See the README there for the corpus
+//! layout.
+
+use otel_arrow_rust::compare::{diff_json, format_differences};
+use otel_arrow_rust::export::json::Json;
+use otel_arrow_rust::replay::Replayer;
+use otel_arrow_rust::Consumer;
+use otel_arrow_rust::SignalType;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const CORPUS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/golden");
+
+/// Captures of given signal, e.g. `metrics`, sorted by path.
+fn captures(signal: &str) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(CORPUS_DIR) else {
+        return vec![];
+    };
+    let mut captures: Vec<_> = entries
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "otapcap"))
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with(&format!("{signal}-")))
+        })
+        .collect();
+    captures.sort();
+    captures
+}
+
+/// Checks every batch against the expected requests, returns the number of schema resets.
+fn check_capture(capture: &Path) -> usize {
+    let expected_path = capture.with_extension("jsonl");
+    let expected = fs::read_to_string(&expected_path)
+        .unwrap_or_else(|e| panic!("failed to read {}: {e}", expected_path.display()));
+    let mut expected = expected.lines().filter(|l| !l.trim().is_empty());
+
+    let mut consumer = Consumer::default();
+    let mut schema_resets = 0;
+    for (idx, batch) in Replayer::open(capture).unwrap().batches().enumerate() {
+        let request = consumer
+            .consume_batches(&mut batch.unwrap())
+            .unwrap_or_else(|e| panic!("{}: batch {idx} failed to decode: {e}", capture.display()));
+        // safety: set once the batch was consumed
+        schema_resets += consumer.last_batch_stats().unwrap().schema_resets();
+        let actual = serde_json::to_value(Json(&request)).unwrap();
+        let line = expected
+            .next()
+            .unwrap_or_else(|| panic!("{}: no expected output for batch {idx}", capture.display()));
+        let differences = diff_json(actual, serde_json::from_str(line).unwrap());
+        assert!(
+            differences.is_empty(),
+            "{}: batch {idx} differs:\n{}",
+            capture.display(),
+            format_differences(&differences)
+        );
+    }
+    assert!(
+        expected.next().is_none(),
+        "{}: more expected requests than batches",
+        capture.display()
+    );
+    schema_resets
+}
+
+/// Checks that every batch is rejected as a signal this crate can't decode yet.
+fn check_rejected(capture: &Path, signal: SignalType) {
+    let mut consumer = Consumer::default();
+    for (idx, batch) in Replayer::open(capture).unwrap().batches().enumerate() {
+        let mut batch = batch.unwrap();
+        assert_eq!(
+            signal,
+            SignalType::of_batch(&batch).unwrap(),
+            "{}: batch {idx}",
+            capture.display()
+        );
+        let e = consumer.consume_signal(&mut batch).err();
+        assert_eq!(
+            Some("unsupported_payload_type"),
+            e.as_ref().map(|e| e.kind()),
+            "{}: batch {idx}",
+            capture.display()
+        );
+    }
+}
+
+#[test]
+#[ignore = "needs captures of the Go encoder under tests/data/golden, not checked in yet"]
+fn test_golden_corpus() {
+    let metrics = captures("metrics");
+    assert!(
+        !metrics.is_empty(),
+        "no metrics capture under {CORPUS_DIR}, see the README there"
+    );
+    let schema_resets: usize = metrics.iter().map(|c| check_capture(c)).sum();
+    assert!(
+        schema_resets > 0,
+        "no metrics capture changes schema mid-stream"
+    );
+
+    for (signal, signal_type) in [("logs", SignalType::Logs), ("traces", SignalType::Traces)] {
+        let captures = captures(signal);
+        assert!(
+            !captures.is_empty(),
+            "no {signal} capture under {CORPUS_DIR}"
+        );
+        for capture in captures {
+            check_rejected(&capture, signal_type);
+        }
+    }
+}